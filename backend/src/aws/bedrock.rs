@@ -1,5 +1,6 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use aws_sdk_bedrockruntime::operation::converse::ConverseOutput;
+use serde::de::DeserializeOwned;
 
 pub fn get_converse_output_text(output: ConverseOutput) -> Result<String> {
     Ok(output
@@ -14,3 +15,17 @@ pub fn get_converse_output_text(output: ConverseOutput) -> Result<String> {
         .map_err(|e| anyhow!("Error content is not text: {:#?}", e))?
         .to_string())
 }
+
+/// Parses the JSON the model was asked to answer with.
+///
+/// Models like to wrap JSON in a Markdown code block even when told not to, so the
+/// block is unwrapped first.
+pub fn parse_model_json<T: DeserializeOwned>(output: &str) -> Result<T> {
+    let output = output.trim();
+    let json = output
+        .strip_prefix("```json")
+        .or_else(|| output.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .unwrap_or(output);
+    serde_json::from_str(json.trim()).context("Error parsing the model output as JSON")
+}
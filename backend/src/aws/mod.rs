@@ -2,14 +2,26 @@ pub mod bedrock;
 
 use bedrock::get_converse_output_text;
 
+use crate::conversation::Conversation;
+use crate::metrics;
+
 use anyhow::{anyhow, Context, Result};
 use aws_config::SdkConfig;
-use aws_sdk_bedrockruntime::{types::InferenceConfiguration, types::Message, Client};
+use aws_sdk_bedrockruntime::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_bedrockruntime::operation::converse::ConverseError;
+use aws_sdk_bedrockruntime::types::{InferenceConfiguration, Message, SystemContentBlock};
+use aws_sdk_bedrockruntime::Client;
 use aws_sdk_sts::Client as StsClient;
+use serde_json::json;
+use tracing::{field::Empty, info, info_span, Instrument, Span};
 
 const AWS_REGION: &str = "us-east-1";
-const INFERENCE_PROFILE_TEMPLATE: &str =
-    "arn:aws:bedrock:us-east-1:{}:inference-profile/us.anthropic.claude-3-5-sonnet-20241022-v2:0";
+// The `gen_ai.system` of the model calls.
+pub(crate) const BEDROCK_SYSTEM: &str = "aws.bedrock";
+/// The model behind every call, through a cross-region inference profile.
+pub const MODEL_ID: &str = "us.anthropic.claude-3-5-sonnet-20241022-v2:0";
+// The `error.type` of failures that aren't Bedrock errors.
+const OTHER_ERROR_TYPE: &str = "_OTHER";
 
 async fn get_aws_account_id(config: &SdkConfig) -> Result<String> {
     let sts_client = StsClient::new(config);
@@ -45,8 +57,10 @@ impl Default for InferenceParameters {
 
 pub struct AWSClient {
     bedrock_client: Client,
+    // The ARN names our AWS account, so telemetry only ever gets `MODEL_ID`.
     inference_profile: String,
     inference_parameters: InferenceParameters,
+    capture_content: bool,
 }
 
 impl AWSClient {
@@ -60,7 +74,10 @@ impl AWSClient {
         let aws_account_id = get_aws_account_id(&sdk_config)
             .await
             .context("Error getting AWS account ID")?;
-        let aws_inference_profile = INFERENCE_PROFILE_TEMPLATE.replace("{}", &aws_account_id);
+        let aws_inference_profile = format!(
+            "arn:aws:bedrock:{}:{}:inference-profile/{}",
+            AWS_REGION, aws_account_id, MODEL_ID
+        );
         tracing::info!("Using inference profile: {}", aws_inference_profile);
 
         let inference_params = params.unwrap_or_default();
@@ -69,26 +86,165 @@ impl AWSClient {
             bedrock_client: client,
             inference_profile: aws_inference_profile,
             inference_parameters: inference_params,
+            capture_content: false,
         })
     }
 
-    pub async fn create_conversation(&self, messages: Vec<Message>) -> Result<String> {
+    /// Records prompts and completions as events of the model call spans.
+    /// They are user content, so this is off unless explicitly allowed.
+    pub fn with_content_capture(mut self, capture_content: bool) -> Self {
+        self.capture_content = capture_content;
+        self
+    }
+
+    /// Sends the conversation to the model and returns its answer.
+    ///
+    /// Each call gets a span following the OpenTelemetry GenAI semantic conventions.
+    pub async fn create_conversation(&self, conversation: Conversation) -> Result<String> {
+        let params = &self.inference_parameters;
+        let span = info_span!(
+            "gen_ai.chat",
+            otel.name = format!("chat {}", MODEL_ID),
+            otel.kind = "client",
+            otel.status_code = Empty,
+            otel.status_message = Empty,
+            gen_ai.operation.name = "chat",
+            gen_ai.system = BEDROCK_SYSTEM,
+            gen_ai.request.model = MODEL_ID,
+            gen_ai.request.temperature = params.temperature,
+            gen_ai.request.max_tokens = params.max_tokens,
+            gen_ai.request.top_p = params.top_p,
+            gen_ai.response.finish_reasons = Empty,
+            gen_ai.usage.input_tokens = Empty,
+            gen_ai.usage.output_tokens = Empty,
+            error.type = Empty,
+        );
+
+        let result = self.converse(conversation).instrument(span.clone()).await;
+        if let Err(e) = &result {
+            span.record("otel.status_code", "ERROR");
+            span.record("otel.status_message", format!("{:#}", e));
+            span.record("error.type", error_type(e));
+        }
+        result
+    }
+
+    async fn converse(&self, conversation: Conversation) -> Result<String> {
         let inference_config = InferenceConfiguration::builder()
             .temperature(self.inference_parameters.temperature)
             .max_tokens(self.inference_parameters.max_tokens)
             .top_p(self.inference_parameters.top_p)
             .build();
 
+        if self.capture_content {
+            info!(
+                gen_ai.prompt = %prompt_json(&conversation.system, &conversation.messages),
+                "gen_ai.content.prompt"
+            );
+        }
+
         let response = self
             .bedrock_client
             .converse()
             .model_id(&self.inference_profile)
-            .set_messages(Some(messages))
+            .set_system(Some(conversation.system))
+            .set_messages(Some(conversation.messages))
             .set_inference_config(Some(inference_config))
             .send()
             .await
             .context("Error conversing with AWS bedrock")?;
 
-        get_converse_output_text(response)
+        let span = Span::current();
+        span.record(
+            "gen_ai.response.finish_reasons",
+            response.stop_reason().as_str(),
+        );
+        if let Some(usage) = response.usage() {
+            span.record("gen_ai.usage.input_tokens", usage.input_tokens());
+            span.record("gen_ai.usage.output_tokens", usage.output_tokens());
+            metrics::record_token_usage(
+                MODEL_ID,
+                usage.input_tokens().max(0) as u64,
+                usage.output_tokens().max(0) as u64,
+            );
+        }
+
+        let output = get_converse_output_text(response)?;
+        if self.capture_content {
+            info!(gen_ai.completion = %json!([{"role": "assistant", "content": output}]), "gen_ai.content.completion");
+        }
+        Ok(output)
+    }
+}
+
+// A low-cardinality class of the error: the Bedrock error code, or how the call failed if it
+// never got an answer from Bedrock.
+fn error_type(e: &anyhow::Error) -> &str {
+    let Some(sdk_error) = e.downcast_ref::<SdkError<ConverseError>>() else {
+        return OTHER_ERROR_TYPE;
+    };
+    if let Some(code) = sdk_error.code() {
+        return code;
+    }
+    match sdk_error {
+        SdkError::ConstructionFailure(_) => "construction_failure",
+        SdkError::TimeoutError(_) => "timeout",
+        SdkError::DispatchFailure(_) => "dispatch_failure",
+        SdkError::ResponseError(_) => "response_error",
+        _ => OTHER_ERROR_TYPE,
+    }
+}
+
+// The messages as `[{"role": ..., "content": ...}]`, the way the GenAI conventions record prompts.
+fn prompt_json(system: &[SystemContentBlock], messages: &[Message]) -> serde_json::Value {
+    let system = system
+        .iter()
+        .filter_map(|block| block.as_text().ok())
+        .map(|content| json!({"role": "system", "content": content}));
+    let messages = messages.iter().map(|message| {
+        let content: Vec<&str> = message
+            .content()
+            .iter()
+            .filter_map(|block| block.as_text().ok())
+            .map(String::as_str)
+            .collect();
+        json!({"role": message.role().as_str(), "content": content.join("\n")})
+    });
+    system.chain(messages).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConversationBuilder;
+
+    #[test]
+    fn prompts_are_recorded_as_role_and_content() {
+        let conversation = ConversationBuilder::new()
+            .with_system_prompt("Be brief.")
+            .add_user_message("hi")
+            .add_assistant_message("hello")
+            .add_user_message("bye")
+            .build()
+            .unwrap();
+        assert_eq!(
+            prompt_json(&conversation.system, &conversation.messages),
+            json!([
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": "hello"},
+                {"role": "user", "content": "bye"},
+            ])
+        );
+    }
+
+    #[test]
+    fn errors_are_typed_by_their_class_not_their_message() {
+        let timeout = anyhow::Error::new(SdkError::<ConverseError>::timeout_error("took 31s"))
+            .context("Error conversing with AWS bedrock");
+        assert_eq!(error_type(&timeout), "timeout");
+
+        let other = anyhow!("No text in the response to request 1234");
+        assert_eq!(error_type(&other), OTHER_ERROR_TYPE);
     }
 }
@@ -0,0 +1,432 @@
+//! A conversation with the tutor, for the `chat` subcommand.
+//!
+//! The whole conversation is kept in a [`Transcript`], which can be saved and picked back up.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::aws::{self, AWSClient};
+use crate::lesson::{LessonError, Level};
+use crate::{json_file, Conversation, ConversationBuilder, Language};
+
+// Only the latest turns are sent to the model, so that long chats stay within its context.
+const MAX_CONTEXT_TURNS: usize = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
+    pub at: DateTime<Utc>,
+}
+
+/// A chat with the tutor and its settings, everything needed to resume it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Transcript {
+    pub language: Language,
+    pub level: Option<Level>,
+    /// Whether the tutor spells out how its sentences are read, in romaji or pinyin.
+    pub romaji: bool,
+    pub started_at: DateTime<Utc>,
+    pub turns: Vec<Turn>,
+}
+
+impl Transcript {
+    pub fn new(language: Language, level: Option<Level>) -> Result<Self, LessonError> {
+        let mut transcript = Self {
+            language,
+            level: None,
+            romaji: false,
+            started_at: Utc::now(),
+            turns: Vec::new(),
+        };
+        transcript.set_level(level)?;
+        Ok(transcript)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Error reading {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("Error parsing {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        json_file::write(path, self)
+    }
+
+    pub fn set_level(&mut self, level: Option<Level>) -> Result<(), LessonError> {
+        if let Some(level) = level {
+            if level.language() != self.language {
+                return Err(LessonError::LevelMismatch {
+                    level,
+                    language: self.language,
+                });
+            }
+        }
+        self.level = level;
+        Ok(())
+    }
+
+    fn push(&mut self, role: Role, content: impl Into<String>) {
+        self.turns.push(Turn {
+            role,
+            content: content.into(),
+            at: Utc::now(),
+        });
+    }
+
+    fn system_prompt(&self) -> String {
+        let level = match self.level {
+            Some(level) => format!(
+                "The student is at the {} level: stick to grammar and vocabulary suited to it.",
+                level
+            ),
+            None => "Adapt to the level the student writes at.".to_string(),
+        };
+        let romaji = if self.romaji {
+            format!(
+                "After each {} sentence, add how it reads in {} between parentheses.",
+                self.language,
+                reading_name(self.language)
+            )
+        } else {
+            String::new()
+        };
+        format!(
+            "You are a friendly {language} tutor chatting with a student in a terminal. \
+            Answer in {language}, in a few short sentences, and keep the conversation going \
+            with questions. {level} \
+            When the student makes a mistake, answer first, then point it out briefly in English \
+            with the corrected sentence. \
+            When asked for an explanation, give it in English. \
+            Write plain text, without Markdown. {romaji}",
+            language = self.language,
+            level = level,
+            romaji = romaji,
+        )
+    }
+
+    // The latest turns, starting with one of the user's as the model requires.
+    fn conversation(&self) -> Result<Conversation> {
+        let mut start = self.turns.len().saturating_sub(MAX_CONTEXT_TURNS);
+        while self
+            .turns
+            .get(start)
+            .is_some_and(|turn| turn.role != Role::User)
+        {
+            start += 1;
+        }
+
+        let mut builder = ConversationBuilder::new().with_system_prompt(self.system_prompt());
+        for turn in &self.turns[start..] {
+            builder = match turn.role {
+                Role::User => builder.add_user_message(&turn.content),
+                Role::Assistant => builder.add_assistant_message(&turn.content),
+            };
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Sends the user's message and records the tutor's reply.
+    /// The message is dropped if there is no reply, so that it can be sent again.
+    pub async fn send(&mut self, client: &AWSClient, message: &str) -> Result<&str> {
+        self.push(Role::User, message);
+        let reply = match self.conversation() {
+            Ok(conversation) => client.create_conversation(conversation).await,
+            Err(e) => Err(e),
+        };
+        match reply {
+            Ok(reply) => {
+                self.push(Role::Assistant, reply);
+                Ok(&self.turns[self.turns.len() - 1].content)
+            }
+            Err(e) => {
+                self.turns.pop();
+                Err(e)
+            }
+        }
+    }
+}
+
+fn reading_name(language: Language) -> &'static str {
+    match language {
+        Language::Japanese => "romaji",
+        Language::Chinese => "pinyin",
+    }
+}
+
+/// The slash commands of the chat.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Turns romaji (or pinyin) on or off, toggles it without an argument.
+    Romaji(Option<bool>),
+    /// Explains the given text, or the tutor's last reply.
+    Explain(Option<String>),
+    /// Saves the transcript, to the given file or the current one.
+    Save(Option<PathBuf>),
+    /// Changes the level, shows it without an argument.
+    Level(Option<Level>),
+    Help,
+    Quit,
+}
+
+const HELP: &str = "\
+/romaji [on|off]  Show how the tutor's sentences are read (pinyin in Chinese)
+/explain [text]   Explain the text, or the tutor's last reply
+/save [file]      Save the transcript, later chats are saved there too
+/level [level]    Change the level, e.g. n4 or hsk2, or show it
+/help             Show this
+/quit             Leave, Ctrl-D works too";
+
+/// Parses a slash command. Returns `None` for anything else, which goes to the tutor.
+pub fn parse_command(line: &str) -> Option<Result<Command, String>> {
+    let line = line.trim().strip_prefix('/')?;
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, Some(arg.trim()).filter(|arg| !arg.is_empty())),
+        None => (line, None),
+    };
+    let command = match (name, arg) {
+        ("romaji" | "pinyin", None) => Ok(Command::Romaji(None)),
+        ("romaji" | "pinyin", Some("on")) => Ok(Command::Romaji(Some(true))),
+        ("romaji" | "pinyin", Some("off")) => Ok(Command::Romaji(Some(false))),
+        ("romaji" | "pinyin", Some(arg)) => Err(format!("Expected on or off, not {:?}", arg)),
+        ("explain", arg) => Ok(Command::Explain(arg.map(str::to_string))),
+        ("save", arg) => Ok(Command::Save(arg.map(PathBuf::from))),
+        ("level", None) => Ok(Command::Level(None)),
+        ("level", Some(arg)) => Level::from_str(arg, true)
+            .map(|level| Command::Level(Some(level)))
+            .map_err(|_| format!("Unknown level {:?}, try n5 to n1 or hsk1 to hsk6", arg)),
+        ("help", _) => Ok(Command::Help),
+        ("quit" | "exit", _) => Ok(Command::Quit),
+        _ => Err(format!("Unknown command /{}, try /help", name)),
+    };
+    Some(command)
+}
+
+/// Chats with the tutor on stdin and stdout until the user quits.
+///
+/// With a `save_path`, the transcript is saved there after every reply.
+pub async fn run(mut transcript: Transcript, mut save_path: Option<PathBuf>) -> Result<()> {
+    let client = AWSClient::new(Some(aws::InferenceParameters {
+        temperature: 0.8,
+        max_tokens: 1024,
+        top_p: 0.95,
+    }))
+    .await
+    .context("Error creating AWS client")?;
+
+    for turn in &transcript.turns {
+        print_turn(turn.role, &turn.content);
+    }
+    println!(
+        "Chatting in {}{}. /help for the commands, /quit or Ctrl-D to leave.",
+        transcript.language,
+        transcript
+            .level
+            .map(|level| format!(" ({})", level))
+            .unwrap_or_default()
+    );
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            break;
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let message = match parse_command(line) {
+            None => line.to_string(),
+            Some(Err(e)) => {
+                println!("{}", e);
+                continue;
+            }
+            Some(Ok(command)) => match command {
+                Command::Quit => break,
+                Command::Help => {
+                    println!("{}", HELP);
+                    continue;
+                }
+                Command::Romaji(on) => {
+                    transcript.romaji = on.unwrap_or(!transcript.romaji);
+                    let state = if transcript.romaji { "on" } else { "off" };
+                    println!("{} {}", reading_name(transcript.language), state);
+                    continue;
+                }
+                Command::Level(None) => {
+                    match transcript.level {
+                        Some(level) => println!("{}", level),
+                        None => println!("No level set"),
+                    }
+                    continue;
+                }
+                Command::Level(Some(level)) => {
+                    match transcript.set_level(Some(level)) {
+                        Ok(()) => println!("Level set to {}", level),
+                        Err(e) => println!("{}", e),
+                    }
+                    continue;
+                }
+                Command::Save(path) => {
+                    let path = path.or_else(|| save_path.clone()).unwrap_or_else(|| {
+                        PathBuf::from(format!("chat-{}.json", Local::now().format("%Y%m%d-%H%M%S")))
+                    });
+                    match transcript.save(&path) {
+                        Ok(()) => println!("Saved to {}", path.display()),
+                        Err(e) => println!("{:#}", e),
+                    }
+                    save_path = Some(path);
+                    continue;
+                }
+                Command::Explain(Some(text)) => format!(
+                    "Explain \"{}\" in English: what it means, word by word, and the grammar it uses.",
+                    text
+                ),
+                Command::Explain(None) => {
+                    if !transcript.turns.iter().any(|t| t.role == Role::Assistant) {
+                        println!("Nothing to explain yet");
+                        continue;
+                    }
+                    "Explain your last message in English: what it means, word by word, and the \
+                    grammar it uses."
+                        .to_string()
+                }
+            },
+        };
+
+        match transcript.send(&client, &message).await {
+            Ok(reply) => print_turn(Role::Assistant, reply),
+            Err(e) => {
+                println!("The tutor couldn't answer: {:#}", e);
+                continue;
+            }
+        }
+        if let Some(path) = &save_path {
+            if let Err(e) = transcript.save(path) {
+                println!("{:#}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_turn(role: Role, content: &str) {
+    match role {
+        Role::User => println!("> {}", content),
+        Role::Assistant => println!("\n{}\n", content),
+    }
+}
+
+/// Opens the transcript to resume, failing if there isn't one.
+pub fn resume(path: &Path) -> Result<Transcript> {
+    if !path.exists() {
+        bail!("There is no transcript at {}", path.display());
+    }
+    Transcript::load(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_slash_commands() {
+        assert_eq!(parse_command("hello"), None);
+        assert_eq!(parse_command("/romaji"), Some(Ok(Command::Romaji(None))));
+        assert_eq!(
+            parse_command(" /romaji off "),
+            Some(Ok(Command::Romaji(Some(false))))
+        );
+        assert!(matches!(parse_command("/romaji maybe"), Some(Err(_))));
+        assert_eq!(
+            parse_command("/explain 食べてもいい"),
+            Some(Ok(Command::Explain(Some("食べてもいい".to_string()))))
+        );
+        assert_eq!(parse_command("/explain"), Some(Ok(Command::Explain(None))));
+        assert_eq!(
+            parse_command("/save chat.json"),
+            Some(Ok(Command::Save(Some(PathBuf::from("chat.json")))))
+        );
+        assert_eq!(
+            parse_command("/level N4"),
+            Some(Ok(Command::Level(Some(Level::N4))))
+        );
+        assert!(matches!(parse_command("/level n6"), Some(Err(_))));
+        assert_eq!(parse_command("/exit"), Some(Ok(Command::Quit)));
+        assert!(matches!(parse_command("/dance"), Some(Err(_))));
+    }
+
+    #[test]
+    fn levels_have_to_match_the_language() {
+        assert!(Transcript::new(Language::Chinese, Some(Level::N3)).is_err());
+        let mut transcript = Transcript::new(Language::Chinese, Some(Level::Hsk3)).unwrap();
+        assert!(transcript.set_level(Some(Level::N3)).is_err());
+        assert_eq!(transcript.level, Some(Level::Hsk3));
+    }
+
+    #[test]
+    fn prompts_follow_the_settings() {
+        let mut transcript = Transcript::new(Language::Chinese, Some(Level::Hsk2)).unwrap();
+        let prompt = transcript.system_prompt();
+        assert!(prompt.contains("the HSK 2 level"));
+        assert!(!prompt.contains("pinyin"));
+
+        transcript.romaji = true;
+        assert!(transcript.system_prompt().contains("reads in pinyin"));
+    }
+
+    #[test]
+    fn sends_the_latest_turns_starting_with_the_user() {
+        let mut transcript = Transcript::new(Language::Japanese, None).unwrap();
+        for i in 0..MAX_CONTEXT_TURNS {
+            transcript.push(Role::User, format!("question {}", i));
+            transcript.push(Role::Assistant, format!("answer {}", i));
+        }
+        transcript.push(Role::User, "last question");
+
+        let conversation = transcript.conversation().unwrap();
+        let messages = &conversation.messages;
+        // The oldest turn in the window is the tutor's, so it's left out too.
+        assert_eq!(messages.len(), MAX_CONTEXT_TURNS - 1);
+        assert_eq!(messages[0].role().as_str(), "user");
+        assert_eq!(
+            messages.last().unwrap().content()[0].as_text().unwrap(),
+            "last question"
+        );
+    }
+
+    #[test]
+    fn transcripts_can_be_resumed() {
+        let path = std::env::temp_dir().join(format!("kamekai-chat-{}.json", uuid::Uuid::new_v4()));
+        assert!(resume(&path).is_err());
+
+        let mut transcript = Transcript::new(Language::Japanese, Some(Level::N5)).unwrap();
+        transcript.romaji = true;
+        transcript.push(Role::User, "こんにちは");
+        transcript.push(Role::Assistant, "こんにちは！(Konnichiwa!)");
+        transcript.save(&path).unwrap();
+
+        let resumed = resume(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resumed.level, Some(Level::N5));
+        assert!(resumed.romaji);
+        assert_eq!(resumed.turns.len(), 2);
+        assert_eq!(resumed.turns[1].role, Role::Assistant);
+    }
+}
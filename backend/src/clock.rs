@@ -0,0 +1,70 @@
+//! The current time, and the time zones it is shown to users in.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+
+/// Where the current time comes from, so that tests can pick it.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// The current time in `time_zone`.
+    fn now_in(&self, time_zone: Tz) -> DateTime<Tz> {
+        self.now().with_timezone(&time_zone)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock stopped at the given time.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// Parses an IANA time zone name, e.g., "Asia/Tokyo".
+pub fn parse_time_zone(name: &str) -> Result<Tz, String> {
+    name.trim()
+        .parse()
+        .map_err(|_| format!("unknown time zone {:?}", name))
+}
+
+/// The time zone of this machine, or UTC if it can't be told.
+pub fn local_time_zone() -> Tz {
+    iana_time_zone::get_timezone()
+        .ok()
+        .and_then(|name| parse_time_zone(&name).ok())
+        .unwrap_or(Tz::UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_the_time_in_the_time_zone() {
+        let clock = FixedClock("2026-10-19T23:30:00Z".parse().unwrap());
+        let tokyo = clock.now_in(parse_time_zone("Asia/Tokyo").unwrap());
+        assert_eq!(
+            tokyo.format("%Y-%m-%d %H:%M %Z %A").to_string(),
+            "2026-10-20 08:30 JST Tuesday"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_time_zones() {
+        assert!(parse_time_zone("Mars/Olympus_Mons").is_err());
+        assert_eq!(parse_time_zone(" UTC ").unwrap(), Tz::UTC);
+    }
+}
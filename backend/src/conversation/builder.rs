@@ -1,10 +1,18 @@
 use crate::error::AppError;
-use aws_sdk_bedrockruntime::types::{ContentBlock, ConversationRole, Message};
+use aws_sdk_bedrockruntime::types::{ContentBlock, ConversationRole, Message, SystemContentBlock};
+
+/// A conversation, ready to be sent to the model.
+#[derive(Clone, Debug)]
+pub struct Conversation {
+    pub system: Vec<SystemContentBlock>,
+    /// Alternating user and assistant turns, starting and ending with the user.
+    pub messages: Vec<Message>,
+}
 
 #[derive(Debug)]
 pub struct ConversationBuilder {
     system_prompt: Option<String>,
-    conversation_history: Vec<String>,
+    conversation_history: Vec<(ConversationRole, String)>,
 }
 
 impl Default for ConversationBuilder {
@@ -28,35 +36,110 @@ impl ConversationBuilder {
 
     pub fn add_user_message(mut self, message: impl Into<String>) -> Self {
         self.conversation_history
-            .push(format!("User: {}", message.into()));
+            .push((ConversationRole::User, message.into()));
         self
     }
 
-    #[allow(dead_code)]
+    /// Adds one of the model's previous answers, so that it sees the conversation so far.
     pub fn add_assistant_message(mut self, message: impl Into<String>) -> Self {
         self.conversation_history
-            .push(format!("Assistant: {}", message.into()));
+            .push((ConversationRole::Assistant, message.into()));
         self
     }
 
-    pub fn build(self) -> Result<Message, AppError> {
-        let mut content = String::new();
-
-        // Add system prompt if present.
-        if let Some(system) = self.system_prompt {
-            content.push_str(&format!("System: {}\n\n", system));
+    /// Turns the history into messages. Consecutive messages from the same role are merged,
+    /// since the model expects the roles to alternate.
+    pub fn build(self) -> Result<Conversation, AppError> {
+        let mut turns: Vec<(ConversationRole, String)> = Vec::new();
+        for (role, message) in self.conversation_history {
+            match turns.last_mut() {
+                Some((last_role, last)) if *last_role == role => {
+                    last.push_str("\n\n");
+                    last.push_str(&message);
+                }
+                _ => turns.push((role, message)),
+            }
         }
 
-        // Add conversation history.
-        for message in self.conversation_history {
-            content.push_str(&message);
-            content.push_str("\n\n");
+        match (turns.first(), turns.last()) {
+            (Some((ConversationRole::User, _)), Some((ConversationRole::User, _))) => {}
+            _ => {
+                return Err(AppError::MessageParse(
+                    "Conversations have to start and end with a user message".to_string(),
+                ))
+            }
         }
 
-        Message::builder()
-            .role(ConversationRole::User)
-            .content(ContentBlock::Text(content))
+        let messages = turns
+            .into_iter()
+            .map(|(role, message)| {
+                Message::builder()
+                    .role(role)
+                    .content(ContentBlock::Text(message))
+                    .build()
+                    .map_err(|e| AppError::MessageParse(format!("Failed to build message: {}", e)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Conversation {
+            system: self
+                .system_prompt
+                .map(SystemContentBlock::Text)
+                .into_iter()
+                .collect(),
+            messages,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turns(conversation: &Conversation) -> Vec<(&str, &str)> {
+        conversation
+            .messages
+            .iter()
+            .map(|message| {
+                (
+                    message.role().as_str(),
+                    message.content()[0].as_text().unwrap().as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_assistant_turns() {
+        let conversation = ConversationBuilder::new()
+            .with_system_prompt("Be brief.")
+            .add_user_message("こんにちは")
+            .add_assistant_message("こんにちは！")
+            .add_user_message("元気？")
+            .add_user_message("I mean, how are you?")
             .build()
-            .map_err(|e| AppError::MessageParse(format!("Failed to build message: {}", e)))
+            .unwrap();
+        assert_eq!(
+            conversation.system,
+            [SystemContentBlock::Text("Be brief.".to_string())]
+        );
+        assert_eq!(
+            turns(&conversation),
+            [
+                ("user", "こんにちは"),
+                ("assistant", "こんにちは！"),
+                ("user", "元気？\n\nI mean, how are you?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn conversations_end_with_the_user() {
+        let result = ConversationBuilder::new()
+            .add_user_message("hi")
+            .add_assistant_message("hello")
+            .build();
+        assert!(result.is_err());
+        assert!(ConversationBuilder::new().build().is_err());
     }
 }
@@ -1,3 +1,3 @@
 pub mod builder;
 
-pub use builder::ConversationBuilder;
+pub use builder::{Conversation, ConversationBuilder};
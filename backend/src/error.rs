@@ -15,6 +15,9 @@ pub enum AppError {
     #[error("Server error: {0}")]
     Server(String),
 
+    #[error("API key error: {0}")]
+    ApiKey(String),
+
     #[error(transparent)]
     OpenTelemetry(#[from] AnyhowError),
 }
@@ -0,0 +1,113 @@
+//! Flashcards to drill what users translated, reviewed on an SM-2 schedule.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Language;
+
+mod scheduler;
+
+pub use scheduler::{Grade, Schedule, MATURE_INTERVAL_DAYS};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Card {
+    pub id: Uuid,
+    pub language: Language,
+    /// What the user is shown, in English.
+    pub front: String,
+    /// What they have to recall, in `language`.
+    pub back: String,
+    pub pronunciation: String,
+    /// E.g., the grammar behind the phrase.
+    pub notes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub schedule: Schedule,
+    /// How many times the card was forgotten after being learned.
+    pub lapses: u32,
+    pub last_reviewed_at: Option<DateTime<Utc>>,
+}
+
+impl Card {
+    /// A new card, due right away.
+    pub fn new(
+        language: Language,
+        front: impl Into<String>,
+        back: impl Into<String>,
+        pronunciation: impl Into<String>,
+        notes: Vec<String>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            language,
+            front: front.into().trim().to_string(),
+            back: back.into().trim().to_string(),
+            pronunciation: pronunciation.into(),
+            notes,
+            created_at: now,
+            schedule: Schedule::new(now),
+            lapses: 0,
+            last_reviewed_at: None,
+        }
+    }
+
+    /// Reschedules the card after the user recalled it as well as `grade` says.
+    pub fn review(&mut self, grade: Grade, now: DateTime<Utc>) -> Review {
+        if grade == Grade::Again && self.schedule.repetitions > 0 {
+            self.lapses += 1;
+        }
+        self.schedule = self.schedule.next(grade, now);
+        self.last_reviewed_at = Some(now);
+        Review {
+            card_id: self.id,
+            grade,
+            interval_days: self.schedule.interval_days,
+            reviewed_at: now,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Review {
+    pub card_id: Uuid,
+    pub grade: Grade,
+    /// The interval the review set.
+    pub interval_days: u32,
+    pub reviewed_at: DateTime<Utc>,
+}
+
+/// How a user's deck is doing.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DeckStats {
+    pub total: usize,
+    /// Never reviewed.
+    pub new: usize,
+    pub due: usize,
+    /// Reviewed, with an interval under [`MATURE_INTERVAL_DAYS`].
+    pub learning: usize,
+    pub mature: usize,
+    pub reviewed_today: usize,
+    /// The share of the last 30 days' reviews that weren't forgotten, if there were any.
+    pub retention: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_lapses_of_learned_cards_only() {
+        let now = Utc::now();
+        let mut card = Card::new(Language::Japanese, "Hi", "こんにちは", "", Vec::new(), now);
+        card.review(Grade::Again, now);
+        assert_eq!(card.lapses, 0);
+
+        card.review(Grade::Good, now);
+        let review = card.review(Grade::Again, now);
+        assert_eq!(card.lapses, 1);
+        assert_eq!(review.grade, Grade::Again);
+        assert_eq!(review.interval_days, 1);
+        assert_eq!(card.last_reviewed_at, Some(now));
+    }
+}
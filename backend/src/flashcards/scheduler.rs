@@ -0,0 +1,133 @@
+//! The SM-2 algorithm, from SuperMemo, with four grades instead of six.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+const INITIAL_EASE: f64 = 2.5;
+const MIN_EASE: f64 = 1.3;
+/// Cards with intervals this long are considered learned.
+pub const MATURE_INTERVAL_DAYS: u32 = 21;
+
+/// How well the user recalled a card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Grade {
+    /// Forgotten.
+    Again,
+    Hard,
+    Good,
+    Easy,
+}
+
+impl Grade {
+    // SM-2's 0 to 5 response quality, under 3 being a failure.
+    fn quality(self) -> f64 {
+        match self {
+            Grade::Again => 1.0,
+            Grade::Hard => 3.0,
+            Grade::Good => 4.0,
+            Grade::Easy => 5.0,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Grade::Again => "again",
+            Grade::Hard => "hard",
+            Grade::Good => "good",
+            Grade::Easy => "easy",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    /// How much the interval grows with each successful review.
+    pub ease: f64,
+    pub interval_days: u32,
+    /// Successful reviews in a row.
+    pub repetitions: u32,
+    pub due_at: DateTime<Utc>,
+}
+
+impl Schedule {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            ease: INITIAL_EASE,
+            interval_days: 0,
+            repetitions: 0,
+            due_at: now,
+        }
+    }
+
+    /// The schedule after a review graded `grade` at `now`.
+    pub fn next(&self, grade: Grade, now: DateTime<Utc>) -> Self {
+        let quality = grade.quality();
+        let (repetitions, interval_days) = if quality < 3.0 {
+            (0, 1)
+        } else {
+            let interval_days = match self.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (f64::from(self.interval_days) * self.ease).round() as u32,
+            };
+            (self.repetitions + 1, interval_days)
+        };
+        let ease =
+            (self.ease + 0.1 - (5.0 - quality) * (0.08 + (5.0 - quality) * 0.02)).max(MIN_EASE);
+        Self {
+            ease,
+            interval_days,
+            repetitions,
+            due_at: now + Duration::days(i64::from(interval_days)),
+        }
+    }
+
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.due_at <= now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn review(schedule: Schedule, grades: &[Grade]) -> Schedule {
+        grades.iter().fold(schedule, |schedule, grade| {
+            schedule.next(*grade, schedule.due_at)
+        })
+    }
+
+    #[test]
+    fn intervals_grow_with_each_good_review() {
+        let now = Utc::now();
+        let schedule = Schedule::new(now);
+        assert!(schedule.is_due(now));
+
+        let intervals: Vec<_> = (1..=4)
+            .map(|n| review(schedule, &vec![Grade::Good; n]).interval_days)
+            .collect();
+        assert_eq!(intervals, [1, 6, 15, 38]);
+        // Good answers leave the ease where it was.
+        assert_eq!(review(schedule, &[Grade::Good; 4]).ease, INITIAL_EASE);
+
+        let next = schedule.next(Grade::Good, now);
+        assert_eq!(next.due_at, now + Duration::days(1));
+        assert!(!next.is_due(now));
+    }
+
+    #[test]
+    fn forgetting_starts_over_and_makes_the_card_harder() {
+        let schedule = review(Schedule::new(Utc::now()), &[Grade::Good, Grade::Good]);
+        let forgotten = review(schedule, &[Grade::Again]);
+        assert_eq!((forgotten.repetitions, forgotten.interval_days), (0, 1));
+        assert!((forgotten.ease - 1.96).abs() < 1e-9);
+        assert!(review(schedule, &[Grade::Easy]).ease > INITIAL_EASE);
+    }
+
+    #[test]
+    fn ease_has_a_floor() {
+        let schedule = review(Schedule::new(Utc::now()), &[Grade::Again; 10]);
+        assert_eq!(schedule.ease, MIN_EASE);
+    }
+}
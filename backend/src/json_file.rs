@@ -0,0 +1,36 @@
+//! Small JSON files the server keeps its state in.
+
+use anyhow::{bail, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+
+/// Reads `path`, or returns the default value if the file doesn't exist yet.
+pub(crate) fn read<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(e).with_context(|| format!("Error reading {}", path.display())),
+    };
+    serde_json::from_str(&contents).with_context(|| format!("Error parsing {}", path.display()))
+}
+
+/// Writes `value` to `path`, only readable by its owner.
+///
+/// Writes to a temporary file first so that readers never see half a file.
+pub(crate) fn write<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let Some(file_name) = path.file_name() else {
+        bail!("Invalid path {}", path.display());
+    };
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let contents = serde_json::to_string_pretty(value)?;
+    std::fs::write(&tmp, contents).with_context(|| format!("Error writing {}", tmp.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+    }
+    std::fs::rename(&tmp, path).with_context(|| format!("Error writing {}", path.display()))
+}
@@ -1,13 +1,17 @@
-use chrono::{Local, Timelike};
+use chrono::{DateTime, TimeZone, Timelike};
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
 
 // First, we'll define our Language enum.
 // We derive several useful traits:
 // - Clone and Copy make it easy to pass around
 // - Debug for printing during development
 // - ValueEnum allows Clap to parse it from command line arguments
-#[derive(Debug, Clone, Copy, ValueEnum)]
+// - Serialize and Deserialize for the API, using the same names as Display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Language {
     Japanese,
     Chinese,
@@ -24,27 +28,130 @@ impl fmt::Display for Language {
     }
 }
 
-impl Language {
-    pub fn get_greeting(&self) -> &'static str {
+/// The language of the text users send to be translated, as told by its script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceLanguage {
+    English,
+    Japanese,
+    Chinese,
+}
+
+impl SourceLanguage {
+    /// Kana means Japanese, and hanzi without kana Chinese. Everything else is taken for
+    /// English, like the translation prompt does.
+    pub fn detect(text: &str) -> Self {
+        let is_kana = |c: char| matches!(c, '\u{3040}'..='\u{30ff}' | '\u{ff66}'..='\u{ff9d}');
+        let is_han = |c: char| matches!(c, '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}');
+        if text.chars().any(is_kana) {
+            SourceLanguage::Japanese
+        } else if text.chars().any(is_han) {
+            SourceLanguage::Chinese
+        } else {
+            SourceLanguage::English
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Language::Japanese => Self::get_japanese_greeting(),
-            Language::Chinese => Self::get_chinese_greeting(),
+            SourceLanguage::English => "english",
+            SourceLanguage::Japanese => "japanese",
+            SourceLanguage::Chinese => "chinese",
+        }
+    }
+}
+
+impl FromStr for SourceLanguage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "english" => Ok(SourceLanguage::English),
+            "japanese" => Ok(SourceLanguage::Japanese),
+            "chinese" => Ok(SourceLanguage::Chinese),
+            _ => Err(format!("unknown source language {:?}", s)),
         }
     }
+}
 
-    fn get_japanese_greeting() -> &'static str {
-        match Local::now().hour() {
-            5..=10 => "おはようございます。",
-            11..=17 => "こんにちは。",
-            _ => "こんばんは。",
+// Greetings by the hour they start at, in order.
+// Before the first one, it's still the last one of the day before.
+const JAPANESE_GREETINGS: [(u32, &str); 3] = [
+    (5, "おはようございます。"),
+    (11, "こんにちは。"),
+    (18, "こんばんは。"),
+];
+const CHINESE_GREETINGS: [(u32, &str); 3] = [(5, "早上好。"), (11, "下午好。"), (18, "晚上好。")];
+
+impl Language {
+    /// The greeting for the time of day `time` is at, in its own time zone.
+    pub fn greeting<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> &'static str {
+        let greetings: &[(u32, &'static str)] = match self {
+            Language::Japanese => &JAPANESE_GREETINGS,
+            Language::Chinese => &CHINESE_GREETINGS,
+        };
+        let hour = time.hour();
+        let (_, greeting) = greetings
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= hour)
+            .unwrap_or(&greetings[greetings.len() - 1]);
+        greeting
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{parse_time_zone, Clock, FixedClock};
+    use chrono::Utc;
+
+    #[test]
+    fn greets_according_to_the_hour() {
+        let cases = [
+            (0, "こんばんは。", "晚上好。"),
+            (4, "こんばんは。", "晚上好。"),
+            (5, "おはようございます。", "早上好。"),
+            (10, "おはようございます。", "早上好。"),
+            (11, "こんにちは。", "下午好。"),
+            (17, "こんにちは。", "下午好。"),
+            (18, "こんばんは。", "晚上好。"),
+            (23, "こんばんは。", "晚上好。"),
+        ];
+        for (hour, japanese, chinese) in cases {
+            let time = Utc.with_ymd_and_hms(2026, 10, 19, hour, 0, 0).unwrap();
+            assert_eq!(Language::Japanese.greeting(&time), japanese, "{}h", hour);
+            assert_eq!(Language::Chinese.greeting(&time), chinese, "{}h", hour);
         }
     }
 
-    fn get_chinese_greeting() -> &'static str {
-        match Local::now().hour() {
-            5..=10 => "早上好。",
-            11..=17 => "下午好。",
-            _ => "晚上好。",
+    #[test]
+    fn greets_in_the_users_time_zone() {
+        // 23:00 in UTC is the next morning in Tokyo and Shanghai.
+        let clock = FixedClock(Utc.with_ymd_and_hms(2026, 10, 19, 23, 0, 0).unwrap());
+        let tokyo = clock.now_in(parse_time_zone("Asia/Tokyo").unwrap());
+        let shanghai = clock.now_in(parse_time_zone("Asia/Shanghai").unwrap());
+        assert_eq!(Language::Japanese.greeting(&tokyo), "おはようございます。");
+        assert_eq!(Language::Chinese.greeting(&shanghai), "早上好。");
+        assert_eq!(Language::Japanese.greeting(&clock.now()), "こんばんは。");
+    }
+
+    #[test]
+    fn detects_the_source_language_by_script() {
+        assert_eq!(
+            SourceLanguage::detect("I told you so"),
+            SourceLanguage::English
+        );
+        assert_eq!(
+            SourceLanguage::detect("だから言ったでしょう"),
+            SourceLanguage::Japanese
+        );
+        assert_eq!(
+            SourceLanguage::detect("我早就跟你说了"),
+            SourceLanguage::Chinese
+        );
+        for language in [SourceLanguage::English, SourceLanguage::Chinese] {
+            assert_eq!(language.as_str().parse(), Ok(language));
         }
     }
 }
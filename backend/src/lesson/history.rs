@@ -0,0 +1,241 @@
+//! The lessons each user has been given, so that the next ones don't repeat them.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{Formality, Lesson, Level};
+use crate::per_user_store::{Item, PerUserStore};
+use crate::Language;
+
+// How much of the history makes it into the prompt.
+const MAX_PROGRESS_GRAMMAR_POINTS: usize = 40;
+const MAX_PROGRESS_VOCABULARY: usize = 200;
+
+/// What a user has already been taught in a language, oldest first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub grammar_points: Vec<String>,
+    pub vocabulary: Vec<String>,
+}
+
+impl Progress {
+    pub fn is_empty(&self) -> bool {
+        self.grammar_points.is_empty() && self.vocabulary.is_empty()
+    }
+}
+
+/// A past lesson, without its content.
+#[derive(Clone, Debug, Serialize)]
+pub struct LessonSummary {
+    pub id: Uuid,
+    pub title: String,
+    pub language: Language,
+    pub level: Option<Level>,
+    pub theme: Option<String>,
+    pub formality: Formality,
+    pub grammar_point: String,
+    pub vocabulary: Vec<String>,
+    pub generated_at: DateTime<Utc>,
+}
+
+impl From<&Lesson> for LessonSummary {
+    fn from(lesson: &Lesson) -> Self {
+        let params = &lesson.metadata.params;
+        Self {
+            id: lesson.metadata.id,
+            title: lesson.content.title.clone(),
+            language: params.language,
+            level: params.level,
+            theme: params.theme.clone(),
+            formality: params.formality,
+            grammar_point: lesson.content.grammar_point.pattern.clone(),
+            vocabulary: lesson
+                .content
+                .vocabulary
+                .iter()
+                .map(|v| v.word.clone())
+                .collect(),
+            generated_at: lesson.metadata.generated_at,
+        }
+    }
+}
+
+impl Item for Lesson {
+    const KIND: &'static str = "lesson";
+    const MAX_PER_USER: usize = 500;
+
+    fn id(&self) -> Uuid {
+        self.metadata.id
+    }
+}
+
+/// Every user's lessons, oldest first.
+pub type LessonHistory = PerUserStore<Lesson>;
+
+impl LessonHistory {
+    /// The user's lessons, newest first, optionally only those in `language`.
+    pub async fn list(
+        &self,
+        sub: &str,
+        language: Option<Language>,
+        limit: usize,
+    ) -> Result<Vec<LessonSummary>> {
+        self.read(sub, |lessons| {
+            lessons
+                .iter()
+                .rev()
+                .filter(|lesson| language.is_none_or(|l| lesson.metadata.params.language == l))
+                .take(limit)
+                .map(LessonSummary::from)
+                .collect()
+        })
+        .await
+    }
+
+    /// The grammar points and words the user was most recently taught in `language`.
+    pub async fn progress(&self, sub: &str, language: Language) -> Result<Progress> {
+        let mut progress = Progress::default();
+        self.read(sub, |lessons| {
+            let lessons = lessons
+                .iter()
+                .filter(|lesson| lesson.metadata.params.language == language);
+            // Newest first so that the most recent ones are kept, then put back in order.
+            for lesson in lessons.rev() {
+                let pattern = &lesson.content.grammar_point.pattern;
+                if progress.grammar_points.len() < MAX_PROGRESS_GRAMMAR_POINTS
+                    && !progress.grammar_points.contains(pattern)
+                {
+                    progress.grammar_points.push(pattern.clone());
+                }
+                for vocabulary in lesson.content.vocabulary.iter().rev() {
+                    if progress.vocabulary.len() < MAX_PROGRESS_VOCABULARY
+                        && !progress.vocabulary.contains(&vocabulary.word)
+                    {
+                        progress.vocabulary.push(vocabulary.word.clone());
+                    }
+                }
+            }
+        })
+        .await?;
+        progress.grammar_points.reverse();
+        progress.vocabulary.reverse();
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{LessonContent, LessonMetadata, LessonParams};
+    use super::*;
+    use serde_json::json;
+
+    fn lesson(language: Language, pattern: &str, words: &[&str]) -> Lesson {
+        let vocabulary: Vec<_> = words
+            .iter()
+            .map(|word| json!({"word": word, "reading": "", "meaning": ""}))
+            .collect();
+        let content: LessonContent = serde_json::from_value(json!({
+            "title": format!("Learning {}", pattern),
+            "grammar_point": {"pattern": pattern, "meaning": "", "explanation": "", "examples": []},
+            "vocabulary": vocabulary,
+            "scenario": {"setting": "", "dialogue": []},
+            "cultural_note": "",
+            "formality_notes": [],
+            "pronunciation_guide": []
+        }))
+        .unwrap();
+        Lesson {
+            metadata: LessonMetadata {
+                id: Uuid::new_v4(),
+                params: LessonParams::new(language),
+                generated_at: Utc::now(),
+            },
+            content,
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_the_newest_lessons_first() {
+        let history = LessonHistory::in_memory();
+        let first = lesson(Language::Japanese, "〜たい", &["水"]);
+        let second = lesson(Language::Chinese, "了", &["水"]);
+        let third = lesson(Language::Japanese, "〜ている", &["本"]);
+        for lesson in [&first, &second, &third] {
+            history.save("user-1", lesson).await.unwrap();
+        }
+
+        let listed: Vec<_> = history
+            .list("user-1", None, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|l| l.grammar_point)
+            .collect();
+        assert_eq!(listed, ["〜ている", "了", "〜たい"]);
+
+        let japanese = history
+            .list("user-1", Some(Language::Japanese), 1)
+            .await
+            .unwrap();
+        assert_eq!(japanese.len(), 1);
+        assert_eq!(japanese[0].id, third.metadata.id);
+
+        assert!(history.list("user-2", None, 10).await.unwrap().is_empty());
+        assert!(history
+            .get("user-2", first.metadata.id)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            history
+                .get("user-1", first.metadata.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .content
+                .title,
+            "Learning 〜たい"
+        );
+    }
+
+    #[tokio::test]
+    async fn progress_is_per_language_and_deduplicated() {
+        let history = LessonHistory::in_memory();
+        history
+            .save(
+                "user-1",
+                &lesson(Language::Japanese, "〜たい", &["水", "本"]),
+            )
+            .await
+            .unwrap();
+        history
+            .save("user-1", &lesson(Language::Chinese, "了", &["书"]))
+            .await
+            .unwrap();
+        history
+            .save(
+                "user-1",
+                &lesson(Language::Japanese, "〜ている", &["本", "駅"]),
+            )
+            .await
+            .unwrap();
+        history
+            .save("user-1", &lesson(Language::Japanese, "〜たい", &[]))
+            .await
+            .unwrap();
+
+        let progress = history
+            .progress("user-1", Language::Japanese)
+            .await
+            .unwrap();
+        assert_eq!(progress.grammar_points, ["〜ている", "〜たい"]);
+        assert_eq!(progress.vocabulary, ["水", "本", "駅"]);
+        assert!(history
+            .progress("user-2", Language::Japanese)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
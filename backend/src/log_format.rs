@@ -0,0 +1,257 @@
+//! Log output formats.
+//!
+//! The JSON format writes one object per line, which is what CloudWatch and friends can query.
+//! Besides the event fields, each line carries the trace and span IDs and the request and user
+//! IDs of the spans the event happened in, all at the top level.
+
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing::{Event, Id, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::otel::span_context;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+// The span fields that are copied onto every log line, and the keys they are copied to.
+const CORRELATION_FIELDS: [(&str, &str); 2] =
+    [("request_id", "request_id"), ("user.id", "user_id")];
+
+/// The correlation fields of a span, kept aside so that the JSON format can get to them.
+#[derive(Debug, Default)]
+struct CorrelationFields(Map<String, Value>);
+
+impl Visit for CorrelationFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if let Some((_, key)) = CORRELATION_FIELDS.iter().find(|(f, _)| *f == field.name()) {
+            self.0.insert(key.to_string(), value.into());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+/// Records the correlation fields of every span. Needed by [`JsonFormat`].
+pub struct CorrelationLayer;
+
+impl<S> Layer<S> for CorrelationLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut fields = CorrelationFields::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(fields) = extensions.get_mut::<CorrelationFields>() {
+            values.record(fields);
+        }
+    }
+}
+
+// Collects the fields of an event.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.0
+            .insert(field.name().to_string(), value.to_string().into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// Formats events as single line JSON objects.
+///
+/// The request and user IDs are only there when [`CorrelationLayer`] is part of the subscriber,
+/// the trace and span IDs when the OpenTelemetry layer is.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".to_string(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        line.insert("level".to_string(), metadata.level().as_str().into());
+        line.insert("target".to_string(), metadata.target().into());
+
+        if let Some(scope) = ctx.event_scope() {
+            let mut spans = Vec::new();
+            // From the innermost span out, so that the closest value wins.
+            for span in scope {
+                spans.push(span.name());
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<CorrelationFields>() {
+                    for (key, value) in &fields.0 {
+                        line.entry(key.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+                if !line.contains_key("span_id") {
+                    if let Some(context) = extensions.get::<OtelData>().and_then(span_context) {
+                        line.insert(
+                            "trace_id".to_string(),
+                            context.trace_id().to_string().into(),
+                        );
+                        line.insert("span_id".to_string(), context.span_id().to_string().into());
+                    }
+                }
+            }
+            spans.reverse();
+            line.insert("spans".to_string(), spans.join(":").into());
+        }
+
+        // The event fields come last so that they can't be mistaken for the ones above.
+        event.record(&mut JsonVisitor(&mut line));
+
+        let line = serde_json::to_string(&line).map_err(|_| fmt::Error)?;
+        writeln!(writer, "{}", line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn lines(f: impl FnOnce()) -> Vec<Value> {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let tracer = TracerProvider::builder().build().tracer("test");
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .with(CorrelationLayer)
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(JsonFormat)
+                    .with_writer(move || writer.clone()),
+            );
+        tracing::subscriber::with_default(subscriber, f);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn flattens_the_correlation_fields() {
+        let lines = lines(|| {
+            let request = tracing::info_span!("http_request", request_id = "req-1");
+            let _request = request.enter();
+            let handler = tracing::info_span!("translate", user.id = %"user-1");
+            let _handler = handler.enter();
+            tracing::info!(chars = 5, "translating");
+        });
+
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "translating");
+        assert_eq!(line["chars"], 5);
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["user_id"], "user-1");
+        assert_eq!(line["spans"], "http_request:translate");
+        assert_eq!(line["trace_id"].as_str().unwrap().len(), 32);
+        assert_eq!(line["span_id"].as_str().unwrap().len(), 16);
+    }
+
+    #[test]
+    fn picks_up_fields_recorded_later() {
+        let lines = lines(|| {
+            let span = tracing::info_span!("http_request", request_id = tracing::field::Empty);
+            let _span = span.enter();
+            tracing::info!("before");
+            span.record("request_id", "req-2");
+            tracing::info!("after");
+        });
+
+        assert!(lines[0].get("request_id").is_none());
+        assert_eq!(lines[1]["request_id"], "req-2");
+    }
+
+    #[test]
+    fn events_outside_spans_have_no_ids() {
+        let lines = lines(|| tracing::warn!("starting"));
+        assert!(lines[0].get("trace_id").is_none());
+        assert!(lines[0].get("spans").is_none());
+    }
+}
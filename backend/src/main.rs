@@ -1,12 +1,28 @@
-use clap::{Parser, Subcommand};
+use anyhow::{anyhow, Context};
+use chrono::{Duration as ChronoDuration, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, ValueEnum};
 use opentelemetry::global;
 use opentelemetry::trace::Tracer;
-use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use backend::otel;
-use backend::server::run_server;
+use backend::chat::{self, Transcript};
+use backend::clock::{local_time_zone, parse_time_zone, Clock, SystemClock};
+use backend::lesson::{generate_lesson, Formality, LessonHistory, LessonParams, Level, Progress};
+use backend::log_format::LogFormat;
+use backend::otel::{self, Exporter, OtlpConfig, OtlpProtocol, SamplerKind, TelemetryConfig};
+use backend::practice::PracticeStore;
+use backend::quiz::QuizStore;
+use backend::redaction::RedactionPolicy;
+use backend::roleplay::SessionStore;
+use backend::server::{
+    run_server, ApiKeyStore, DevToken, IssuerConfig, JobConfig, NewApiKey, OidcConfig,
+    RevocationList, ServerConfig, SqliteStorage,
+};
 use backend::Language;
-use backend::{create_conversation, AppError};
+use backend::{init_cli_logging, AppError};
 
 #[derive(Parser)]
 #[command(version)]
@@ -19,15 +35,74 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 
+    /// How logs are written out
+    #[arg(
+        long,
+        global = true,
+        env = "APP_LOG_FORMAT",
+        value_enum,
+        default_value = "text"
+    )]
+    log_format: LogFormat,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+/// How the CLI prints what it got.
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+// Parsed once at startup, the size of the server variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
+    /// Generate a lesson
     Lesson {
         #[arg(short, long, value_enum)]
         language: Language,
+
+        /// JLPT level for Japanese, HSK level for Chinese
+        #[arg(long, value_enum)]
+        level: Option<Level>,
+
+        /// What the vocabulary and scenario are about, e.g. travel, business or food
+        #[arg(long)]
+        theme: Option<String>,
+
+        #[arg(long, value_enum, default_value = "mixed")]
+        formality: Formality,
+
+        /// The language explanations are written in
+        #[arg(long, default_value = "English")]
+        explain_in: String,
+
+        /// Time zone of the lesson's greeting and date, e.g. Asia/Tokyo, this machine's by default
+        #[arg(long, env = "APP_TIME_ZONE", value_parser = parse_time_zone)]
+        time_zone: Option<Tz>,
+
+        #[arg(short, long, value_enum, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Chat with the tutor
+    Chat {
+        #[arg(short, long, value_enum, required_unless_present = "resume")]
+        language: Option<Language>,
+
+        /// JLPT level for Japanese, HSK level for Chinese
+        #[arg(long, value_enum, conflicts_with = "resume")]
+        level: Option<Level>,
+
+        /// Transcript to pick the conversation back up from, and to keep saving it to
+        #[arg(long, conflicts_with = "language")]
+        resume: Option<PathBuf>,
+
+        /// File to save the transcript to after every reply
+        #[arg(long, conflicts_with = "resume")]
+        save: Option<PathBuf>,
     },
     Server {
         #[arg(short, long, default_value = "8080")]
@@ -38,55 +113,351 @@ enum Commands {
         #[arg(long, env = "APP_ENABLE_ANSI_LOGS", default_value = "true")]
         enable_ansi: bool,
 
+        /// Where to export traces, metrics and logs
+        #[arg(
+            long,
+            env = "APP_TELEMETRY_EXPORTER",
+            value_enum,
+            default_value = "none"
+        )]
+        telemetry_exporter: Exporter,
+
+        #[arg(
+            long,
+            env = "OTEL_EXPORTER_OTLP_PROTOCOL",
+            value_enum,
+            default_value = "grpc"
+        )]
+        otlp_protocol: OtlpProtocol,
+
+        /// OTLP collector to export to, defaults to one on localhost
+        #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+        otlp_endpoint: Option<String>,
+
+        /// Headers to send with every export, as key=value
+        #[arg(
+            long = "otlp-header",
+            env = "OTEL_EXPORTER_OTLP_HEADERS",
+            value_delimiter = ',',
+            value_parser = otel::parse_header,
+            hide_env_values = true
+        )]
+        otlp_headers: Vec<(String, String)>,
+
+        /// Sent as the x-honeycomb-team exporter header
+        #[arg(long, env = "HONEYCOMB_API_KEY", hide_env_values = true)]
+        honeycomb_api_key: Option<String>,
+
+        #[arg(
+            long,
+            env = "OTEL_TRACES_SAMPLER",
+            value_enum,
+            default_value = "parentbased_always_on"
+        )]
+        trace_sampler: SamplerKind,
+
+        /// Share of traces kept by the ratio samplers
+        #[arg(long, env = "OTEL_TRACES_SAMPLER_ARG", default_value_t = 1.0)]
+        trace_sampler_arg: f64,
+
         #[arg(long, short, env = "APP_REQ_TIMEOUT", default_value_t = 60)]
         request_timeout: u64,
 
-        #[arg(long, env = "APP_USER_POOL")]
-        cognito_user_pool: String,
+        /// JSON file listing the trusted OIDC issuers
+        #[arg(long, env = "APP_OIDC_CONFIG")]
+        oidc_config: Option<PathBuf>,
+
+        /// Cognito user pool endpoint, trusted in addition to the issuers in --oidc-config
+        #[arg(long, env = "APP_USER_POOL", requires = "cognito_client_id")]
+        cognito_user_pool: Option<String>,
+
+        #[arg(long, env = "APP_CLIENT_ID", requires = "cognito_user_pool")]
+        cognito_client_id: Option<String>,
+
+        /// Number of background workers processing translation jobs
+        #[arg(long, env = "APP_JOB_WORKERS", default_value_t = 4)]
+        job_workers: usize,
+
+        /// Maximum number of translation jobs waiting for a worker
+        #[arg(long, env = "APP_JOB_QUEUE_SIZE", default_value_t = 100)]
+        job_queue_size: usize,
+
+        /// Seconds to keep finished translation jobs around for polling
+        #[arg(long, env = "APP_JOB_RETENTION", default_value_t = 3600)]
+        job_retention: u64,
+
+        /// Maximum number of queued or running translation jobs per user
+        #[arg(long, env = "APP_JOB_MAX_PER_USER", default_value_t = 5)]
+        job_max_per_user: usize,
+
+        /// Headers whose values are masked in logs and spans (Authorization always is)
+        #[arg(
+            long,
+            env = "APP_REDACT_HEADERS",
+            value_delimiter = ',',
+            default_value = "cookie,set-cookie,x-api-key"
+        )]
+        redact_headers: Vec<String>,
+
+        /// JSON fields that are masked whenever a payload is logged
+        #[arg(
+            long,
+            env = "APP_REDACT_FIELDS",
+            value_delimiter = ',',
+            default_value = "password,token,access_token,id_token,refresh_token"
+        )]
+        redact_fields: Vec<String>,
+
+        /// Allow the text users send and the model output to show up in logs
+        #[arg(long, env = "APP_LOG_USER_CONTENT", default_value = "false")]
+        log_user_content: bool,
+
+        /// JSON file with the API keys to accept, as managed by the api-key subcommands
+        #[arg(long, env = "APP_API_KEYS_FILE")]
+        api_keys_file: Option<PathBuf>,
+
+        /// JSON file the revoked tokens are kept in
+        #[arg(long, env = "APP_REVOCATIONS_FILE", default_value = "revocations.json")]
+        revocations_file: PathBuf,
+
+        /// SQLite database the users' translation history, flashcards, lessons, exercises,
+        /// quizzes (right answers included) and role-play sessions are kept in
+        #[arg(long, env = "APP_DATABASE_FILE", default_value = "kamekai.db")]
+        database_file: PathBuf,
+
+        /// Issue our own tokens with an in-memory key, for local development only
+        #[arg(long, env = "APP_DEV_AUTH", default_value = "false")]
+        dev_auth: bool,
+    },
+    /// Get an access token from a server running with --dev-auth
+    DevToken {
+        /// The server to get the token from
+        #[arg(long, default_value = "http://localhost:8080")]
+        url: String,
+
+        #[arg(long, default_value = "dev")]
+        sub: String,
+
+        /// Scopes to grant
+        #[arg(long = "scope", default_values = ["kamekai/translate", "kamekai/learn"])]
+        scopes: Vec<String>,
 
-        #[arg(long, env = "APP_CLIENT_ID")]
-        cognito_client_id: String,
+        /// Groups to put the user in, e.g. admin
+        #[arg(long = "group")]
+        groups: Vec<String>,
+
+        /// Lifetime of the token in seconds, at most a day
+        #[arg(long, default_value_t = 3600)]
+        expires_in: u64,
+    },
+    /// Manage the API keys scripts and batch jobs authenticate with
+    ApiKey {
+        /// JSON file the API keys are stored in
+        #[arg(long, env = "APP_API_KEYS_FILE", default_value = "api-keys.json")]
+        file: PathBuf,
+
+        #[command(subcommand)]
+        command: ApiKeyCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApiKeyCommands {
+    /// Create a key, which is printed only once
+    Mint {
+        /// The user the key acts as
+        #[arg(long)]
+        sub: String,
+
+        /// What the key is for
+        #[arg(long)]
+        name: String,
+
+        /// Scopes granted to the key, e.g. kamekai/translate
+        #[arg(long = "scope", required = true)]
+        scopes: Vec<String>,
+
+        /// Days until the key expires, it never does if not set
+        #[arg(long)]
+        expires_in_days: Option<i64>,
+    },
+    /// List the keys
+    List {
+        /// Only list the keys of this user
+        #[arg(long)]
+        sub: Option<String>,
     },
+    /// Revoke a key, effective on the next request
+    Revoke { id: String },
 }
 
 async fn run(cli: Cli) -> Result<(), AppError> {
     match cli.command {
-        Some(Commands::Lesson { language }) => {
-            let response = create_conversation(language)
+        Some(Commands::Lesson {
+            language,
+            level,
+            theme,
+            formality,
+            explain_in,
+            time_zone,
+            output,
+        }) => {
+            init_cli_logging(cli.log_format)?;
+            let params = LessonParams {
+                language,
+                level,
+                theme,
+                formality,
+                explanation_language: explain_in,
+            }
+            .validate()
+            .map_err(|e| anyhow!("Invalid lesson: {}", e))?;
+            let now = SystemClock.now_in(time_zone.unwrap_or_else(local_time_zone));
+            let lesson = generate_lesson(params, &Progress::default(), now, false)
                 .await
                 .map_err(|e| AppError::Bedrock(format!("Failed to call bedrock: {:#?}", e)))?;
-            println!("Claude's response:\n{}", response);
+            match output {
+                OutputFormat::Text => println!("{}", lesson),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&lesson)
+                        .context("Error serializing the lesson")?
+                ),
+            }
+        }
+        Some(Commands::Chat {
+            language,
+            level,
+            resume,
+            save,
+        }) => {
+            // Logs would get in the way of the conversation, so they are only on when asked for.
+            if std::env::var_os("RUST_LOG").is_some() {
+                init_cli_logging(cli.log_format)?;
+            }
+            let transcript = match &resume {
+                Some(path) => {
+                    chat::resume(path).map_err(|e| anyhow!("Failed to resume the chat: {:#}", e))?
+                }
+                None => Transcript::new(language.expect("clap requires a language"), level)
+                    .map_err(|e| anyhow!("Invalid chat: {}", e))?,
+            };
+            chat::run(transcript, resume.or(save))
+                .await
+                .map_err(|e| AppError::Bedrock(format!("Chat failed: {:#}", e)))?;
         }
         Some(Commands::Server {
             port,
             host,
             enable_ansi,
+            telemetry_exporter,
+            otlp_protocol,
+            otlp_endpoint,
+            mut otlp_headers,
+            honeycomb_api_key,
+            trace_sampler,
+            trace_sampler_arg,
             request_timeout,
+            oidc_config,
             cognito_user_pool,
             cognito_client_id,
+            job_workers,
+            job_queue_size,
+            job_retention,
+            job_max_per_user,
+            redact_headers,
+            redact_fields,
+            log_user_content,
+            api_keys_file,
+            revocations_file,
+            database_file,
+            dev_auth,
         }) => {
-            let honeycomb_api_key = env::var("HONEYCOMB_API_KEY")
-                .map_err(|e| AppError::Server(format!("HONEYCOMB_API_KEY is empty: {}", e)))?;
-            let otel_endpoint = env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
-                .unwrap_or("api.honeycomb.io:443".to_string());
-            otel::init_tracer(honeycomb_api_key, otel_endpoint, enable_ansi)
-                .map_err(AppError::OpenTelemetry)?;
+            if let Some(api_key) = honeycomb_api_key {
+                otlp_headers.push(("x-honeycomb-team".to_string(), api_key));
+            }
+            otel::init_telemetry(TelemetryConfig {
+                exporter: telemetry_exporter,
+                otlp: OtlpConfig {
+                    protocol: otlp_protocol,
+                    endpoint: otlp_endpoint,
+                    headers: otlp_headers,
+                },
+                sampler: trace_sampler.sampler(trace_sampler_arg)?,
+                enable_ansi,
+                log_format: cli.log_format,
+            })
+            .map_err(AppError::OpenTelemetry)?;
+
+            let mut oidc = match oidc_config {
+                Some(path) => OidcConfig::from_file(path)
+                    .map_err(|e| AppError::Server(format!("Invalid OIDC config: {:#}", e)))?,
+                None => OidcConfig::default(),
+            };
+            if let (Some(user_pool), Some(client_id)) = (cognito_user_pool, cognito_client_id) {
+                oidc.issuers
+                    .push(IssuerConfig::cognito(&user_pool, client_id));
+            }
+
+            let api_keys = api_keys_file
+                .map(ApiKeyStore::open)
+                .transpose()
+                .map_err(|e| AppError::ApiKey(format!("{:#}", e)))?;
+            let revocations = RevocationList::open(revocations_file)
+                .map_err(|e| AppError::Server(format!("Invalid revocation list: {:#}", e)))?;
+            let storage = SqliteStorage::open(database_file)
+                .map(Arc::new)
+                .map_err(|e| AppError::Server(format!("Invalid database: {:#}", e)))?;
+            let lessons = LessonHistory::new(Arc::clone(&storage));
+            let practice = PracticeStore::new(Arc::clone(&storage));
+            let quizzes = QuizStore::new(Arc::clone(&storage));
+            let sessions = SessionStore::new(Arc::clone(&storage));
 
             let tracer = global::tracer("my-component");
             tracer.in_span("doing_work", |_cx| {
                 print!("test span");
             });
-            let server_result = run_server(
+            let server_result = run_server(ServerConfig {
                 host,
                 port,
                 request_timeout,
-                cognito_user_pool,
-                cognito_client_id,
-            )
+                oidc,
+                jobs: JobConfig {
+                    workers: job_workers,
+                    queue_size: job_queue_size,
+                    retention: Duration::from_secs(job_retention),
+                    max_jobs_per_user: job_max_per_user,
+                },
+                redaction: RedactionPolicy::new(redact_headers, redact_fields, log_user_content),
+                api_keys,
+                revocations,
+                history: storage.clone(),
+                cards: storage,
+                lessons,
+                practice,
+                quizzes,
+                sessions,
+                dev_auth,
+            })
             .await;
             otel::shutdown_telemetry();
             server_result.map_err(|e| AppError::Server(format!("Error on server: {:#?}", e)))?;
         }
+        Some(Commands::DevToken {
+            url,
+            sub,
+            scopes,
+            groups,
+            expires_in,
+        }) => {
+            let token = dev_token(&url, &sub, &scopes, &groups, expires_in)
+                .await
+                .map_err(|e| AppError::Server(format!("Failed to get a dev token: {:#}", e)))?;
+            println!("{}", token.access_token);
+        }
+        Some(Commands::ApiKey { file, command }) => {
+            manage_api_keys(file, command).map_err(|e| AppError::ApiKey(format!("{:#}", e)))?;
+        }
         None => {
             println!("No subcommand provided. Run with the -h flag to see usage.");
         }
@@ -95,6 +466,102 @@ async fn run(cli: Cli) -> Result<(), AppError> {
     Ok(())
 }
 
+async fn dev_token(
+    url: &str,
+    sub: &str,
+    scopes: &[String],
+    groups: &[String],
+    expires_in: u64,
+) -> anyhow::Result<DevToken> {
+    #[derive(serde::Deserialize)]
+    struct Response {
+        data: Option<DevToken>,
+        error: Option<String>,
+    }
+
+    let token_url = format!("{}/dev/token", url.trim_end_matches('/'));
+    let response: Response = reqwest::Client::new()
+        .post(&token_url)
+        .json(&serde_json::json!({
+            "sub": sub,
+            "scopes": scopes,
+            "groups": groups,
+            "expires_in": expires_in,
+        }))
+        .send()
+        .await
+        .with_context(|| {
+            format!(
+                "Error calling {}, is the server running with --dev-auth?",
+                token_url
+            )
+        })?
+        .json()
+        .await
+        .with_context(|| format!("Error parsing the response from {}", token_url))?;
+    match (response.data, response.error) {
+        (Some(token), _) => Ok(token),
+        (None, error) => {
+            anyhow::bail!(error.unwrap_or_else(|| "no token in the response".to_string()))
+        }
+    }
+}
+
+fn manage_api_keys(file: PathBuf, command: ApiKeyCommands) -> anyhow::Result<()> {
+    let store = ApiKeyStore::open(file)?;
+    match command {
+        ApiKeyCommands::Mint {
+            sub,
+            name,
+            scopes,
+            expires_in_days,
+        } => {
+            let (plain, key) = store.mint(NewApiKey {
+                name,
+                sub,
+                scopes,
+                expires_at: expires_in_days.map(|days| Utc::now() + ChronoDuration::days(days)),
+            })?;
+            println!("Created API key {} for {}.", key.id, key.sub);
+            println!(
+                "Store it somewhere safe, it won't be shown again:\n\n{}",
+                plain
+            );
+        }
+        ApiKeyCommands::List { sub } => {
+            let now = Utc::now();
+            println!(
+                "{:<16}  {:<20}  {:<36}  {:<10}  {:<20}  SCOPES",
+                "ID", "NAME", "SUB", "STATUS", "EXPIRES"
+            );
+            for key in store.list(sub.as_deref())? {
+                let status = match (key.revoked_at, key.is_active(now)) {
+                    (Some(_), _) => "revoked",
+                    (None, true) => "active",
+                    (None, false) => "expired",
+                };
+                let expires = key.expires_at.map_or("never".to_string(), |t| {
+                    t.to_rfc3339_opts(SecondsFormat::Secs, true)
+                });
+                println!(
+                    "{:<16}  {:<20}  {:<36}  {:<10}  {:<20}  {}",
+                    key.id,
+                    key.name,
+                    key.sub,
+                    status,
+                    expires,
+                    key.scopes.join(" ")
+                );
+            }
+        }
+        ApiKeyCommands::Revoke { id } => {
+            let key = store.revoke(&id)?;
+            println!("Revoked API key {} of {}.", key.id, key.sub);
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
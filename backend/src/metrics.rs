@@ -0,0 +1,74 @@
+//! Application metrics, exported through the global meter provider set up in [`crate::otel`].
+//!
+//! Without a meter provider (e.g., in the CLI) recording is a no-op.
+
+use opentelemetry::metrics::Histogram;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_semantic_conventions::attribute::{
+    GEN_AI_OPERATION_NAME, GEN_AI_REQUEST_MODEL, GEN_AI_SYSTEM, GEN_AI_TOKEN_TYPE,
+    HTTP_REQUEST_METHOD, HTTP_RESPONSE_STATUS_CODE, HTTP_ROUTE,
+};
+use opentelemetry_semantic_conventions::metric::{
+    GEN_AI_CLIENT_TOKEN_USAGE, HTTP_SERVER_REQUEST_DURATION,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::aws::BEDROCK_SYSTEM;
+use crate::otel::SVC_NAME;
+
+struct Instruments {
+    // Also counts the requests.
+    request_duration: Histogram<f64>,
+    token_usage: Histogram<u64>,
+}
+
+// Created on first use, which has to come after the meter provider is set.
+static INSTRUMENTS: LazyLock<Instruments> = LazyLock::new(|| {
+    let meter = global::meter(SVC_NAME);
+    Instruments {
+        request_duration: meter
+            .f64_histogram(HTTP_SERVER_REQUEST_DURATION)
+            .with_unit("s")
+            .with_description("Duration of HTTP server requests.")
+            .build(),
+        token_usage: meter
+            .u64_histogram(GEN_AI_CLIENT_TOKEN_USAGE)
+            .with_unit("{token}")
+            .with_description("Number of input and output tokens used per model call.")
+            .build(),
+    }
+});
+
+/// Records a handled HTTP request. `route` is the matched route, if any,
+/// so that paths with IDs in them don't each become their own series.
+pub fn record_request(method: &str, route: Option<&str>, status: u16, duration: Duration) {
+    let mut attributes = vec![
+        KeyValue::new(HTTP_REQUEST_METHOD, method.to_string()),
+        KeyValue::new(HTTP_RESPONSE_STATUS_CODE, i64::from(status)),
+    ];
+    if let Some(route) = route {
+        attributes.push(KeyValue::new(HTTP_ROUTE, route.to_string()));
+    }
+    INSTRUMENTS
+        .request_duration
+        .record(duration.as_secs_f64(), &attributes);
+}
+
+/// Records the tokens a Bedrock call used.
+pub fn record_token_usage(model: &str, input_tokens: u64, output_tokens: u64) {
+    let attributes = |token_type: &'static str| {
+        [
+            KeyValue::new(GEN_AI_OPERATION_NAME, "chat"),
+            KeyValue::new(GEN_AI_SYSTEM, BEDROCK_SYSTEM),
+            KeyValue::new(GEN_AI_REQUEST_MODEL, model.to_string()),
+            KeyValue::new(GEN_AI_TOKEN_TYPE, token_type),
+        ]
+    };
+    INSTRUMENTS
+        .token_usage
+        .record(input_tokens, &attributes("input"));
+    INSTRUMENTS
+        .token_usage
+        .record(output_tokens, &attributes("output"));
+}
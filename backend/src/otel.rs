@@ -1,48 +1,263 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use opentelemetry::trace::{
+    SpanContext, TraceContextExt, TraceFlags, TraceState, TracerProvider as _,
+};
 use opentelemetry::{global, KeyValue};
-use opentelemetry_otlp::{WithExportConfig, WithTonicConfig};
+use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
+use opentelemetry_otlp::{Protocol, WithExportConfig, WithHttpConfig, WithTonicConfig};
+use opentelemetry_sdk::logs::LoggerProvider;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
 use opentelemetry_sdk::runtime::Tokio;
-use opentelemetry_sdk::trace::{RandomIdGenerator, Sampler};
+use opentelemetry_sdk::trace::{RandomIdGenerator, Sampler, TracerProvider};
 use opentelemetry_sdk::Resource;
 use opentelemetry_semantic_conventions::attribute::{SERVICE_NAME, SERVICE_VERSION};
+use std::collections::HashMap;
+use std::sync::OnceLock;
 use std::time::Duration;
-use tonic::metadata::MetadataMap;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tonic::metadata::{MetadataKey, MetadataMap};
+use tracing::{Event, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::layer::{Context as LayerContext, Filter};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{filter, layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
-const SVC_NAME: &str = "kamekai";
+use crate::log_format::{CorrelationLayer, JsonFormat, LogFormat};
 
-/// Initialize OpenTelemetry with Honeycomb OTLP exporter.
-pub fn init_tracer(
-    honeycomb_api_key: String,
-    otel_endpoint: String,
-    enable_ansi: bool,
-) -> Result<()> {
+pub(crate) const SVC_NAME: &str = "kamekai";
+
+// How often metrics are exported.
+const METRICS_INTERVAL: Duration = Duration::from_secs(30);
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Where traces, metrics and logs are sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Exporter {
+    /// An OpenTelemetry collector, or any vendor accepting OTLP.
+    Otlp,
+    /// Printed to stdout, for debugging.
+    Stdout,
+    /// Telemetry is only logged locally. Trace IDs are still generated and propagated.
+    None,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OtlpProtocol {
+    #[default]
+    Grpc,
+    #[value(name = "http/protobuf")]
+    HttpProtobuf,
+}
+
+/// The samplers of the `OTEL_TRACES_SAMPLER` spec.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SamplerKind {
+    #[value(name = "always_on")]
+    AlwaysOn,
+    #[value(name = "always_off")]
+    AlwaysOff,
+    /// Keeps a ratio of the traces, given by the sampler argument.
+    #[value(name = "traceidratio")]
+    TraceIdRatio,
+    /// Follows the caller's decision, or samples everything for new traces.
+    #[default]
+    #[value(name = "parentbased_always_on")]
+    ParentBasedAlwaysOn,
+    #[value(name = "parentbased_always_off")]
+    ParentBasedAlwaysOff,
+    #[value(name = "parentbased_traceidratio")]
+    ParentBasedTraceIdRatio,
+}
+
+impl SamplerKind {
+    /// Builds the sampler, `ratio` being the share of traces to keep for the ratio samplers.
+    pub fn sampler(self, ratio: f64) -> Result<Sampler> {
+        if !(0.0..=1.0).contains(&ratio) {
+            bail!("The sampling ratio must be between 0 and 1, got {}", ratio);
+        }
+        let parent_based = |root| Sampler::ParentBased(Box::new(root));
+        Ok(match self {
+            Self::AlwaysOn => Sampler::AlwaysOn,
+            Self::AlwaysOff => Sampler::AlwaysOff,
+            Self::TraceIdRatio => Sampler::TraceIdRatioBased(ratio),
+            Self::ParentBasedAlwaysOn => parent_based(Sampler::AlwaysOn),
+            Self::ParentBasedAlwaysOff => parent_based(Sampler::AlwaysOff),
+            Self::ParentBasedTraceIdRatio => parent_based(Sampler::TraceIdRatioBased(ratio)),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OtlpConfig {
+    pub protocol: OtlpProtocol,
+    /// Defaults to a collector on localhost.
+    pub endpoint: Option<String>,
+    /// Sent with every export, e.g., an API key.
+    pub headers: Vec<(String, String)>,
+}
+
+impl OtlpConfig {
+    fn endpoint(&self) -> String {
+        self.endpoint.clone().unwrap_or_else(|| {
+            match self.protocol {
+                OtlpProtocol::Grpc => "http://localhost:4317",
+                OtlpProtocol::HttpProtobuf => "http://localhost:4318",
+            }
+            .to_string()
+        })
+    }
+
+    // Unlike gRPC, OTLP over HTTP has a path per signal.
+    fn http_endpoint(&self, path: &str) -> String {
+        format!("{}{}", self.endpoint().trim_end_matches('/'), path)
+    }
+
+    fn metadata(&self) -> Result<MetadataMap> {
+        let mut map = MetadataMap::new();
+        for (key, value) in &self.headers {
+            let key = MetadataKey::from_bytes(key.to_lowercase().as_bytes())
+                .with_context(|| format!("Invalid exporter header name {}", key))?;
+            let value = value
+                .parse()
+                .with_context(|| format!("Invalid value for the exporter header {}", key))?;
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+
+    fn http_headers(&self) -> HashMap<String, String> {
+        self.headers.iter().cloned().collect()
+    }
+}
+
+/// Parses `key=value` exporter headers, as in `OTEL_EXPORTER_OTLP_HEADERS`.
+pub fn parse_header(header: &str) -> Result<(String, String)> {
+    let Some((key, value)) = header.split_once('=') else {
+        bail!("Exporter headers must look like key=value, got {}", header);
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("Exporter header without a name: {}", header);
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+#[derive(Clone, Debug)]
+pub struct TelemetryConfig {
+    pub exporter: Exporter,
+    pub otlp: OtlpConfig,
+    pub sampler: Sampler,
+    pub enable_ansi: bool,
+    pub log_format: LogFormat,
+}
+
+// Builds an OTLP exporter for one signal, over whichever protocol is configured.
+macro_rules! otlp_exporter {
+    ($exporter:ty, $path:literal, $config:expr) => {{
+        let config: &OtlpConfig = $config;
+        let builder = <$exporter>::builder();
+        match config.protocol {
+            OtlpProtocol::Grpc => builder
+                .with_tonic()
+                .with_endpoint(config.endpoint())
+                .with_timeout(EXPORT_TIMEOUT)
+                .with_metadata(config.metadata()?)
+                .build()?,
+            OtlpProtocol::HttpProtobuf => builder
+                .with_http()
+                .with_protocol(Protocol::HttpBinary)
+                .with_endpoint(config.http_endpoint($path))
+                .with_timeout(EXPORT_TIMEOUT)
+                .with_headers(config.http_headers())
+                .build()?,
+        }
+    }};
+}
+
+// The providers that need flushing on shutdown, other than the tracer provider which the
+// global API already takes care of.
+struct Providers {
+    meter: SdkMeterProvider,
+    logger: LoggerProvider,
+}
+
+static PROVIDERS: OnceLock<Providers> = OnceLock::new();
+
+/// Initialize logging and, unless the exporter is `none`, the export of OpenTelemetry traces,
+/// metrics and logs.
+pub fn init_telemetry(config: TelemetryConfig) -> Result<()> {
     // Set propagator.
     global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
 
-    // Create resource attributes.
+    // Create resource attributes, shared by all three signals.
     let resource = Resource::new(vec![
         KeyValue::new(SERVICE_NAME, SVC_NAME),
         KeyValue::new(SERVICE_VERSION, env!("CARGO_PKG_VERSION")),
     ]);
 
-    // Create OTLP exporter for Honeycomb.
-    let exporter = opentelemetry_otlp::SpanExporter::builder()
-        .with_tonic() // Use tonic as the gRPC layer.
-        .with_endpoint(otel_endpoint)
-        .with_timeout(Duration::from_secs(3))
-        .with_metadata(create_honeycomb_headers(honeycomb_api_key)?)
-        .build()?;
-
-    // Create and set tracer provider with batch span processor.
-    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
-        .with_batch_exporter(exporter, Tokio)
-        .with_sampler(Sampler::AlwaysOn)
+    // A tracer provider is set up even without an exporter, so that requests still get
+    // trace IDs to correlate their logs with.
+    let tracer_provider = TracerProvider::builder()
+        .with_sampler(config.sampler.clone())
         .with_id_generator(RandomIdGenerator::default())
-        .with_resource(resource)
-        .build();
+        .with_resource(resource.clone());
+    let tracer_provider = match config.exporter {
+        Exporter::Otlp => tracer_provider.with_batch_exporter(
+            otlp_exporter!(opentelemetry_otlp::SpanExporter, "/v1/traces", &config.otlp),
+            Tokio,
+        ),
+        Exporter::Stdout => tracer_provider
+            .with_batch_exporter(opentelemetry_stdout::SpanExporter::default(), Tokio),
+        Exporter::None => tracer_provider,
+    }
+    .build();
+    let tracer = tracer_provider.tracer(SVC_NAME);
+    global::set_tracer_provider(tracer_provider);
 
-    global::set_tracer_provider(provider);
+    // Metrics are collected in memory and exported periodically.
+    let meter_provider = match config.exporter {
+        Exporter::Otlp => Some(
+            PeriodicReader::builder(
+                otlp_exporter!(
+                    opentelemetry_otlp::MetricExporter,
+                    "/v1/metrics",
+                    &config.otlp
+                ),
+                Tokio,
+            )
+            .with_interval(METRICS_INTERVAL)
+            .build(),
+        ),
+        Exporter::Stdout => Some(
+            PeriodicReader::builder(opentelemetry_stdout::MetricExporter::default(), Tokio)
+                .with_interval(METRICS_INTERVAL)
+                .build(),
+        ),
+        Exporter::None => None,
+    }
+    .map(|reader| {
+        SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource.clone())
+            .build()
+    });
+    if let Some(meter_provider) = &meter_provider {
+        global::set_meter_provider(meter_provider.clone());
+    }
+
+    let logger_provider = match config.exporter {
+        Exporter::Otlp => Some(LoggerProvider::builder().with_batch_exporter(
+            otlp_exporter!(opentelemetry_otlp::LogExporter, "/v1/logs", &config.otlp),
+            Tokio,
+        )),
+        Exporter::Stdout => Some(
+            LoggerProvider::builder()
+                .with_batch_exporter(opentelemetry_stdout::LogExporter::default(), Tokio),
+        ),
+        Exporter::None => None,
+    }
+    .map(|builder| builder.with_resource(resource).build());
 
     // Set up the tracing subscriber with both fmt and opentelemetry layers
     let env_filter = tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -55,31 +270,245 @@ pub fn init_tracer(
 
     tracing_subscriber::registry()
         .with(env_filter)
-        .with(
+        .with((config.log_format == LogFormat::Text).then(|| {
             tracing_subscriber::fmt::layer()
                 .with_target(true)
                 .with_line_number(true)
-                .with_ansi(enable_ansi)
+                .with_ansi(config.enable_ansi)
                 .with_file(true)
                 .with_thread_ids(true)
-                .with_thread_names(true),
-        )
-        .with(tracing_opentelemetry::layer())
+                .with_thread_names(true)
+        }))
+        .with((config.log_format == LogFormat::Json).then(|| {
+            CorrelationLayer.and_then(tracing_subscriber::fmt::layer().event_format(JsonFormat))
+        }))
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .with(logger_provider.as_ref().map(|logger_provider| {
+            CorrelatedLogs(OpenTelemetryTracingBridge::new(logger_provider))
+                .with_filter(exported_logs())
+        }))
         .init();
 
+    if let (Some(meter), Some(logger)) = (meter_provider, logger_provider) {
+        let _ = PROVIDERS.set(Providers { meter, logger });
+    }
     Ok(())
 }
 
-fn create_honeycomb_headers(api_key: String) -> Result<MetadataMap> {
-    let mut map = MetadataMap::new();
-    map.insert(
-        "x-honeycomb-team",
-        api_key.parse().context("Invalid API key format")?,
-    );
-    Ok(map)
+// Exporting the logs of the exporters themselves would feed back into the exporters.
+fn exported_logs<S>() -> impl Filter<S> {
+    filter::filter_fn(|metadata| {
+        ![
+            "opentelemetry",
+            "tonic",
+            "h2",
+            "hyper",
+            "tower::buffer",
+            "reqwest",
+        ]
+        .iter()
+        .any(|target| metadata.target().starts_with(target))
+    })
+}
+
+/// Makes the span an event happens in the current OpenTelemetry context while the event
+/// is exported, so that the log record carries its trace and span IDs.
+///
+/// `tracing` spans only become OpenTelemetry spans when they close, and the log bridge
+/// only looks at the OpenTelemetry context.
+struct CorrelatedLogs<L>(L);
+
+impl<S, L> Layer<S> for CorrelatedLogs<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: LayerContext<'_, S>) {
+        let span = match event.parent() {
+            Some(id) => ctx.span(id),
+            None if event.is_contextual() => ctx.lookup_current(),
+            None => None,
+        };
+        let otel_context = span.and_then(|span| {
+            let extensions = span.extensions();
+            let data = extensions.get::<OtelData>()?;
+            let span_context = span_context(data)?;
+            Some(
+                data.parent_cx
+                    .with_remote_span_context(span_context)
+                    .attach(),
+            )
+        });
+
+        self.0.on_event(event, ctx);
+        drop(otel_context);
+    }
+}
+
+/// The IDs the OpenTelemetry span of a `tracing` span will have, before it is closed.
+pub(crate) fn span_context(data: &OtelData) -> Option<SpanContext> {
+    let parent = data.parent_cx.span().span_context().clone();
+    let trace_id = if parent.is_valid() {
+        parent.trace_id()
+    } else {
+        data.builder.trace_id?
+    };
+    Some(SpanContext::new(
+        trace_id,
+        data.builder.span_id?,
+        TraceFlags::SAMPLED,
+        false,
+        TraceState::default(),
+    ))
 }
 
-// Shutdown telemetry provider.
+// Shutdown telemetry providers, flushing whatever they haven't exported yet.
 pub fn shutdown_telemetry() {
     global::shutdown_tracer_provider();
+    if let Some(providers) = PROVIDERS.get() {
+        if let Err(e) = providers.meter.shutdown() {
+            eprintln!("Error shutting down the meter provider: {}", e);
+        }
+        if let Err(e) = providers.logger.shutdown() {
+            eprintln!("Error shutting down the logger provider: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanId, TraceId};
+    use opentelemetry::InstrumentationScope;
+    use opentelemetry_sdk::logs::{LogProcessor, LogRecord, LogResult};
+    use std::sync::{Arc, Mutex};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    type Ids = (TraceId, SpanId);
+
+    // Keeps the trace context of every record.
+    #[derive(Clone, Debug, Default)]
+    struct Captured(Arc<Mutex<Vec<Option<Ids>>>>);
+
+    impl LogProcessor for Captured {
+        fn emit(&self, record: &mut LogRecord, _: &InstrumentationScope) {
+            let context = record
+                .trace_context
+                .as_ref()
+                .map(|cx| (cx.trace_id, cx.span_id));
+            self.0.lock().unwrap().push(context);
+        }
+
+        fn force_flush(&self) -> LogResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&self) -> LogResult<()> {
+            Ok(())
+        }
+    }
+
+    fn with_subscriber(f: impl FnOnce()) -> Vec<Option<Ids>> {
+        let captured = Captured::default();
+        let logger_provider = LoggerProvider::builder()
+            .with_log_processor(captured.clone())
+            .build();
+        let tracer = TracerProvider::builder().build().tracer("test");
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .with(
+                CorrelatedLogs(OpenTelemetryTracingBridge::new(&logger_provider))
+                    .with_filter(exported_logs()),
+            );
+        tracing::subscriber::with_default(subscriber, f);
+        let records = captured.0.lock().unwrap().clone();
+        records
+    }
+
+    fn ids(span: &tracing::Span) -> Ids {
+        let context = span.context();
+        let span_context = context.span().span_context().clone();
+        (span_context.trace_id(), span_context.span_id())
+    }
+
+    #[test]
+    fn logs_carry_the_ids_of_the_span_they_happen_in() {
+        let mut expected = Vec::new();
+        let records = with_subscriber(|| {
+            tracing::info!("outside of any span");
+
+            let request = tracing::info_span!("http_request");
+            let _entered = request.enter();
+            tracing::info!("in the request");
+            expected.push(ids(&request));
+
+            let child = tracing::info_span!("translate");
+            tracing::info!(parent: &child, "explicitly in the child");
+            expected.push(ids(&child));
+        });
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0], None);
+        assert_eq!(records[1], Some(expected[0]));
+        assert_eq!(records[2], Some(expected[1]));
+        // The child is part of the same trace.
+        assert_eq!(expected[0].0, expected[1].0);
+        assert_ne!(expected[0].1, expected[1].1);
+    }
+
+    #[test]
+    fn does_not_export_the_logs_of_the_exporters() {
+        let records = with_subscriber(|| {
+            tracing::info!(target: "h2::codec", "frame sent");
+            tracing::info!(target: "opentelemetry_sdk", "batch exported");
+            tracing::info!("kept");
+        });
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn parses_exporter_headers() {
+        assert_eq!(
+            parse_header("x-honeycomb-team = abc=def").unwrap(),
+            ("x-honeycomb-team".to_string(), "abc=def".to_string())
+        );
+        assert!(parse_header("no-value").is_err());
+        assert!(parse_header("=value").is_err());
+    }
+
+    #[test]
+    fn resolves_otlp_endpoints() {
+        let grpc = OtlpConfig::default();
+        assert_eq!(grpc.endpoint(), "http://localhost:4317");
+
+        let http = OtlpConfig {
+            protocol: OtlpProtocol::HttpProtobuf,
+            endpoint: Some("https://api.honeycomb.io/".to_string()),
+            headers: vec![("X-Honeycomb-Team".to_string(), "key".to_string())],
+        };
+        assert_eq!(
+            http.http_endpoint("/v1/traces"),
+            "https://api.honeycomb.io/v1/traces"
+        );
+        assert_eq!(
+            http.metadata().unwrap().get("x-honeycomb-team").unwrap(),
+            "key"
+        );
+    }
+
+    #[test]
+    fn builds_samplers() {
+        assert_eq!(
+            format!(
+                "{:?}",
+                SamplerKind::ParentBasedTraceIdRatio.sampler(0.25).unwrap()
+            ),
+            "ParentBased(TraceIdRatioBased(0.25))"
+        );
+        assert!(matches!(
+            SamplerKind::AlwaysOff.sampler(1.0).unwrap(),
+            Sampler::AlwaysOff
+        ));
+        assert!(SamplerKind::TraceIdRatio.sampler(1.5).is_err());
+    }
 }
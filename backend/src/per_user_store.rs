@@ -0,0 +1,289 @@
+//! Items kept per user, e.g., lessons or quizzes, as JSON in the SQLite database.
+//!
+//! Each save or update only writes the item it is about, on Tokio's blocking threads.
+
+use anyhow::Result;
+use rusqlite::{params, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::server::SqliteStorage;
+
+/// What a [`PerUserStore`] keeps.
+pub trait Item: Clone + Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Tells the items of different stores apart in the database. Never change it once shipped.
+    const KIND: &'static str;
+
+    /// Older items are dropped past this, per user.
+    const MAX_PER_USER: usize;
+
+    fn id(&self) -> Uuid;
+}
+
+#[derive(Error, Debug)]
+pub enum UpdateError<E> {
+    #[error("not found")]
+    NotFound,
+
+    /// The update didn't apply to the item as it is now.
+    #[error("{0}")]
+    Rejected(E),
+
+    #[error(transparent)]
+    Storage(#[from] anyhow::Error),
+}
+
+/// Every user's items, oldest first.
+#[derive(Debug)]
+pub struct PerUserStore<T> {
+    storage: Arc<SqliteStorage>,
+    items: PhantomData<fn() -> T>,
+}
+
+impl<T: Item> PerUserStore<T> {
+    /// The items kept in `storage`'s database.
+    pub fn new(storage: Arc<SqliteStorage>) -> Self {
+        Self {
+            storage,
+            items: PhantomData,
+        }
+    }
+
+    /// A store that is forgotten once dropped.
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Self {
+        Self::new(Arc::new(
+            SqliteStorage::in_memory().expect("in-memory databases open"),
+        ))
+    }
+
+    pub async fn get(&self, sub: &str, id: Uuid) -> Result<Option<T>> {
+        let sub = sub.to_string();
+        self.storage
+            .run(move |connection| {
+                let item: Option<String> = connection
+                    .query_row(
+                        "SELECT item FROM items WHERE kind = ?1 AND sub = ?2 AND id = ?3",
+                        params![T::KIND, sub, id.to_string()],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                Ok(item.map(|item| serde_json::from_str(&item)).transpose()?)
+            })
+            .await
+    }
+
+    /// Runs `f` on the user's items, oldest first.
+    pub async fn read<R>(&self, sub: &str, f: impl FnOnce(&[T]) -> R) -> Result<R> {
+        let sub = sub.to_string();
+        let items: Vec<T> = self
+            .storage
+            .run(move |connection| {
+                let mut statement = connection
+                    .prepare("SELECT item FROM items WHERE kind = ?1 AND sub = ?2 ORDER BY seq")?;
+                let items = statement
+                    .query_map(params![T::KIND, sub], |row| row.get::<_, String>(0))?
+                    .map(|item| Ok(serde_json::from_str(&item?)?))
+                    .collect::<Result<_>>()?;
+                Ok(items)
+            })
+            .await?;
+        Ok(f(&items))
+    }
+
+    /// Adds `item`, or replaces the one with the same id where it is.
+    pub async fn save(&self, sub: &str, item: &T) -> Result<()> {
+        let (sub, id, item) = (sub.to_string(), item.id(), serde_json::to_string(item)?);
+        self.storage
+            .run(move |connection| {
+                let transaction = connection.transaction()?;
+                transaction.execute(
+                    "INSERT INTO items (kind, sub, id, item) VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT (kind, sub, id) DO UPDATE SET item = excluded.item",
+                    params![T::KIND, sub, id.to_string(), item],
+                )?;
+                transaction.execute(
+                    "DELETE FROM items WHERE kind = ?1 AND sub = ?2 AND seq NOT IN (
+                        SELECT seq FROM items WHERE kind = ?1 AND sub = ?2
+                        ORDER BY seq DESC LIMIT ?3
+                    )",
+                    params![T::KIND, sub, T::MAX_PER_USER],
+                )?;
+                transaction.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Applies `f` to the item as it is in the store, and saves it unless `f` fails.
+    ///
+    /// Nothing else writes to the database between reading the item and saving it, so
+    /// concurrent updates of an item are applied one after the other.
+    pub async fn update<R, E>(
+        &self,
+        sub: &str,
+        id: Uuid,
+        f: impl FnOnce(&mut T) -> Result<R, E> + Send + 'static,
+    ) -> Result<R, UpdateError<E>>
+    where
+        R: Send + 'static,
+        E: Send + 'static,
+    {
+        let sub = sub.to_string();
+        self.storage
+            .run(move |connection| {
+                let transaction = connection.transaction()?;
+                let item: Option<String> = transaction
+                    .query_row(
+                        "SELECT item FROM items WHERE kind = ?1 AND sub = ?2 AND id = ?3",
+                        params![T::KIND, sub, id.to_string()],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                let Some(item) = item else {
+                    return Ok(Err(UpdateError::NotFound));
+                };
+                let mut item: T = serde_json::from_str(&item)?;
+                let result = match f(&mut item) {
+                    Ok(result) => result,
+                    Err(e) => return Ok(Err(UpdateError::Rejected(e))),
+                };
+                transaction.execute(
+                    "UPDATE items SET item = ?4 WHERE kind = ?1 AND sub = ?2 AND id = ?3",
+                    params![T::KIND, sub, id.to_string(), serde_json::to_string(&item)?],
+                )?;
+                transaction.commit()?;
+                Ok(Ok(result))
+            })
+            .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        id: Uuid,
+        text: String,
+    }
+
+    impl Note {
+        fn new(text: &str) -> Self {
+            Self {
+                id: Uuid::new_v4(),
+                text: text.to_string(),
+            }
+        }
+    }
+
+    impl Item for Note {
+        const KIND: &'static str = "note";
+        const MAX_PER_USER: usize = 3;
+
+        fn id(&self) -> Uuid {
+            self.id
+        }
+    }
+
+    #[tokio::test]
+    async fn survives_restarts() {
+        let path = std::env::temp_dir().join(format!("kamekai-store-{}.db", Uuid::new_v4()));
+        let store = PerUserStore::new(Arc::new(SqliteStorage::open(&path).unwrap()));
+        let alice = Note::new("alice's");
+        let bob = Note::new("bob's");
+        store.save("alice", &alice).await.unwrap();
+        store.save("bob", &bob).await.unwrap();
+        store
+            .update("alice", alice.id, |note| {
+                note.text = "edited".to_string();
+                Ok::<_, ()>(())
+            })
+            .await
+            .unwrap();
+        drop(store);
+
+        let reopened = PerUserStore::<Note>::new(Arc::new(SqliteStorage::open(&path).unwrap()));
+        let alice_note = reopened.get("alice", alice.id).await.unwrap();
+        let bob_note = reopened.get("bob", bob.id).await.unwrap();
+        let bob_alice_note = reopened.get("bob", alice.id).await.unwrap();
+        drop(reopened);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(alice_note.unwrap().text, "edited");
+        assert_eq!(bob_note, Some(bob));
+        assert!(bob_alice_note.is_none());
+    }
+
+    #[tokio::test]
+    async fn drops_the_oldest_items() {
+        let store = PerUserStore::in_memory();
+        let notes: Vec<_> = (0..Note::MAX_PER_USER + 1)
+            .map(|i| Note::new(&i.to_string()))
+            .collect();
+        for note in &notes {
+            store.save("alice", note).await.unwrap();
+        }
+        // Replacing one doesn't drop any, nor move it.
+        store.save("alice", &notes[1]).await.unwrap();
+        store.save("bob", &notes[0]).await.unwrap();
+
+        let items = store.read("alice", <[Note]>::to_vec).await.unwrap();
+        assert_eq!(items, notes[1..]);
+        assert!(store.get("alice", notes[0].id).await.unwrap().is_none());
+        assert!(store.get("bob", notes[0].id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn rejected_updates_leave_items_untouched() {
+        let store = PerUserStore::in_memory();
+        let note = Note::new("draft");
+        store.save("alice", &note).await.unwrap();
+
+        let result = store
+            .update("alice", note.id, |note| {
+                note.text = "edited".to_string();
+                Err::<(), _>("not now")
+            })
+            .await;
+        assert!(matches!(result, Err(UpdateError::Rejected("not now"))));
+        assert_eq!(
+            store.get("alice", note.id).await.unwrap(),
+            Some(note.clone())
+        );
+
+        let result = store.update("bob", note.id, |_| Ok::<_, ()>(())).await;
+        assert!(matches!(result, Err(UpdateError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates_are_all_applied() {
+        let store = Arc::new(PerUserStore::in_memory());
+        let note = Note::new("");
+        store.save("alice", &note).await.unwrap();
+        let id = note.id;
+
+        let updates: Vec<_> = (0..10)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store
+                        .update("alice", id, move |note| {
+                            note.text.push_str(&i.to_string());
+                            Ok::<_, ()>(())
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for update in updates {
+            update.await.unwrap().unwrap();
+        }
+        let note: Note = store.get("alice", id).await.unwrap().unwrap();
+        assert_eq!(note.text.len(), 10);
+    }
+}
@@ -0,0 +1,401 @@
+//! Translation practice: the user gets an English sentence, translates it, and gets scored on
+//! their attempts.
+//!
+//! Like quiz answers, the reference translations stay on the server until the first attempt.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::aws::{self, bedrock::parse_model_json};
+use crate::lesson::{Formality, LessonParams};
+use crate::quiz::normalize;
+use crate::ConversationBuilder;
+
+mod store;
+
+pub use store::PracticeStore;
+
+pub const MAX_TRANSLATION_LEN: usize = 500;
+pub const MAX_ATTEMPTS: usize = 20;
+// How many of the user's latest sentences the model is told not to repeat.
+const RECENT_SOURCES: usize = 20;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PracticeError {
+    #[error("exercises take up to {} attempts", MAX_ATTEMPTS)]
+    TooManyAttempts,
+}
+
+/// An English sentence to translate.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Exercise {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub params: LessonParams,
+    pub source: String,
+    /// Good translations of `source`, not the only ones.
+    pub references: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub attempts: Vec<Attempt>,
+}
+
+/// What users see of an exercise: the references only show up once they have tried.
+#[derive(Clone, Debug, Serialize)]
+pub struct ExerciseView {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub params: LessonParams,
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub references: Option<Vec<String>>,
+    pub created_at: DateTime<Utc>,
+    pub attempts: Vec<Attempt>,
+}
+
+/// Each out of 100.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scores {
+    /// Whether the translation says what the source says.
+    pub meaning: u8,
+    pub grammar: u8,
+    /// Whether a native speaker would say it that way.
+    pub naturalness: u8,
+}
+
+impl Scores {
+    /// Meaning counts the most, then grammar, then naturalness.
+    pub fn overall(&self) -> u8 {
+        let weighted = 5 * u32::from(self.meaning)
+            + 3 * u32::from(self.grammar)
+            + 2 * u32::from(self.naturalness);
+        ((weighted + 5) / 10) as u8
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attempt {
+    pub translation: String,
+    pub score: u8,
+    pub scores: Scores,
+    pub feedback: String,
+    /// Other acceptable ways of saying it, close to what the user wrote.
+    pub alternatives: Vec<String>,
+    pub submitted_at: DateTime<Utc>,
+}
+
+/// A graded attempt, along with the references to compare it with.
+#[derive(Clone, Debug, Serialize)]
+pub struct AttemptResult {
+    pub exercise_id: Uuid,
+    #[serde(flatten)]
+    pub attempt: Attempt,
+    pub references: Vec<String>,
+}
+
+impl Exercise {
+    pub fn view(&self) -> ExerciseView {
+        ExerciseView {
+            id: self.id,
+            params: self.params.clone(),
+            source: self.source.clone(),
+            references: (!self.attempts.is_empty()).then(|| self.references.clone()),
+            created_at: self.created_at,
+            attempts: self.attempts.clone(),
+        }
+    }
+
+    /// Whether the user can make another attempt.
+    pub fn check_attempt(&self) -> Result<(), PracticeError> {
+        if self.attempts.len() >= MAX_ATTEMPTS {
+            return Err(PracticeError::TooManyAttempts);
+        }
+        Ok(())
+    }
+
+    /// Adds a graded attempt, unless the exercise got all of its attempts since.
+    pub fn add_attempt(&mut self, attempt: Attempt) -> Result<AttemptResult, PracticeError> {
+        self.check_attempt()?;
+        self.attempts.push(attempt.clone());
+        Ok(AttemptResult {
+            exercise_id: self.id,
+            attempt,
+            references: self.references.clone(),
+        })
+    }
+
+    // Translations matching a reference need no grading.
+    fn matching_reference(&self, translation: &str) -> Option<&String> {
+        let translation = normalize(translation);
+        self.references
+            .iter()
+            .find(|reference| normalize(reference) == translation)
+    }
+}
+
+#[derive(Deserialize)]
+struct GeneratedExercise {
+    source: String,
+    references: Vec<String>,
+}
+
+/// Picks a sentence for the user to translate, other than the ones in `recent`.
+#[instrument(name = "generate_exercise", fields(exercise.language = %params.language), skip_all)]
+pub async fn generate_exercise(
+    params: LessonParams,
+    recent: &[String],
+    capture_content: bool,
+) -> Result<Exercise> {
+    let aws_client = aws::AWSClient::new(Some(aws::InferenceParameters {
+        temperature: 0.9,
+        max_tokens: 1024,
+        top_p: 0.95,
+    }))
+    .await
+    .context("Error creating AWS client")?
+    .with_content_capture(capture_content);
+
+    let conversation = ConversationBuilder::new()
+        .with_system_prompt(exercise_prompt(&params, recent))
+        .add_user_message(format!(
+            "Give me a sentence to translate into {}.",
+            params.language
+        ))
+        .build()
+        .context("Error creating messages for AWS Bedrock")?;
+    let output = aws_client
+        .create_conversation(conversation)
+        .await
+        .context("Error creating conversation with AWS Bedrock")?;
+    let generated: GeneratedExercise =
+        parse_model_json(&output).context("Error parsing the exercise")?;
+
+    let references: Vec<_> = generated
+        .references
+        .into_iter()
+        .map(|reference| reference.trim().to_string())
+        .filter(|reference| !reference.is_empty())
+        .collect();
+    if generated.source.trim().is_empty() || references.is_empty() {
+        bail!("The model gave an incomplete exercise");
+    }
+    Ok(Exercise {
+        id: Uuid::new_v4(),
+        params,
+        source: generated.source.trim().to_string(),
+        references,
+        created_at: Utc::now(),
+        attempts: Vec::new(),
+    })
+}
+
+fn exercise_prompt(params: &LessonParams, recent: &[String]) -> String {
+    let level = match params.level {
+        Some(level) => format!(
+            "The student is at the {} level: the translation should only need grammar and \
+            vocabulary suited to it.",
+            level
+        ),
+        None => "Pick a sentence of intermediate difficulty.".to_string(),
+    };
+    let theme = match &params.theme {
+        Some(theme) => format!("The sentence is about this theme: {}.", theme),
+        None => String::new(),
+    };
+    let formality = match params.formality {
+        Formality::Mixed => "The translations can be in any register that fits.".to_string(),
+        formality => format!("The translations should be in {} speech.", formality),
+    };
+    let recent = match recent {
+        [] => String::new(),
+        recent => format!(
+            "The student already translated these, pick something else: {}. ",
+            recent.join(" / ")
+        ),
+    };
+    format!(
+        "You are a {language} language teacher giving a student an English sentence to \
+        translate into {language}. {level} {theme} {formality} {recent}\
+        Give up to three good translations, the most common first. \
+        Respond only with JSON in the following format, without any other text:\n\
+        {{\"source\": \"...\", \"references\": [\"...\"]}}",
+        language = params.language,
+        level = level,
+        theme = theme,
+        formality = formality,
+        recent = recent,
+    )
+}
+
+#[derive(Deserialize)]
+struct ModelGrade {
+    meaning: f64,
+    grammar: f64,
+    naturalness: f64,
+    feedback: String,
+    #[serde(default)]
+    alternatives: Vec<String>,
+}
+
+fn score(value: f64) -> u8 {
+    value.round().clamp(0.0, 100.0) as u8
+}
+
+/// Grades the user's translation of the exercise, to be added with [`Exercise::add_attempt`].
+#[instrument(
+    name = "grade_attempt",
+    fields(exercise.id = %exercise.id, exercise.attempts = exercise.attempts.len()),
+    skip_all,
+)]
+pub async fn grade_attempt(
+    exercise: &Exercise,
+    translation: &str,
+    capture_content: bool,
+) -> Result<Attempt> {
+    let translation = translation.trim();
+    match exercise.matching_reference(translation) {
+        Some(_) => {
+            let scores = Scores {
+                meaning: 100,
+                grammar: 100,
+                naturalness: 100,
+            };
+            Ok(Attempt {
+                translation: translation.to_string(),
+                score: scores.overall(),
+                scores,
+                feedback: "This matches one of the reference translations.".to_string(),
+                alternatives: Vec::new(),
+                submitted_at: Utc::now(),
+            })
+        }
+        None => grade_with_model(exercise, translation, capture_content).await,
+    }
+}
+
+async fn grade_with_model(
+    exercise: &Exercise,
+    translation: &str,
+    capture_content: bool,
+) -> Result<Attempt> {
+    let aws_client = aws::AWSClient::new(Some(aws::InferenceParameters {
+        temperature: 0.0,
+        max_tokens: 1024,
+        top_p: 0.95,
+    }))
+    .await
+    .context("Error creating AWS client")?
+    .with_content_capture(capture_content);
+
+    let system_prompt = format!(
+        "You are a {language} language teacher grading a student's translation of an English \
+        sentence. The references are good translations, others can be just as good. \
+        Score, from 0 to 100, how well the translation keeps the meaning of the source, its \
+        grammar, and how natural it sounds to a native speaker. \
+        Give two or three sentences of feedback in {explanation_language}, and list other \
+        acceptable ways of saying it that stay close to what the student wrote. \
+        Respond only with JSON in the following format, without any other text:\n\
+        {{\"meaning\": 90, \"grammar\": 80, \"naturalness\": 70, \"feedback\": \"...\", \
+        \"alternatives\": [\"...\"]}}",
+        language = exercise.params.language,
+        explanation_language = exercise.params.explanation_language,
+    );
+    let item = serde_json::json!({
+        "source": exercise.source,
+        "references": exercise.references,
+        "translation": translation,
+    });
+    let conversation = ConversationBuilder::new()
+        .with_system_prompt(system_prompt)
+        .add_user_message(item.to_string())
+        .build()
+        .context("Error creating messages for AWS Bedrock")?;
+    let output = aws_client
+        .create_conversation(conversation)
+        .await
+        .context("Error creating conversation with AWS Bedrock")?;
+    let grade: ModelGrade = parse_model_json(&output).context("Error parsing the grade")?;
+
+    let scores = Scores {
+        meaning: score(grade.meaning),
+        grammar: score(grade.grammar),
+        naturalness: score(grade.naturalness),
+    };
+    Ok(Attempt {
+        translation: translation.to_string(),
+        score: scores.overall(),
+        scores,
+        feedback: grade.feedback,
+        alternatives: grade.alternatives,
+        submitted_at: Utc::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lesson::Level;
+    use crate::Language;
+
+    fn exercise() -> Exercise {
+        Exercise {
+            id: Uuid::new_v4(),
+            params: LessonParams::new(Language::Japanese),
+            source: "I'm a student.".to_string(),
+            references: vec!["私は学生です。".to_string(), "学生です。".to_string()],
+            created_at: Utc::now(),
+            attempts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn weighs_meaning_the_most() {
+        let scores = Scores {
+            meaning: 100,
+            grammar: 50,
+            naturalness: 0,
+        };
+        assert_eq!(scores.overall(), 65);
+        let scores = Scores {
+            meaning: 0,
+            grammar: 50,
+            naturalness: 100,
+        };
+        assert_eq!(scores.overall(), 35);
+    }
+
+    #[tokio::test]
+    async fn grades_matching_references_without_the_model() {
+        let mut exercise = exercise();
+        assert!(exercise.view().references.is_none());
+
+        let attempt = grade_attempt(&exercise, " 私は 学生です ", false)
+            .await
+            .unwrap();
+        let result = exercise.add_attempt(attempt).unwrap();
+        assert_eq!(result.attempt.score, 100);
+        assert_eq!(result.attempt.translation, "私は 学生です");
+        assert_eq!(result.references, exercise.references);
+        assert_eq!(exercise.attempts.len(), 1);
+        assert_eq!(
+            exercise.view().references,
+            Some(exercise.references.clone())
+        );
+    }
+
+    #[test]
+    fn prompts_reflect_the_params_and_skip_recent_sentences() {
+        let mut params = LessonParams::new(Language::Japanese);
+        params.level = Some(Level::N4);
+        params.theme = Some("travel".to_string());
+        let prompt = exercise_prompt(&params, &["I'm a student.".to_string()]);
+        assert!(prompt.contains("the JLPT N4 level"));
+        assert!(prompt.contains("about this theme: travel."));
+        assert!(prompt.contains("pick something else: I'm a student."));
+        assert!(!exercise_prompt(&params, &[]).contains("already translated"));
+    }
+}
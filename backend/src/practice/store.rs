@@ -0,0 +1,168 @@
+use anyhow::Result;
+use uuid::Uuid;
+
+use super::{Attempt, AttemptResult, Exercise, ExerciseView, PracticeError, RECENT_SOURCES};
+use crate::per_user_store::{Item, PerUserStore, UpdateError};
+use crate::Language;
+
+impl Item for Exercise {
+    const KIND: &'static str = "exercise";
+    const MAX_PER_USER: usize = 500;
+
+    fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Every user's translation exercises, along with their attempts.
+pub type PracticeStore = PerUserStore<Exercise>;
+
+impl PracticeStore {
+    /// Adds a graded attempt to the exercise, if it doesn't have all of its attempts by now.
+    pub async fn add_attempt(
+        &self,
+        sub: &str,
+        id: Uuid,
+        attempt: Attempt,
+    ) -> Result<AttemptResult, UpdateError<PracticeError>> {
+        self.update(sub, id, move |exercise| exercise.add_attempt(attempt))
+            .await
+    }
+
+    /// The user's exercises, newest first.
+    pub async fn list(
+        &self,
+        sub: &str,
+        language: Option<Language>,
+        limit: usize,
+    ) -> Result<Vec<ExerciseView>> {
+        self.read(sub, |exercises| {
+            exercises
+                .iter()
+                .rev()
+                .filter(|e| language.is_none_or(|language| e.params.language == language))
+                .take(limit)
+                .map(Exercise::view)
+                .collect()
+        })
+        .await
+    }
+
+    /// The latest sentences the user got in `language`, so as not to give them again.
+    pub async fn recent_sources(&self, sub: &str, language: Language) -> Result<Vec<String>> {
+        let mut sources: Vec<_> = self
+            .read(sub, |exercises| {
+                exercises
+                    .iter()
+                    .rev()
+                    .filter(|e| e.params.language == language)
+                    .take(RECENT_SOURCES)
+                    .map(|e| e.source.clone())
+                    .collect()
+            })
+            .await?;
+        sources.reverse();
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Scores, MAX_ATTEMPTS};
+    use super::*;
+    use crate::lesson::LessonParams;
+    use chrono::Utc;
+
+    fn exercise(language: Language, source: &str) -> Exercise {
+        Exercise {
+            id: Uuid::new_v4(),
+            params: LessonParams::new(language),
+            source: source.to_string(),
+            references: vec!["...".to_string()],
+            created_at: Utc::now(),
+            attempts: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_exercises_newest_first() {
+        let store = PracticeStore::in_memory();
+        let japanese = exercise(Language::Japanese, "I'm a student.");
+        store.save("alice", &japanese).await.unwrap();
+        store
+            .save(
+                "alice",
+                &exercise(Language::Chinese, "Where is the station?"),
+            )
+            .await
+            .unwrap();
+
+        let listed = store.list("alice", None, 10).await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].source, "Where is the station?");
+        let listed = store
+            .list("alice", Some(Language::Japanese), 10)
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, japanese.id);
+        assert!(store.list("bob", None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn recent_sources_are_per_language_and_oldest_first() {
+        let store = PracticeStore::in_memory();
+        for i in 0..RECENT_SOURCES + 2 {
+            store
+                .save("alice", &exercise(Language::Japanese, &i.to_string()))
+                .await
+                .unwrap();
+        }
+        store
+            .save("alice", &exercise(Language::Chinese, "chinese"))
+            .await
+            .unwrap();
+
+        let recent = store
+            .recent_sources("alice", Language::Japanese)
+            .await
+            .unwrap();
+        assert_eq!(recent.len(), RECENT_SOURCES);
+        assert_eq!(recent[0], "2");
+        assert_eq!(recent.last().unwrap(), &(RECENT_SOURCES + 1).to_string());
+    }
+
+    #[tokio::test]
+    async fn attempts_stop_at_the_limit() {
+        let store = PracticeStore::in_memory();
+        let exercise = exercise(Language::Japanese, "I'm a student.");
+        store.save("alice", &exercise).await.unwrap();
+        let attempt = Attempt {
+            translation: "学生です。".to_string(),
+            score: 100,
+            scores: Scores {
+                meaning: 100,
+                grammar: 100,
+                naturalness: 100,
+            },
+            feedback: String::new(),
+            alternatives: Vec::new(),
+            submitted_at: Utc::now(),
+        };
+
+        // The limit is checked again as the graded attempts are added.
+        for _ in 0..MAX_ATTEMPTS {
+            store
+                .add_attempt("alice", exercise.id, attempt.clone())
+                .await
+                .unwrap();
+        }
+        let result = store.add_attempt("alice", exercise.id, attempt).await;
+        assert!(matches!(
+            result,
+            Err(UpdateError::Rejected(PracticeError::TooManyAttempts))
+        ));
+        let stored = store.get("alice", exercise.id).await.unwrap().unwrap();
+        assert_eq!(stored.attempts.len(), MAX_ATTEMPTS);
+    }
+}
@@ -0,0 +1,313 @@
+//! Grading of quiz answers: closed questions locally, free-form translations by the model.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{Question, QuestionKind, Quiz};
+use crate::aws::{self, bedrock::parse_model_json};
+use crate::{ConversationBuilder, Language};
+
+/// An answer to a question: the index of a choice, some text, or reordered fragments.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Answer {
+    Choice(usize),
+    Text(String),
+    Fragments(Vec<String>),
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QuestionFeedback {
+    pub correct: bool,
+    pub expected: String,
+    pub explanation: String,
+    /// The model's comments on free-form answers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feedback: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QuizResult {
+    pub quiz_id: Uuid,
+    pub score: usize,
+    pub total: usize,
+    /// In the order of the questions.
+    pub questions: Vec<QuestionFeedback>,
+}
+
+/// Folds the differences that shouldn't make an answer wrong: case, full-width characters,
+/// spaces and punctuation.
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            // Full-width ASCII variants, e.g., "Ａ" or "？".
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+            _ => c,
+        })
+        .filter(|c| !c.is_whitespace() && !is_punctuation(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || "。、，．・！？：；「」『』（）【】〜～…‘’“”《》".contains(c)
+}
+
+// The verdict on an answer, unless it takes the model to tell.
+fn grade_locally(kind: &QuestionKind, answer: Option<&Answer>) -> Option<bool> {
+    let Some(answer) = answer else {
+        return Some(false);
+    };
+    let matches = |text: &str| normalize(text) == normalize(kind.expected());
+    match (kind, answer) {
+        (QuestionKind::MultipleChoice { answer: right, .. }, Answer::Choice(choice)) => {
+            Some(choice == right)
+        }
+        (QuestionKind::Reorder { .. }, Answer::Fragments(fragments)) => {
+            Some(matches(&fragments.concat()))
+        }
+        (QuestionKind::Translation { .. }, Answer::Text(text)) if text.trim().is_empty() => {
+            Some(false)
+        }
+        (QuestionKind::Translation { .. }, Answer::Text(text)) => matches(text).then_some(true),
+        (_, Answer::Text(text)) => Some(matches(text)),
+        _ => Some(false),
+    }
+}
+
+/// Grades `answers`, given in the order of the questions. Missing answers are wrong.
+///
+/// Translations that don't match the reference are sent to the model, all at once.
+#[instrument(
+    name = "grade_quiz",
+    fields(quiz.id = %quiz.id, quiz.questions = quiz.questions.len()),
+    skip_all,
+)]
+pub async fn grade_quiz(
+    quiz: &Quiz,
+    answers: &[Option<Answer>],
+    capture_content: bool,
+) -> Result<QuizResult> {
+    let mut questions: Vec<_> = quiz
+        .questions
+        .iter()
+        .enumerate()
+        .map(|(i, question)| {
+            let answer = answers.get(i).and_then(Option::as_ref);
+            (
+                feedback(question, grade_locally(&question.kind, answer)),
+                answer,
+            )
+        })
+        .collect();
+
+    let pending: Vec<_> = questions
+        .iter()
+        .enumerate()
+        .filter(|(_, (feedback, _))| feedback.is_none())
+        .map(|(i, _)| i)
+        .collect();
+    if !pending.is_empty() {
+        let items: Vec<_> = pending
+            .iter()
+            .map(|&i| {
+                let question = &quiz.questions[i];
+                let (QuestionKind::Translation { source, reference }, Some(Answer::Text(answer))) =
+                    (&question.kind, questions[i].1)
+                else {
+                    unreachable!("only translations are graded by the model");
+                };
+                json!({
+                    "prompt": question.prompt,
+                    "source": source,
+                    "reference": reference,
+                    "answer": answer,
+                })
+            })
+            .collect();
+        let grades = grade_translations(quiz.language, &items, capture_content).await?;
+        for (i, grade) in pending.into_iter().zip(grades) {
+            let mut feedback = self::feedback(&quiz.questions[i], Some(grade.correct))
+                .expect("graded questions have feedback");
+            feedback.feedback = Some(grade.feedback);
+            questions[i].0 = Some(feedback);
+        }
+    }
+
+    let questions: Vec<_> = questions
+        .into_iter()
+        .map(|(feedback, _)| feedback.expect("every question is graded"))
+        .collect();
+    Ok(QuizResult {
+        quiz_id: quiz.id,
+        score: questions.iter().filter(|q| q.correct).count(),
+        total: questions.len(),
+        questions,
+    })
+}
+
+fn feedback(question: &Question, correct: Option<bool>) -> Option<QuestionFeedback> {
+    Some(QuestionFeedback {
+        correct: correct?,
+        expected: question.kind.expected().to_string(),
+        explanation: question.explanation.clone(),
+        feedback: None,
+    })
+}
+
+#[derive(Deserialize)]
+struct TranslationGrade {
+    correct: bool,
+    feedback: String,
+}
+
+#[derive(Deserialize)]
+struct TranslationGrades {
+    grades: Vec<TranslationGrade>,
+}
+
+async fn grade_translations(
+    language: Language,
+    items: &[serde_json::Value],
+    capture_content: bool,
+) -> Result<Vec<TranslationGrade>> {
+    let aws_client = aws::AWSClient::new(Some(aws::InferenceParameters {
+        temperature: 0.0,
+        max_tokens: 2048,
+        top_p: 0.95,
+    }))
+    .await
+    .context("Error creating AWS client")?
+    .with_content_capture(capture_content);
+
+    let system_prompt = format!(
+        "You are a {} language teacher grading a student's translations. \
+        For each item, tell whether the answer is an acceptable translation of the source. \
+        The reference is one good translation, others can be just as good: only count meaning \
+        errors, grammar errors and wrong words as mistakes. \
+        Give one sentence of feedback per item, in the language of its prompt. \
+        Respond only with JSON, one grade per item and in the same order, without any other text:\n\
+        {{\"grades\": [{{\"correct\": true, \"feedback\": \"...\"}}]}}",
+        language
+    );
+    let conversation = ConversationBuilder::new()
+        .with_system_prompt(system_prompt)
+        .add_user_message(serde_json::to_string(items)?)
+        .build()
+        .context("Error creating messages for AWS Bedrock")?;
+
+    let output = aws_client
+        .create_conversation(conversation)
+        .await
+        .context("Error creating conversation with AWS Bedrock")?;
+    let graded: TranslationGrades =
+        parse_model_json(&output).context("Error parsing the grades")?;
+    if graded.grades.len() != items.len() {
+        bail!(
+            "Expected {} grades, the model gave {}",
+            items.len(),
+            graded.grades.len()
+        );
+    }
+    Ok(graded.grades)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds() -> [QuestionKind; 4] {
+        [
+            QuestionKind::MultipleChoice {
+                choices: vec!["水".to_string(), "火".to_string()],
+                answer: 0,
+            },
+            QuestionKind::Cloze {
+                sentence: "私___学生です。".to_string(),
+                answer: "は".to_string(),
+            },
+            QuestionKind::Reorder {
+                fragments: vec!["学生".to_string(), "私は".to_string(), "です".to_string()],
+                answer: "私は学生です。".to_string(),
+            },
+            QuestionKind::Translation {
+                source: "I'm a student.".to_string(),
+                reference: "私は学生です。".to_string(),
+            },
+        ]
+    }
+
+    fn text(text: &str) -> Answer {
+        Answer::Text(text.to_string())
+    }
+
+    #[test]
+    fn normalizes_width_case_spaces_and_punctuation() {
+        assert_eq!(normalize(" 私は　学生です。"), "私は学生です");
+        assert_eq!(normalize("ＡＢＣ？"), "abc");
+        assert_eq!(normalize("Wǒ shì xuéshēng!"), "wǒshìxuéshēng");
+    }
+
+    #[test]
+    fn grades_closed_questions_locally() {
+        let [choice, cloze, reorder, _] = kinds();
+        assert_eq!(grade_locally(&choice, Some(&Answer::Choice(0))), Some(true));
+        assert_eq!(
+            grade_locally(&choice, Some(&Answer::Choice(1))),
+            Some(false)
+        );
+        assert_eq!(grade_locally(&choice, Some(&text("水"))), Some(true));
+        assert_eq!(grade_locally(&cloze, Some(&text(" は "))), Some(true));
+        assert_eq!(grade_locally(&cloze, Some(&text("が"))), Some(false));
+        let fragments = Answer::Fragments(vec![
+            "私は".to_string(),
+            "学生".to_string(),
+            "です".to_string(),
+        ]);
+        assert_eq!(grade_locally(&reorder, Some(&fragments)), Some(true));
+        assert_eq!(
+            grade_locally(&reorder, Some(&text("学生私はです"))),
+            Some(false)
+        );
+        assert_eq!(grade_locally(&cloze, None), Some(false));
+        assert_eq!(grade_locally(&cloze, Some(&Answer::Choice(0))), Some(false));
+    }
+
+    #[test]
+    fn only_sends_unmatched_translations_to_the_model() {
+        let [.., translation] = kinds();
+        assert_eq!(
+            grade_locally(&translation, Some(&text("私は学生です"))),
+            Some(true)
+        );
+        assert_eq!(grade_locally(&translation, Some(&text(" "))), Some(false));
+        assert_eq!(grade_locally(&translation, Some(&text("学生です"))), None);
+    }
+
+    #[tokio::test]
+    async fn grades_closed_quizzes_without_the_model() {
+        let quiz = Quiz {
+            id: Uuid::new_v4(),
+            language: Language::Japanese,
+            created_at: chrono::Utc::now(),
+            questions: kinds()
+                .into_iter()
+                .map(|kind| Question {
+                    prompt: "...".to_string(),
+                    kind,
+                    explanation: "Because.".to_string(),
+                })
+                .collect(),
+        };
+        let answers: Vec<Option<Answer>> = serde_json::from_str(r#"[1, "は", null]"#).unwrap();
+        let result = grade_quiz(&quiz, &answers, false).await.unwrap();
+        assert_eq!((result.score, result.total), (1, 4));
+        let correct: Vec<_> = result.questions.iter().map(|q| q.correct).collect();
+        assert_eq!(correct, [false, true, false, false]);
+        assert_eq!(result.questions[0].expected, "水");
+        assert_eq!(result.questions[0].explanation, "Because.");
+    }
+}
@@ -0,0 +1,370 @@
+//! Exercises generated from a lesson or a translation, and their grading.
+//!
+//! The answers stay on the server: users get a [`QuizView`] and submit their answers for grading.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use tracing::{instrument, warn};
+use uuid::Uuid;
+
+use crate::aws::{self, bedrock::parse_model_json};
+use crate::{ConversationBuilder, Language};
+
+mod grading;
+mod store;
+
+pub use grading::{grade_quiz, normalize, Answer, QuestionFeedback, QuizResult};
+pub use store::QuizStore;
+
+/// The blank in cloze sentences.
+pub const BLANK: &str = "___";
+// Fragments only ever left in order are, e.g., all the same.
+const MAX_SHUFFLES: usize = 10;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Quiz {
+    pub id: Uuid,
+    pub language: Language,
+    pub created_at: DateTime<Utc>,
+    pub questions: Vec<Question>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Question {
+    /// What the user is asked to do, e.g., "Pick the right particle".
+    pub prompt: String,
+    #[serde(flatten)]
+    pub kind: QuestionKind,
+    /// Why the answer is the right one, shown once the question is graded.
+    #[serde(default)]
+    pub explanation: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QuestionKind {
+    MultipleChoice {
+        choices: Vec<String>,
+        /// Index of the right choice.
+        answer: usize,
+    },
+    /// Fill in the blank, e.g., a particle or a measure word.
+    Cloze { sentence: String, answer: String },
+    /// Put the fragments back in order.
+    Reorder {
+        fragments: Vec<String>,
+        answer: String,
+    },
+    /// Free-form, graded by the model unless it matches the reference.
+    Translation { source: String, reference: String },
+}
+
+impl QuestionKind {
+    /// The right answer, as shown to the user.
+    pub fn expected(&self) -> &str {
+        match self {
+            Self::MultipleChoice { choices, answer } => &choices[*answer],
+            Self::Cloze { answer, .. } | Self::Reorder { answer, .. } => answer,
+            Self::Translation { reference, .. } => reference,
+        }
+    }
+
+    // Models are asked to shuffle the fragments, but often leave them in the answer's order.
+    fn shuffle(&mut self, rng: &mut impl Rng) {
+        if let Self::Reorder { fragments, answer } = self {
+            let answer = normalize(answer);
+            for _ in 0..MAX_SHUFFLES {
+                fragments.shuffle(rng);
+                if normalize(&fragments.concat()) != answer {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Models occasionally get their own questions wrong, those are dropped.
+    fn check(&self) -> Result<()> {
+        match self {
+            Self::MultipleChoice { choices, answer } => {
+                if choices.len() < 2 || *answer >= choices.len() {
+                    bail!("answer {} out of {} choices", answer, choices.len());
+                }
+            }
+            Self::Cloze { sentence, answer } => {
+                if !sentence.contains(BLANK) || answer.trim().is_empty() {
+                    bail!("cloze without a blank or an answer");
+                }
+            }
+            Self::Reorder { fragments, answer } => {
+                // The fragments are shuffled, so only their characters can be compared.
+                let mut given: Vec<_> = normalize(&fragments.concat()).chars().collect();
+                let mut expected: Vec<_> = normalize(answer).chars().collect();
+                given.sort_unstable();
+                expected.sort_unstable();
+                if fragments.len() < 2 || given != expected {
+                    bail!("fragments don't make up the answer");
+                }
+            }
+            Self::Translation { source, reference } => {
+                if source.trim().is_empty() || reference.trim().is_empty() {
+                    bail!("translation without a source or a reference");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A quiz as shown to the user, without the answers.
+#[derive(Clone, Debug, Serialize)]
+pub struct QuizView {
+    pub id: Uuid,
+    pub language: Language,
+    pub created_at: DateTime<Utc>,
+    pub questions: Vec<QuestionView>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QuestionView {
+    pub prompt: String,
+    #[serde(flatten)]
+    pub kind: QuestionViewKind,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QuestionViewKind {
+    MultipleChoice { choices: Vec<String> },
+    Cloze { sentence: String },
+    Reorder { fragments: Vec<String> },
+    Translation { source: String },
+}
+
+impl Quiz {
+    pub fn view(&self) -> QuizView {
+        let questions = self
+            .questions
+            .iter()
+            .map(|question| QuestionView {
+                prompt: question.prompt.clone(),
+                kind: match &question.kind {
+                    QuestionKind::MultipleChoice { choices, .. } => {
+                        QuestionViewKind::MultipleChoice {
+                            choices: choices.clone(),
+                        }
+                    }
+                    QuestionKind::Cloze { sentence, .. } => QuestionViewKind::Cloze {
+                        sentence: sentence.clone(),
+                    },
+                    QuestionKind::Reorder { fragments, .. } => QuestionViewKind::Reorder {
+                        fragments: fragments.clone(),
+                    },
+                    QuestionKind::Translation { source, .. } => QuestionViewKind::Translation {
+                        source: source.clone(),
+                    },
+                },
+            })
+            .collect();
+        QuizView {
+            id: self.id,
+            language: self.language,
+            created_at: self.created_at,
+            questions,
+        }
+    }
+}
+
+/// The most questions a quiz can have.
+pub const MAX_QUESTIONS: usize = 20;
+
+/// What kind of quiz to generate.
+#[derive(Clone, Debug)]
+pub struct QuizParams {
+    pub language: Language,
+    /// The language prompts and explanations are written in.
+    pub explanation_language: String,
+    pub questions: usize,
+}
+
+// The JSON the model has to answer with.
+const QUIZ_FORMAT: &str = r#"{
+    "questions": [
+        {"type": "multiple_choice", "prompt": "...", "choices": ["...", "..."], "answer": 0, "explanation": "..."},
+        {"type": "cloze", "prompt": "...", "sentence": "A sentence with ___ for the blank", "answer": "...", "explanation": "..."},
+        {"type": "reorder", "prompt": "...", "fragments": ["Shuffled", "parts", "of the answer"], "answer": "The whole sentence", "explanation": "..."},
+        {"type": "translation", "prompt": "...", "source": "A sentence to translate", "reference": "A good translation", "explanation": "..."}
+    ]
+}"#;
+
+#[derive(Deserialize)]
+struct GeneratedQuiz {
+    questions: Vec<Question>,
+}
+
+/// Asks the model for exercises on `material`, the JSON of a lesson or a translation.
+///
+/// With `capture_content` the prompt and the quiz are recorded in the model call span.
+#[instrument(
+    name = "generate_quiz",
+    fields(quiz.language = %params.language, quiz.questions = params.questions),
+    skip_all,
+)]
+pub async fn generate_quiz(
+    params: &QuizParams,
+    material: &str,
+    capture_content: bool,
+) -> Result<Quiz> {
+    let aws_client = aws::AWSClient::new(Some(aws::InferenceParameters {
+        temperature: 0.5,
+        max_tokens: 4096,
+        top_p: 0.95,
+    }))
+    .await
+    .context("Error creating AWS client")?
+    .with_content_capture(capture_content);
+
+    let conversation = ConversationBuilder::new()
+        .with_system_prompt(system_prompt(params))
+        .add_user_message(material)
+        .build()
+        .context("Error creating messages for AWS Bedrock")?;
+
+    let output = aws_client
+        .create_conversation(conversation)
+        .await
+        .context("Error creating conversation with AWS Bedrock")?;
+    let generated: GeneratedQuiz = parse_model_json(&output).context("Error parsing the quiz")?;
+
+    let mut questions: Vec<_> = generated
+        .questions
+        .into_iter()
+        .filter(|question| match question.kind.check() {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(error = %e, "Dropping an invalid question");
+                false
+            }
+        })
+        .take(params.questions)
+        .collect();
+    for question in &mut questions {
+        question.kind.shuffle(&mut rand::thread_rng());
+    }
+    if questions.is_empty() {
+        bail!("The model didn't generate any valid question");
+    }
+
+    Ok(Quiz {
+        id: Uuid::new_v4(),
+        language: params.language,
+        created_at: Utc::now(),
+        questions,
+    })
+}
+
+fn system_prompt(params: &QuizParams) -> String {
+    format!(
+        "You are a {language} language teacher who writes exercises on the material the student \
+        sends you. Write {questions} questions, mixing these types: \
+        multiple_choice, with a single right choice; \
+        cloze, a {language} sentence where {blank} replaces a particle, a measure word or a \
+        conjugation; \
+        reorder, a {language} sentence cut into shuffled fragments that make up the answer exactly \
+        when put back together; \
+        translation, a sentence to translate into or out of {language}. \
+        Only ask about what is in the material. \
+        Write the prompts and explanations in {explanation_language}. \
+        Respond only with JSON in the following format, without any other text:\n{format}",
+        language = params.language,
+        questions = params.questions,
+        blank = BLANK,
+        explanation_language = params.explanation_language,
+        format = QUIZ_FORMAT,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_json::json;
+
+    fn generated() -> serde_json::Value {
+        json!({"questions": [
+            {"type": "multiple_choice", "prompt": "How do you say water?", "choices": ["水", "火"], "answer": 0, "explanation": "水 is water."},
+            {"type": "cloze", "prompt": "Fill in the particle", "sentence": "私___学生です。", "answer": "は"},
+            {"type": "reorder", "prompt": "Put the sentence back together", "fragments": ["学生", "私は", "です"], "answer": "私は学生です"},
+            {"type": "translation", "prompt": "Translate", "source": "I'm a student.", "reference": "私は学生です。"},
+            {"type": "multiple_choice", "prompt": "Broken", "choices": ["a", "b"], "answer": 2}
+        ]})
+    }
+
+    #[test]
+    fn parses_and_checks_the_model_output() {
+        let output = format!("```json\n{}\n```", generated());
+        let generated: GeneratedQuiz = parse_model_json(&output).unwrap();
+        let valid: Vec<_> = generated
+            .questions
+            .iter()
+            .map(|question| question.kind.check().is_ok())
+            .collect();
+        assert_eq!(valid, [true, true, true, true, false]);
+        assert_eq!(generated.questions[0].kind.expected(), "水");
+        assert_eq!(generated.questions[1].explanation, "");
+    }
+
+    #[test]
+    fn reorder_fragments_have_to_make_up_the_answer() {
+        let kind = QuestionKind::Reorder {
+            fragments: vec!["私は".to_string(), "学生".to_string()],
+            answer: "私は先生です".to_string(),
+        };
+        assert!(kind.check().is_err());
+    }
+
+    #[test]
+    fn reorder_fragments_are_never_left_in_order() {
+        let in_order = QuestionKind::Reorder {
+            fragments: vec!["私は".to_string(), "学生".to_string(), "です".to_string()],
+            answer: "私は学生です。".to_string(),
+        };
+        for seed in 0..100 {
+            let mut kind = in_order.clone();
+            kind.shuffle(&mut StdRng::seed_from_u64(seed));
+            assert!(kind.check().is_ok());
+            assert_ne!(kind, in_order);
+        }
+
+        // Gives up when every order is the answer's.
+        let mut same = QuestionKind::Reorder {
+            fragments: vec!["はい".to_string(), "はい".to_string()],
+            answer: "はいはい".to_string(),
+        };
+        same.shuffle(&mut StdRng::seed_from_u64(0));
+        assert_eq!(same.expected(), "はいはい");
+    }
+
+    #[test]
+    fn views_leave_the_answers_out() {
+        let generated: GeneratedQuiz = serde_json::from_value(generated()).unwrap();
+        let quiz = Quiz {
+            id: Uuid::new_v4(),
+            language: Language::Japanese,
+            created_at: Utc::now(),
+            questions: generated.questions,
+        };
+        let view = serde_json::to_value(quiz.view()).unwrap();
+        let questions = view["questions"].as_array().unwrap();
+        assert_eq!(questions[0]["type"], "multiple_choice");
+        assert_eq!(questions[1]["sentence"], "私___学生です。");
+        assert_eq!(questions[3]["source"], "I'm a student.");
+        for question in questions {
+            let question = question.as_object().unwrap();
+            for hidden in ["answer", "reference", "explanation"] {
+                assert!(!question.contains_key(hidden), "{} leaked", hidden);
+            }
+        }
+    }
+}
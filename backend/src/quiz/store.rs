@@ -0,0 +1,18 @@
+use uuid::Uuid;
+
+use super::Quiz;
+use crate::per_user_store::{Item, PerUserStore};
+
+impl Item for Quiz {
+    const KIND: &'static str = "quiz";
+    const MAX_PER_USER: usize = 100;
+
+    fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Every user's quizzes, along with the right answers.
+///
+/// What users answer isn't kept: each grading stands on its own.
+pub type QuizStore = PerUserStore<Quiz>;
@@ -0,0 +1,184 @@
+use axum::http::HeaderMap;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+// Headers that carry credentials are masked no matter what the configuration says.
+const ALWAYS_REDACTED_HEADERS: [&str; 2] = ["authorization", "proxy-authorization"];
+
+/// Decides what is allowed to show up in logs and spans.
+///
+/// * Headers in the policy have their values replaced with [`REDACTED`].
+/// * Fields in the policy are masked wherever they show up in a JSON payload that gets logged.
+/// * User content (the text users send us and what the model says about it) is only
+///   logged when explicitly allowed, otherwise only its length is.
+#[derive(Clone, Debug)]
+pub struct RedactionPolicy {
+    headers: HashSet<String>,
+    fields: HashSet<String>,
+    log_user_content: bool,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        Self::new(
+            ["cookie", "set-cookie", "x-api-key"],
+            [
+                "password",
+                "token",
+                "access_token",
+                "id_token",
+                "refresh_token",
+            ],
+            false,
+        )
+    }
+}
+
+impl RedactionPolicy {
+    pub fn new(
+        headers: impl IntoIterator<Item = impl AsRef<str>>,
+        fields: impl IntoIterator<Item = impl AsRef<str>>,
+        log_user_content: bool,
+    ) -> Self {
+        let mut headers: HashSet<String> = headers
+            .into_iter()
+            .map(|h| h.as_ref().trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        headers.extend(ALWAYS_REDACTED_HEADERS.iter().map(|h| h.to_string()));
+
+        let fields = fields
+            .into_iter()
+            .map(|f| f.as_ref().trim().to_ascii_lowercase())
+            .filter(|f| !f.is_empty())
+            .collect();
+
+        Self {
+            headers,
+            fields,
+            log_user_content,
+        }
+    }
+
+    pub fn log_user_content(&self) -> bool {
+        self.log_user_content
+    }
+
+    /// Returns the headers as name/value pairs, safe to attach to a span.
+    pub fn headers(&self, headers: &HeaderMap) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(key, value)| {
+                let value = if self.headers.contains(key.as_str()) {
+                    REDACTED
+                } else {
+                    value.to_str().unwrap_or("invalid")
+                };
+                (key.as_str().to_owned(), value.to_owned())
+            })
+            .collect()
+    }
+
+    /// Masks the configured fields anywhere within a JSON value.
+    pub fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.fields.contains(&key.to_ascii_lowercase()) {
+                        *value = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|v| self.redact_json(v)),
+            _ => {}
+        }
+    }
+
+    /// Returns user content in a form that may be logged.
+    ///
+    /// If the content is JSON then the configured fields are masked.
+    pub fn user_content<'a>(&self, content: &'a str) -> Cow<'a, str> {
+        if !self.log_user_content {
+            return Cow::Owned(format!("{} ({} chars)", REDACTED, content.chars().count()));
+        }
+
+        match serde_json::from_str::<Value>(content) {
+            Ok(mut value) if value.is_object() || value.is_array() => {
+                self.redact_json(&mut value);
+                Cow::Owned(value.to_string())
+            }
+            _ => Cow::Borrowed(content),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    #[test]
+    fn authorization_is_always_redacted() {
+        let policy = RedactionPolicy::new(Vec::<String>::new(), Vec::<String>::new(), true);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("Bearer secret-token"),
+        );
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+
+        let headers = policy.headers(&headers);
+        assert!(headers.contains(&("authorization".to_string(), REDACTED.to_string())));
+        assert!(headers.contains(&("content-type".to_string(), "application/json".to_string())));
+    }
+
+    #[test]
+    fn configured_headers_are_redacted() {
+        let policy = RedactionPolicy::new(["X-Custom-Secret"], Vec::<String>::new(), true);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-custom-secret", HeaderValue::from_static("hunter2"));
+
+        assert_eq!(
+            policy.headers(&headers),
+            vec![("x-custom-secret".to_string(), REDACTED.to_string())]
+        );
+    }
+
+    #[test]
+    fn configured_fields_are_redacted_at_any_depth() {
+        let policy = RedactionPolicy::default();
+        let mut value = json!({
+            "user": "alice",
+            "nested": [{"access_token": "abc"}, {"Password": "hunter2"}],
+        });
+
+        policy.redact_json(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "user": "alice",
+                "nested": [{"access_token": REDACTED}, {"Password": REDACTED}],
+            })
+        );
+    }
+
+    #[test]
+    fn user_content_is_hidden_unless_allowed() {
+        let content = r#"{"original": "私の秘密", "token": "abc"}"#;
+
+        let hidden = RedactionPolicy::default().user_content(content);
+        assert!(!hidden.contains("私の秘密"));
+        assert!(hidden.starts_with(REDACTED));
+
+        let allowed = RedactionPolicy::new(Vec::<String>::new(), ["token"], true);
+        let shown = allowed.user_content(content);
+        assert!(shown.contains("私の秘密"));
+        assert!(!shown.contains("abc"));
+    }
+}
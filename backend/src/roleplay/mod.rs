@@ -0,0 +1,547 @@
+//! Role-play sessions: the user practices a scenario, e.g., ordering at a restaurant, with the
+//! tutor playing the other part and correcting them along the way.
+//!
+//! Sessions are kept whole, but once their turns grow past [`MAX_CONTEXT_CHARS`] the oldest
+//! ones are summarized and only the summary is sent to the model.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use crate::aws::{self, bedrock::parse_model_json};
+use crate::chat::Role;
+use crate::lesson::{LessonError, Level};
+use crate::{Conversation, ConversationBuilder, Language};
+
+mod store;
+
+pub use store::SessionStore;
+
+/// How much of the conversation is sent to the model, in characters, before older turns
+/// get summarized.
+pub const MAX_CONTEXT_CHARS: usize = 8_000;
+// How many of the latest turns are always sent as they are.
+const RECENT_TURNS: usize = 8;
+/// Sessions are ended past this many turns, the tutor's included.
+pub const MAX_TURNS: usize = 200;
+pub const MAX_SCENARIO_LEN: usize = 200;
+pub const MAX_MESSAGE_LEN: usize = 1_000;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SessionError {
+    #[error(transparent)]
+    Params(#[from] LessonError),
+
+    #[error("{0}")]
+    Invalid(String),
+
+    #[error("the session has ended")]
+    Ended,
+
+    #[error("the session is too long, end it and start a new one")]
+    TooLong,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStatus {
+    Active,
+    Ended,
+}
+
+/// A mistake in one of the user's messages.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Correction {
+    pub original: String,
+    pub corrected: String,
+    pub explanation: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionTurn {
+    pub role: Role,
+    pub content: String,
+    /// The mistakes in the user's turns, as pointed out in the tutor's next turn.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub corrections: Vec<Correction>,
+    pub at: DateTime<Utc>,
+}
+
+/// How the user did over the whole session.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub summary: String,
+    pub strengths: Vec<String>,
+    /// The mistakes worth reviewing.
+    pub mistakes: Vec<String>,
+    pub vocabulary: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub language: Language,
+    pub level: Option<Level>,
+    pub scenario: String,
+    pub status: SessionStatus,
+    pub created_at: DateTime<Utc>,
+    pub turns: Vec<SessionTurn>,
+    /// What happened in the oldest turns, sent to the model instead of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub earlier_summary: Option<String>,
+    /// How many of the oldest turns `earlier_summary` covers.
+    #[serde(default)]
+    pub summarized_turns: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<SessionSummary>,
+}
+
+/// A session without its turns.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub language: Language,
+    pub level: Option<Level>,
+    pub scenario: String,
+    pub status: SessionStatus,
+    pub created_at: DateTime<Utc>,
+    pub turns: usize,
+}
+
+impl From<&Session> for SessionInfo {
+    fn from(session: &Session) -> Self {
+        Self {
+            id: session.id,
+            language: session.language,
+            level: session.level,
+            scenario: session.scenario.clone(),
+            status: session.status,
+            created_at: session.created_at,
+            turns: session.turns.len(),
+        }
+    }
+}
+
+/// The tutor's answer to a turn.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TutorReply {
+    /// The tutor's line, in character.
+    pub reply: String,
+    /// The mistakes in the user's last message.
+    #[serde(default)]
+    pub corrections: Vec<Correction>,
+}
+
+/// A turn taken on a copy of the session, to be added with [`Session::add_turn`].
+#[derive(Clone, Debug)]
+pub struct Turn {
+    reply: TutorReply,
+    // The user's turn and the tutor's.
+    turns: Vec<SessionTurn>,
+    // The summary the turns up to the given one were folded into, if they were for this turn.
+    earlier_summary: Option<(String, usize)>,
+}
+
+impl Session {
+    pub fn new(
+        language: Language,
+        level: Option<Level>,
+        scenario: &str,
+    ) -> Result<Self, SessionError> {
+        if let Some(level) = level {
+            if level.language() != language {
+                return Err(LessonError::LevelMismatch { level, language }.into());
+            }
+        }
+        let scenario = scenario.trim();
+        if scenario.is_empty() || scenario.chars().count() > MAX_SCENARIO_LEN {
+            return Err(SessionError::Invalid(format!(
+                "the scenario has to be between 1 and {} characters",
+                MAX_SCENARIO_LEN
+            )));
+        }
+        Ok(Self {
+            id: Uuid::new_v4(),
+            language,
+            level,
+            scenario: scenario.to_string(),
+            status: SessionStatus::Active,
+            created_at: Utc::now(),
+            turns: Vec::new(),
+            earlier_summary: None,
+            summarized_turns: 0,
+            summary: None,
+        })
+    }
+
+    /// Whether the user can take another turn.
+    pub fn check_turn(&self, message: &str) -> Result<(), SessionError> {
+        if self.status == SessionStatus::Ended {
+            return Err(SessionError::Ended);
+        }
+        if self.turns.len() + 2 > MAX_TURNS {
+            return Err(SessionError::TooLong);
+        }
+        if message.trim().is_empty() || message.chars().count() > MAX_MESSAGE_LEN {
+            return Err(SessionError::Invalid(format!(
+                "messages have to be between 1 and {} characters",
+                MAX_MESSAGE_LEN
+            )));
+        }
+        Ok(())
+    }
+
+    /// Adds a turn taken on an earlier copy of the session, unless the session ended since.
+    ///
+    /// Turns taken at the same time are both added, one after the other.
+    pub fn add_turn(&mut self, turn: Turn) -> Result<TutorReply, SessionError> {
+        if self.status == SessionStatus::Ended {
+            return Err(SessionError::Ended);
+        }
+        if self.turns.len() + turn.turns.len() > MAX_TURNS {
+            return Err(SessionError::TooLong);
+        }
+        if let Some((summary, summarized_turns)) = turn.earlier_summary {
+            if summarized_turns > self.summarized_turns {
+                self.earlier_summary = Some(summary);
+                self.summarized_turns = summarized_turns;
+            }
+        }
+        self.turns.extend(turn.turns);
+        Ok(turn.reply)
+    }
+
+    /// Ends the session with `summary`, unless it already ended.
+    pub fn end(&mut self, summary: SessionSummary) -> Result<(), SessionError> {
+        if self.status == SessionStatus::Ended {
+            return Err(SessionError::Ended);
+        }
+        self.status = SessionStatus::Ended;
+        self.summary = Some(summary);
+        Ok(())
+    }
+
+    /// How many turns to fold into the summary before the next one, if any.
+    ///
+    /// Once the turns sent to the model get past the budget, all but the latest few are,
+    /// leaving a user turn first.
+    fn turns_to_summarize(&self) -> Option<usize> {
+        let sent = &self.turns[self.summarized_turns..];
+        let chars: usize = sent.iter().map(|turn| turn.content.chars().count()).sum();
+        if chars <= MAX_CONTEXT_CHARS || sent.len() <= RECENT_TURNS {
+            return None;
+        }
+        let mut cut = self.turns.len() - RECENT_TURNS;
+        while self
+            .turns
+            .get(cut)
+            .is_some_and(|turn| turn.role != Role::User)
+        {
+            cut += 1;
+        }
+        (cut > self.summarized_turns && cut < self.turns.len()).then_some(cut)
+    }
+
+    fn system_prompt(&self) -> String {
+        let level = match self.level {
+            Some(level) => format!(
+                "The student is at the {} level: stick to grammar and vocabulary suited to it.",
+                level
+            ),
+            None => "Adapt to the level the student writes at.".to_string(),
+        };
+        let earlier = match &self.earlier_summary {
+            Some(summary) => format!("What happened earlier in the scene: {} ", summary),
+            None => String::new(),
+        };
+        format!(
+            "You are a {language} tutor role-playing a scenario with a student: {scenario}. \
+            Play the other part, in character and in {language}, in one to three sentences, \
+            and keep the scene moving. {level} \
+            Also list the mistakes in the student's last message, if any, with the corrected \
+            phrase and a short explanation in English. Don't count stylistic choices as mistakes. \
+            {earlier}\
+            Respond only with JSON in the following format, without any other text:\n\
+            {{\"reply\": \"...\", \"corrections\": [{{\"original\": \"...\", \"corrected\": \"...\", \
+            \"explanation\": \"...\"}}]}}",
+            language = self.language,
+            scenario = self.scenario,
+            level = level,
+            earlier = earlier,
+        )
+    }
+
+    // The turns that haven't been summarized, with the tutor's in the JSON it answered with.
+    fn conversation(&self) -> Result<Conversation> {
+        let mut builder = ConversationBuilder::new().with_system_prompt(self.system_prompt());
+        for turn in &self.turns[self.summarized_turns..] {
+            builder = match turn.role {
+                Role::User => builder.add_user_message(&turn.content),
+                Role::Assistant => {
+                    builder.add_assistant_message(serde_json::to_string(&TutorReply {
+                        reply: turn.content.clone(),
+                        corrections: Vec::new(),
+                    })?)
+                }
+            };
+        }
+        Ok(builder.build()?)
+    }
+
+    fn transcript(&self, turns: &[SessionTurn]) -> String {
+        turns
+            .iter()
+            .map(|turn| match turn.role {
+                Role::User => format!("Student: {}", turn.content),
+                Role::Assistant => format!("Tutor: {}", turn.content),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn push(&mut self, role: Role, content: String, corrections: Vec<Correction>) {
+        self.turns.push(SessionTurn {
+            role,
+            content,
+            corrections,
+            at: Utc::now(),
+        });
+    }
+}
+
+async fn client(max_tokens: i32, capture_content: bool) -> Result<aws::AWSClient> {
+    Ok(aws::AWSClient::new(Some(aws::InferenceParameters {
+        temperature: 0.7,
+        max_tokens,
+        top_p: 0.95,
+    }))
+    .await
+    .context("Error creating AWS client")?
+    .with_content_capture(capture_content))
+}
+
+/// Takes the user's turn and gets the tutor's, summarizing older turns first if needed.
+///
+/// The session isn't changed: the turn is added to it with [`Session::add_turn`].
+#[instrument(
+    name = "take_turn",
+    fields(session.id = %session.id, session.turns = session.turns.len()),
+    skip_all,
+)]
+pub async fn take_turn(session: &Session, message: &str, capture_content: bool) -> Result<Turn> {
+    let client = client(1024, capture_content).await?;
+    let mut updated = session.clone();
+    let mut earlier_summary = None;
+
+    if let Some(cut) = updated.turns_to_summarize() {
+        info!(
+            turns = cut - updated.summarized_turns,
+            "summarizing older turns"
+        );
+        let turns = &updated.turns[updated.summarized_turns..cut];
+        let prompt = format!(
+            "Summarize this part of a {} role-play ({}) in a few sentences of English, keeping \
+            what the rest of the scene needs to know. Respond with the summary only.\n\n{}{}",
+            updated.language,
+            updated.scenario,
+            updated
+                .earlier_summary
+                .as_ref()
+                .map(|summary| format!("Before that: {}\n\n", summary))
+                .unwrap_or_default(),
+            updated.transcript(turns),
+        );
+        let conversation = ConversationBuilder::new()
+            .add_user_message(prompt)
+            .build()
+            .context("Error creating messages for AWS Bedrock")?;
+        let summary = client
+            .create_conversation(conversation)
+            .await
+            .context("Error summarizing the session")?;
+        updated.earlier_summary = Some(summary.trim().to_string());
+        updated.summarized_turns = cut;
+        earlier_summary = Some((summary.trim().to_string(), cut));
+    }
+
+    updated.push(Role::User, message.trim().to_string(), Vec::new());
+    let output = client
+        .create_conversation(updated.conversation()?)
+        .await
+        .context("Error creating conversation with AWS Bedrock")?;
+    let reply: TutorReply = parse_model_json(&output).context("Error parsing the reply")?;
+    if reply.reply.trim().is_empty() {
+        bail!("The tutor didn't reply");
+    }
+
+    updated
+        .turns
+        .last_mut()
+        .expect("the user's turn was just added")
+        .corrections = reply.corrections.clone();
+    updated.push(Role::Assistant, reply.reply.clone(), Vec::new());
+    Ok(Turn {
+        reply,
+        turns: updated.turns.split_off(session.turns.len()),
+        earlier_summary,
+    })
+}
+
+/// Sums up how the user did, for [`Session::end`].
+#[instrument(name = "end_session", fields(session.id = %session.id), skip_all)]
+pub async fn end_session(session: &Session, capture_content: bool) -> Result<SessionSummary> {
+    if session.status == SessionStatus::Ended {
+        bail!(SessionError::Ended);
+    }
+
+    if session.turns.is_empty() {
+        return Ok(SessionSummary {
+            summary: "The session ended before it started.".to_string(),
+            strengths: Vec::new(),
+            mistakes: Vec::new(),
+            vocabulary: Vec::new(),
+        });
+    }
+
+    let corrections: Vec<_> = session
+        .turns
+        .iter()
+        .flat_map(|turn| &turn.corrections)
+        .collect();
+    let prompt = format!(
+        "This is a {language} role-play between a tutor and a student: {scenario}. \
+        Write, in English, a short summary of how the student did, their strengths, the \
+        mistakes worth reviewing and the useful vocabulary that came up. \
+        Respond only with JSON in the following format, without any other text:\n\
+        {{\"summary\": \"...\", \"strengths\": [\"...\"], \"mistakes\": [\"...\"], \
+        \"vocabulary\": [\"...\"]}}\n\n\
+        {earlier}Transcript:\n{transcript}\n\nCorrections made along the way: {corrections}",
+        language = session.language,
+        scenario = session.scenario,
+        earlier = session
+            .earlier_summary
+            .as_ref()
+            .map(|summary| format!("Summary of the beginning: {}\n\n", summary))
+            .unwrap_or_default(),
+        transcript = session.transcript(&session.turns[session.summarized_turns..]),
+        corrections = serde_json::to_string(&corrections)?,
+    );
+    let conversation = ConversationBuilder::new()
+        .add_user_message(prompt)
+        .build()
+        .context("Error creating messages for AWS Bedrock")?;
+    let output = client(2048, capture_content)
+        .await?
+        .create_conversation(conversation)
+        .await
+        .context("Error creating conversation with AWS Bedrock")?;
+    parse_model_json(&output).context("Error parsing the summary")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session_with_turns(turns: usize, chars: usize) -> Session {
+        let mut session = Session::new(Language::Japanese, None, "At a ramen shop").unwrap();
+        for i in 0..turns {
+            let role = if i % 2 == 0 {
+                Role::User
+            } else {
+                Role::Assistant
+            };
+            session.push(role, "あ".repeat(chars), Vec::new());
+        }
+        session
+    }
+
+    #[test]
+    fn validates_new_sessions() {
+        assert_eq!(
+            Session::new(Language::Japanese, Some(Level::Hsk1), "At a café").unwrap_err(),
+            SessionError::Params(LessonError::LevelMismatch {
+                level: Level::Hsk1,
+                language: Language::Japanese
+            })
+        );
+        assert!(Session::new(Language::Japanese, None, "  ").is_err());
+        let session = Session::new(Language::Chinese, Some(Level::Hsk2), " At a café ").unwrap();
+        assert_eq!(session.scenario, "At a café");
+        assert_eq!(session.status, SessionStatus::Active);
+    }
+
+    #[test]
+    fn checks_turns() {
+        let mut session = session_with_turns(2, 10);
+        assert!(session.check_turn("すみません").is_ok());
+        assert!(session.check_turn(" ").is_err());
+        assert!(session
+            .check_turn(&"あ".repeat(MAX_MESSAGE_LEN + 1))
+            .is_err());
+
+        let long = session_with_turns(MAX_TURNS - 1, 1);
+        assert_eq!(long.check_turn("はい"), Err(SessionError::TooLong));
+
+        session.status = SessionStatus::Ended;
+        assert_eq!(session.check_turn("はい"), Err(SessionError::Ended));
+    }
+
+    #[test]
+    fn summarizes_once_past_the_budget() {
+        let short = session_with_turns(20, 10);
+        assert_eq!(short.turns_to_summarize(), None);
+
+        // 20 turns of 500 characters are past the budget: all but the last 8 get summarized.
+        let mut long = session_with_turns(20, 500);
+        assert_eq!(long.turns_to_summarize(), Some(12));
+
+        long.summarized_turns = 12;
+        assert_eq!(long.turns_to_summarize(), None);
+        long.push(Role::User, "はい".to_string(), Vec::new());
+        let conversation = long.conversation().unwrap();
+        assert_eq!(conversation.messages.len(), 9);
+    }
+
+    #[test]
+    fn summaries_start_on_a_user_turn() {
+        // With an odd number of turns, the last 8 start with the tutor's.
+        let long = session_with_turns(21, 500);
+        assert_eq!(long.turns_to_summarize(), Some(14));
+        assert_eq!(long.turns[14].role, Role::User);
+    }
+
+    #[test]
+    fn prompts_carry_the_scenario_and_the_earlier_summary() {
+        let mut session =
+            Session::new(Language::Japanese, Some(Level::N4), "A job interview").unwrap();
+        let prompt = session.system_prompt();
+        assert!(prompt.contains("role-playing a scenario with a student: A job interview."));
+        assert!(prompt.contains("the JLPT N4 level"));
+        assert!(!prompt.contains("earlier"));
+
+        session.earlier_summary = Some("The student introduced themselves.".to_string());
+        assert!(session
+            .system_prompt()
+            .contains("What happened earlier in the scene: The student introduced themselves."));
+    }
+
+    #[test]
+    fn tutor_turns_are_sent_as_json() {
+        let mut session = session_with_turns(0, 0);
+        session.push(Role::User, "ラーメンください".to_string(), Vec::new());
+        session.push(
+            Role::Assistant,
+            "はい、少々お待ちください。".to_string(),
+            Vec::new(),
+        );
+        session.push(Role::User, "ありがとう".to_string(), Vec::new());
+
+        let conversation = session.conversation().unwrap();
+        let tutor = conversation.messages[1].content()[0].as_text().unwrap();
+        let reply: TutorReply = serde_json::from_str(tutor).unwrap();
+        assert_eq!(reply.reply, "はい、少々お待ちください。");
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use uuid::Uuid;
+
+use super::{Session, SessionInfo};
+use crate::per_user_store::{Item, PerUserStore};
+
+impl Item for Session {
+    const KIND: &'static str = "session";
+    const MAX_PER_USER: usize = 50;
+
+    fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Every user's role-play sessions.
+pub type SessionStore = PerUserStore<Session>;
+
+impl SessionStore {
+    /// The user's sessions, newest first.
+    pub async fn list(&self, sub: &str) -> Result<Vec<SessionInfo>> {
+        self.read(sub, |sessions| {
+            sessions.iter().rev().map(SessionInfo::from).collect()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        SessionError, SessionStatus, SessionSummary, SessionTurn, Turn, TutorReply,
+    };
+    use super::*;
+    use crate::chat::Role;
+    use crate::per_user_store::UpdateError;
+    use crate::Language;
+    use chrono::Utc;
+
+    // A turn as `take_turn` would have taken it.
+    fn turn(message: &str, reply: &str) -> Turn {
+        let turn = |role, content: &str| SessionTurn {
+            role,
+            content: content.to_string(),
+            corrections: Vec::new(),
+            at: Utc::now(),
+        };
+        Turn {
+            reply: TutorReply {
+                reply: reply.to_string(),
+                corrections: Vec::new(),
+            },
+            turns: vec![turn(Role::User, message), turn(Role::Assistant, reply)],
+            earlier_summary: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_sessions_newest_first() {
+        let store = SessionStore::in_memory();
+        let mut first = Session::new(Language::Japanese, None, "At a ramen shop").unwrap();
+        let second = Session::new(Language::Chinese, None, "At a market").unwrap();
+        store.save("alice", &first).await.unwrap();
+        store.save("alice", &second).await.unwrap();
+        first.push(Role::User, "すみません".to_string(), Vec::new());
+        store.save("alice", &first).await.unwrap();
+
+        let sessions = store.list("alice").await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, second.id);
+        assert_eq!(sessions[1].turns, 1);
+        assert!(store.list("bob").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn turns_in_flight_dont_reopen_ended_sessions() {
+        let store = SessionStore::in_memory();
+        let session = Session::new(Language::Japanese, None, "At a ramen shop").unwrap();
+        store.save("alice", &session).await.unwrap();
+
+        // Both turns were taken on the session as it was when they started.
+        let first = turn("ラーメンください", "はい、少々お待ちください。");
+        let second = turn("お水ください", "はい、どうぞ。");
+        store
+            .update("alice", session.id, move |session| session.add_turn(first))
+            .await
+            .unwrap();
+        store
+            .update("alice", session.id, move |session| session.add_turn(second))
+            .await
+            .unwrap();
+        let stored = store.get("alice", session.id).await.unwrap().unwrap();
+        assert_eq!(stored.turns.len(), 4);
+
+        let summary = SessionSummary {
+            summary: "Ordered ramen.".to_string(),
+            strengths: Vec::new(),
+            mistakes: Vec::new(),
+            vocabulary: Vec::new(),
+        };
+        store
+            .update("alice", session.id, move |session| session.end(summary))
+            .await
+            .unwrap();
+        let late = turn("お会計お願いします", "はい。");
+        let result = store
+            .update("alice", session.id, move |session| session.add_turn(late))
+            .await;
+        assert!(matches!(
+            result,
+            Err(UpdateError::Rejected(SessionError::Ended))
+        ));
+
+        let stored = store.get("alice", session.id).await.unwrap().unwrap();
+        assert_eq!(stored.status, SessionStatus::Ended);
+        assert_eq!(stored.turns.len(), 4);
+        assert_eq!(stored.summary.unwrap().summary, "Ordered ramen.");
+    }
+}
@@ -0,0 +1,404 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use ring::{constant_time, digest, rand::SecureRandom, rand::SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tracing::warn;
+
+use super::claims::Claims;
+use crate::json_file;
+
+/// Every API key starts with this, which is how we tell them apart from JWTs.
+pub const API_KEY_PREFIX: &str = "kmk_";
+
+// The `iss` of the claims built out of an API key.
+const API_KEY_ISSUER: &str = "kamekai:api-key";
+
+// How often the server checks whether the CLI changed the keys.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum ApiKeyError {
+    #[error("malformed API key")]
+    Malformed,
+
+    #[error("unknown API key")]
+    Unknown,
+
+    #[error("API key {0} was revoked")]
+    Revoked(String),
+
+    #[error("API key {0} expired")]
+    Expired(String),
+}
+
+/// An API key, as stored. The key itself is never stored, only its SHA-256 hash.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub sub: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    hash: String,
+}
+
+impl ApiKey {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|t| t > now)
+    }
+
+    /// The claims requests authenticated with this key act with.
+    pub fn claims(&self) -> Claims {
+        Claims {
+            sub: self.sub.clone(),
+            iss: API_KEY_ISSUER.to_string(),
+            client_id: Some(self.id.clone()),
+            username: None,
+            scopes: self.scopes.clone(),
+            groups: Vec::new(),
+            jti: Some(self.id.clone()),
+            origin_jti: None,
+            exp: self.expires_at.map_or(i64::MAX, |t| t.timestamp()),
+            iat: Some(self.created_at.timestamp()),
+            zoneinfo: None,
+        }
+    }
+}
+
+/// What to mint a key for.
+#[derive(Clone, Debug)]
+pub struct NewApiKey {
+    pub name: String,
+    pub sub: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ApiKeyFile {
+    keys: Vec<ApiKey>,
+}
+
+// Tells whether the file changed since we last read it.
+type Fingerprint = Option<(SystemTime, u64)>;
+
+#[derive(Debug, Default)]
+struct Loaded {
+    keys: Vec<ApiKey>,
+    fingerprint: Fingerprint,
+}
+
+/// API keys persisted to a JSON file.
+///
+/// The server and the `api-key` CLI subcommands share the file, so the server checks it for
+/// changes every 5s, off the async runtime. Requests are served from the keys last read.
+#[derive(Debug)]
+pub struct ApiKeyStore {
+    path: PathBuf,
+    loaded: Mutex<Loaded>,
+}
+
+impl ApiKeyStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let store = Self {
+            path: path.as_ref().to_path_buf(),
+            loaded: Mutex::new(Loaded::default()),
+        };
+        store.reload()?;
+        Ok(store)
+    }
+
+    /// Keeps checking the file for changes in the background, for as long as the store is around.
+    /// Must be called from within a Tokio runtime.
+    pub fn reload_periodically(self: &Arc<Self>) {
+        tokio::spawn(reload_periodically(Arc::downgrade(self)));
+    }
+
+    /// Creates a key and returns it in plain text, which is the only time it is available.
+    pub fn mint(&self, new_key: NewApiKey) -> Result<(String, ApiKey)> {
+        let rng = SystemRandom::new();
+        let mut id = [0u8; 8];
+        let mut secret = [0u8; 32];
+        rng.fill(&mut id)
+            .and_then(|_| rng.fill(&mut secret))
+            .map_err(|_| anyhow!("Error generating an API key"))?;
+        let id = to_hex(&id);
+        let secret = to_hex(&secret);
+
+        let key = ApiKey {
+            id: id.clone(),
+            name: new_key.name,
+            sub: new_key.sub,
+            scopes: new_key.scopes,
+            created_at: Utc::now(),
+            expires_at: new_key.expires_at,
+            revoked_at: None,
+            hash: hash(&secret),
+        };
+        self.update(|keys| {
+            keys.push(key.clone());
+            Ok(())
+        })?;
+        Ok((format!("{}{}_{}", API_KEY_PREFIX, id, secret), key))
+    }
+
+    /// Lists the keys, optionally only those of one user.
+    pub fn list(&self, sub: Option<&str>) -> Result<Vec<ApiKey>> {
+        self.reload()?;
+        Ok(self
+            .loaded()
+            .keys
+            .iter()
+            .filter(|k| sub.is_none_or(|sub| k.sub == sub))
+            .cloned()
+            .collect())
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<ApiKey> {
+        self.update(|keys| {
+            let key = keys
+                .iter_mut()
+                .find(|k| k.id == id)
+                .with_context(|| format!("There is no API key {}", id))?;
+            if key.revoked_at.is_none() {
+                key.revoked_at = Some(Utc::now());
+            }
+            Ok(key.clone())
+        })
+    }
+
+    /// Finds the active key matching the one presented by a client, among the keys last read.
+    pub fn authenticate(&self, presented: &str) -> Result<ApiKey, ApiKeyError> {
+        let (id, secret) = presented
+            .strip_prefix(API_KEY_PREFIX)
+            .and_then(|rest| rest.split_once('_'))
+            .ok_or(ApiKeyError::Malformed)?;
+        let key = self
+            .loaded()
+            .keys
+            .iter()
+            .find(|k| k.id == id)
+            .cloned()
+            .ok_or(ApiKeyError::Unknown)?;
+
+        constant_time::verify_slices_are_equal(hash(secret).as_bytes(), key.hash.as_bytes())
+            .map_err(|_| ApiKeyError::Unknown)?;
+        if key.revoked_at.is_some() {
+            return Err(ApiKeyError::Revoked(key.id));
+        }
+        if !key.is_active(Utc::now()) {
+            return Err(ApiKeyError::Expired(key.id));
+        }
+        Ok(key)
+    }
+
+    /// Reads the keys again if the file changed since they were last read.
+    pub fn reload(&self) -> Result<()> {
+        let fingerprint = self.fingerprint()?;
+        if fingerprint.is_some() && fingerprint == self.loaded().fingerprint {
+            return Ok(());
+        }
+        // Read outside the lock, so that requests don't wait on the disk.
+        let keys = self.read()?;
+        *self.loaded() = Loaded { keys, fingerprint };
+        Ok(())
+    }
+
+    fn loaded(&self) -> MutexGuard<'_, Loaded> {
+        self.loaded.lock().expect("API key store lock poisoned")
+    }
+
+    // Applies `f` to the latest keys and writes them back.
+    fn update<T>(&self, f: impl FnOnce(&mut Vec<ApiKey>) -> Result<T>) -> Result<T> {
+        let mut loaded = self.loaded();
+        let mut keys = self.read()?;
+        let result = f(&mut keys)?;
+        self.write(&keys)?;
+        loaded.keys = keys;
+        loaded.fingerprint = self.fingerprint()?;
+        Ok(result)
+    }
+
+    fn fingerprint(&self) -> Result<Fingerprint> {
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => Ok(Some((metadata.modified()?, metadata.len()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                Err(e).with_context(|| format!("Error reading API keys {}", self.path.display()))
+            }
+        }
+    }
+
+    fn read(&self) -> Result<Vec<ApiKey>> {
+        let file: ApiKeyFile = json_file::read(&self.path)?;
+        Ok(file.keys)
+    }
+
+    fn write(&self, keys: &[ApiKey]) -> Result<()> {
+        json_file::write(
+            &self.path,
+            &ApiKeyFile {
+                keys: keys.to_vec(),
+            },
+        )
+    }
+}
+
+async fn reload_periodically(store: Weak<ApiKeyStore>) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        let Some(store) = store.upgrade() else {
+            return;
+        };
+        let path = store.path.clone();
+        match tokio::task::spawn_blocking(move || store.reload()).await {
+            Ok(Ok(())) => {}
+            // Keep serving the keys we have, the file may just be in the middle of being replaced.
+            Ok(Err(e)) => {
+                warn!(path = %path.display(), error = format!("{:#}", e), "failed to reload API keys")
+            }
+            Err(e) => warn!(error = %e, "failed to reload API keys"),
+        }
+    }
+}
+
+// The secrets are random, so a plain (fast) hash is as good as a password hash here.
+fn hash(secret: &str) -> String {
+    to_hex(digest::digest(&digest::SHA256, secret.as_bytes()).as_ref())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("kamekai-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn store() -> (TempDir, ApiKeyStore) {
+        let dir = TempDir::new();
+        let store = ApiKeyStore::open(dir.path().join("api-keys.json")).unwrap();
+        (dir, store)
+    }
+
+    fn new_key(expires_at: Option<DateTime<Utc>>) -> NewApiKey {
+        NewApiKey {
+            name: "batch".to_string(),
+            sub: "user-1".to_string(),
+            scopes: vec!["kamekai/translate".to_string()],
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn authenticates_minted_keys() {
+        let (_dir, store) = store();
+        let (plain, minted) = store.mint(new_key(None)).unwrap();
+        assert!(plain.starts_with(API_KEY_PREFIX));
+
+        let key = store.authenticate(&plain).unwrap();
+        assert_eq!(key.id, minted.id);
+        let claims = key.claims();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.scopes, vec!["kamekai/translate"]);
+    }
+
+    #[test]
+    fn only_stores_the_key_hash() {
+        let (dir, store) = store();
+        let (plain, _) = store.mint(new_key(None)).unwrap();
+        let secret = plain.rsplit('_').next().unwrap();
+
+        let contents = std::fs::read_to_string(dir.path().join("api-keys.json")).unwrap();
+        assert!(!contents.contains(secret));
+    }
+
+    #[test]
+    fn rejects_tampered_and_malformed_keys() {
+        let (_dir, store) = store();
+        let (plain, _) = store.mint(new_key(None)).unwrap();
+
+        let mut tampered = plain.clone();
+        tampered.pop();
+        tampered.push('x');
+        assert!(matches!(
+            store.authenticate(&tampered),
+            Err(ApiKeyError::Unknown)
+        ));
+        assert!(matches!(
+            store.authenticate("kmk_nope"),
+            Err(ApiKeyError::Malformed)
+        ));
+        assert!(matches!(
+            store.authenticate("kmk_0000000000000000_secret"),
+            Err(ApiKeyError::Unknown)
+        ));
+    }
+
+    #[test]
+    fn rejects_revoked_and_expired_keys() {
+        let (_dir, store) = store();
+        let (revoked, key) = store.mint(new_key(None)).unwrap();
+        store.revoke(&key.id).unwrap();
+        assert!(matches!(
+            store.authenticate(&revoked),
+            Err(ApiKeyError::Revoked(_))
+        ));
+
+        let (expired, _) = store
+            .mint(new_key(Some(Utc::now() - Duration::seconds(1))))
+            .unwrap();
+        assert!(matches!(
+            store.authenticate(&expired),
+            Err(ApiKeyError::Expired(_))
+        ));
+    }
+
+    #[test]
+    fn sees_changes_made_by_other_processes() {
+        let (dir, store) = store();
+        let (plain, key) = store.mint(new_key(None)).unwrap();
+        assert!(store.authenticate(&plain).is_ok());
+
+        // E.g., the CLI revoking the key while the server is running.
+        let other = ApiKeyStore::open(dir.path().join("api-keys.json")).unwrap();
+        other.revoke(&key.id).unwrap();
+        // Requests only see it once the keys are reloaded.
+        assert!(store.authenticate(&plain).is_ok());
+        store.reload().unwrap();
+        assert!(store.authenticate(&plain).is_err());
+        assert_eq!(store.list(Some("user-1")).unwrap().len(), 1);
+        assert!(store.list(Some("user-2")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Where to find each of the claims we care about within a token.
+///
+/// Providers name things differently (e.g., Cognito puts groups in `cognito:groups`
+/// while Keycloak uses `realm_access.roles`), so each trusted issuer can map them.
+/// Dotted paths are looked up within nested objects.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClaimMapping {
+    pub subject: String,
+    pub client_id: String,
+    pub username: String,
+    pub scope: String,
+    pub groups: String,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            subject: "sub".to_string(),
+            client_id: "client_id".to_string(),
+            username: "username".to_string(),
+            scope: "scope".to_string(),
+            groups: "groups".to_string(),
+        }
+    }
+}
+
+/// The provider-agnostic view of an authenticated token.
+#[derive(Clone, Debug, Serialize)]
+pub struct Claims {
+    pub sub: String, // Making it public because we log the user ID elsewhere.
+    pub iss: String,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub scopes: Vec<String>,
+    pub groups: Vec<String>,
+    pub jti: Option<String>,
+    /// The ID of the original authentication, shared by every token refreshed from it.
+    pub origin_jti: Option<String>,
+    pub exp: i64,
+    pub iat: Option<i64>,
+    /// The user's time zone, e.g., "Asia/Tokyo", from the standard `zoneinfo` claim.
+    pub zoneinfo: Option<String>,
+}
+
+impl Claims {
+    /// Builds the claims out of a validated token payload.
+    /// Returns `None` if the subject or the standard claims can't be found.
+    pub fn from_raw(raw: &Map<String, Value>, mapping: &ClaimMapping) -> Option<Self> {
+        Some(Self {
+            sub: lookup(raw, &mapping.subject)?.as_str()?.to_string(),
+            iss: raw.get("iss")?.as_str()?.to_string(),
+            client_id: lookup_string(raw, &mapping.client_id),
+            username: lookup_string(raw, &mapping.username),
+            scopes: lookup_list(raw, &mapping.scope),
+            groups: lookup_list(raw, &mapping.groups),
+            jti: lookup_string(raw, "jti"),
+            origin_jti: lookup_string(raw, "origin_jti"),
+            exp: raw.get("exp")?.as_i64()?,
+            iat: raw.get("iat").and_then(Value::as_i64),
+            zoneinfo: lookup_string(raw, "zoneinfo"),
+        })
+    }
+}
+
+/// Finds a claim by name, falling back to treating the name as a dotted path.
+pub fn lookup<'a>(raw: &'a Map<String, Value>, name: &str) -> Option<&'a Value> {
+    if let Some(value) = raw.get(name) {
+        return Some(value);
+    }
+
+    let mut parts = name.split('.');
+    let mut value = raw.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_object()?.get(part)?;
+    }
+    Some(value)
+}
+
+fn lookup_string(raw: &Map<String, Value>, name: &str) -> Option<String> {
+    lookup(raw, name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+// Lists come either as JSON arrays or as space-delimited strings (like OAuth2 scopes).
+fn lookup_list(raw: &Map<String, Value>, name: &str) -> Vec<String> {
+    match lookup(raw, name) {
+        Some(Value::String(s)) => s.split_whitespace().map(str::to_string).collect(),
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use thiserror::Error;
+
+use super::claims::ClaimMapping;
+use super::jwks::JsonWebKeySet;
+use super::oidc::IssuerConfig;
+
+/// The client ID of the tokens minted by the dev issuer.
+pub const DEV_CLIENT_ID: &str = "kamekai-dev";
+
+/// The longest a dev token may be valid for, in seconds.
+pub const MAX_EXPIRES_IN: u64 = 24 * 3600;
+
+/// What to put in a dev token.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DevTokenRequest {
+    pub sub: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Lifetime of the token in seconds.
+    #[serde(default = "default_expires_in")]
+    pub expires_in: u64,
+}
+
+fn default_expires_in() -> u64 {
+    3600
+}
+
+#[derive(Error, Debug)]
+pub enum DevTokenError {
+    #[error("Dev tokens can't be valid for more than {MAX_EXPIRES_IN} seconds")]
+    TooLong,
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DevToken {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+}
+
+/// An issuer for local development and tests, which never talks to anyone.
+///
+/// The signing key is generated on startup and only lives in memory,
+/// so tokens stop being valid whenever the server restarts.
+pub struct DevIssuer {
+    issuer: String,
+    kid: String,
+    encoding_key: EncodingKey,
+    jwks: JsonWebKeySet,
+}
+
+// Don't print the key.
+impl std::fmt::Debug for DevIssuer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DevIssuer")
+            .field("issuer", &self.issuer)
+            .field("kid", &self.kid)
+            .finish()
+    }
+}
+
+impl DevIssuer {
+    /// Generates a fresh ES256 signing key.
+    pub fn generate(issuer: impl Into<String>) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .map_err(|_| anyhow!("Error generating the dev signing key"))?;
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .map_err(|_| anyhow!("Error loading the dev signing key"))?;
+
+        // The public key is an uncompressed point: 0x04 || x || y.
+        let point = &key_pair.public_key().as_ref()[1..];
+        let (x, y) = point.split_at(point.len() / 2);
+        let kid = format!("dev-{}", uuid::Uuid::new_v4());
+        let jwks = serde_json::from_value(json!({
+            "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "x": URL_SAFE_NO_PAD.encode(x),
+                "y": URL_SAFE_NO_PAD.encode(y),
+                "kid": kid,
+                "use": "sig",
+                "alg": "ES256",
+            }]
+        }))
+        .context("Error building the dev JWKS")?;
+
+        Ok(Self {
+            issuer: issuer.into(),
+            kid,
+            encoding_key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+            jwks,
+        })
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn jwks(&self) -> &JsonWebKeySet {
+        &self.jwks
+    }
+
+    /// Trusts this issuer's key directly, without fetching anything.
+    pub fn issuer_config(&self) -> IssuerConfig {
+        IssuerConfig {
+            issuer: self.issuer.clone(),
+            jwks_uri: None,
+            jwks: Some(self.jwks.clone()),
+            allowed_algorithms: vec![Algorithm::ES256],
+            leeway: 60,
+            client_ids: vec![DEV_CLIENT_ID.to_string()],
+            audiences: vec![],
+            required_claims: ["sub", "iss", "exp", "iat", "jti"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            expected_claims: HashMap::new(),
+            claim_mapping: ClaimMapping::default(),
+        }
+    }
+
+    pub fn mint(&self, request: &DevTokenRequest) -> Result<DevToken, DevTokenError> {
+        if request.expires_in > MAX_EXPIRES_IN {
+            return Err(DevTokenError::TooLong);
+        }
+        let now = get_current_timestamp();
+        let claims = json!({
+            "sub": request.sub,
+            "iss": self.issuer,
+            "client_id": DEV_CLIENT_ID,
+            "scope": request.scopes.join(" "),
+            "groups": request.groups,
+            "jti": uuid::Uuid::new_v4().to_string(),
+            "iat": now,
+            "exp": now + request.expires_in,
+        });
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.kid.clone());
+        let access_token =
+            encode(&header, &claims, &self.encoding_key).context("Error signing a dev token")?;
+
+        Ok(DevToken {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: request.expires_in,
+        })
+    }
+}
@@ -0,0 +1,450 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::jwk::Jwk;
+use reqwest::header::{HeaderMap, AGE, CACHE_CONTROL, DATE, EXPIRES};
+use reqwest::Client;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+// How long to keep keys when the issuer doesn't say, and the bounds on what it may say.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(300);
+const MIN_MAX_AGE: Duration = Duration::from_secs(60);
+const MAX_MAX_AGE: Duration = Duration::from_secs(24 * 3600);
+
+// How soon to try again after a failed background refresh.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// How long expired keys are still used while the issuer can't be reached.
+const MAX_STALENESS: Duration = Duration::from_secs(24 * 3600);
+
+// Tokens with unknown key IDs are cheap to make, so they can only trigger a refetch this often.
+const UNKNOWN_KID_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+// Once the keys are too old to use, every request would want to refetch them, so only this often.
+const STALE_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct JsonWebKeySet {
+    #[serde(deserialize_with = "supported_keys")]
+    pub keys: Vec<Jwk>,
+}
+
+impl JsonWebKeySet {
+    pub fn find(&self, kid: &str) -> Option<&Jwk> {
+        self.keys
+            .iter()
+            .find(|k| k.common.key_id.as_deref() == Some(kid))
+    }
+
+    fn kids(&self) -> Vec<&str> {
+        self.keys
+            .iter()
+            .filter_map(|k| k.common.key_id.as_deref())
+            .collect()
+    }
+}
+
+// Issuers may publish keys we don't know how to use (e.g., newer encryption algorithms).
+// Skip those instead of rejecting the whole key set.
+fn supported_keys<'de, D>(deserializer: D) -> Result<Vec<Jwk>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let keys = Vec::<Value>::deserialize(deserializer)?;
+    Ok(keys
+        .into_iter()
+        .filter_map(|key| match serde_json::from_value::<Jwk>(key.clone()) {
+            Ok(jwk) => Some(jwk),
+            Err(e) => {
+                warn!(kid = ?key.get("kid"), error = %e, "skipping unsupported JWK");
+                None
+            }
+        })
+        .collect())
+}
+
+/// Keeps the keys of one issuer fresh.
+///
+/// Keys are refreshed in the background for as long as the issuer's cache headers allow,
+/// refetched right away (at most every 30s) when a token names a key we haven't seen,
+/// and kept around for a while when the issuer can't be reached. Past that, requests fail
+/// and the keys are refetched at most every 30s.
+#[derive(Debug)]
+pub struct JwkManager {
+    cache: RwLock<CachedJwks>,
+    // None when the keys were handed to us and there is nowhere to refresh them from.
+    source: Option<JwksSource>,
+}
+
+#[derive(Debug)]
+struct JwksSource {
+    url: String,
+    client: Client,
+    // When an unknown key ID last made us refetch the keys.
+    last_unknown_kid_refetch: Mutex<Option<Instant>>,
+    // When keys too old to be used last made us refetch them.
+    last_stale_refetch: Mutex<Option<Instant>>,
+}
+
+#[derive(Clone, Debug)]
+struct CachedJwks {
+    jwks: JsonWebKeySet,
+    expires_at: Option<SystemTime>,
+}
+
+impl JwkManager {
+    /// Fetches the keys and starts refreshing them in the background.
+    pub async fn new(jwks_url: String) -> Result<Arc<Self>> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .context("Error creating the JWKs HTTP client")?;
+        let source = JwksSource {
+            url: jwks_url,
+            client,
+            last_unknown_kid_refetch: Mutex::new(None),
+            last_stale_refetch: Mutex::new(None),
+        };
+        let (jwks, max_age) = source
+            .fetch()
+            .await
+            .context("Failed to fetch initial JWKs")?;
+
+        let manager = Arc::new(Self {
+            cache: RwLock::new(CachedJwks {
+                jwks,
+                expires_at: Some(SystemTime::now() + max_age),
+            }),
+            source: Some(source),
+        });
+
+        // The task only holds a weak reference so that it ends with the manager.
+        tokio::spawn(refresh_periodically(Arc::downgrade(&manager), max_age));
+        Ok(manager)
+    }
+
+    /// A manager for a fixed set of keys that is never refreshed.
+    pub fn with_keys(jwks: JsonWebKeySet) -> Arc<Self> {
+        Arc::new(Self {
+            cache: RwLock::new(CachedJwks {
+                jwks,
+                expires_at: None,
+            }),
+            source: None,
+        })
+    }
+
+    /// Looks up the key a token was signed with.
+    ///
+    /// Returns `Ok(None)` when the issuer doesn't have such a key, and an error only when
+    /// we have no usable keys at all.
+    pub async fn find(&self, kid: &str) -> Result<Option<Jwk>> {
+        let Some(source) = &self.source else {
+            return Ok(self.cache.read().await.jwks.find(kid).cloned());
+        };
+
+        if self.is_too_stale().await {
+            // The background refresh has been failing for too long to keep trusting these.
+            self.refresh_stale(source)
+                .await
+                .context("The cached JWKs are too old to be used")?;
+        }
+
+        if let Some(jwk) = self.cache.read().await.jwks.find(kid) {
+            return Ok(Some(jwk.clone()));
+        }
+
+        // The issuer may have rotated its keys since we last fetched them.
+        let mut last_refetch = source.last_unknown_kid_refetch.lock().await;
+        // Someone else may have refetched the keys while we were waiting for the lock.
+        if let Some(jwk) = self.cache.read().await.jwks.find(kid) {
+            return Ok(Some(jwk.clone()));
+        }
+        if last_refetch.is_some_and(|t| t.elapsed() < UNKNOWN_KID_REFETCH_INTERVAL) {
+            return Ok(None);
+        }
+        *last_refetch = Some(Instant::now());
+
+        info!(kid = %kid, "refetching JWKs for an unknown key");
+        if let Err(e) = self.refresh(source).await {
+            warn!(error = %e, "failed to refetch JWKs, using the cached keys");
+        }
+        Ok(self.cache.read().await.jwks.find(kid).cloned())
+    }
+
+    async fn is_too_stale(&self) -> bool {
+        let expires_at = self.cache.read().await.expires_at;
+        expires_at.is_some_and(|t| t + MAX_STALENESS < SystemTime::now())
+    }
+
+    // Refetches keys that are too old to be used, failing fast if that was tried just now.
+    async fn refresh_stale(&self, source: &JwksSource) -> Result<()> {
+        let mut last_refetch = source.last_stale_refetch.lock().await;
+        // Someone else may have refetched the keys while we were waiting for the lock.
+        if !self.is_too_stale().await {
+            return Ok(());
+        }
+        if last_refetch.is_some_and(|t| t.elapsed() < STALE_REFETCH_INTERVAL) {
+            bail!(
+                "The JWKs were refetched less than {}s ago",
+                STALE_REFETCH_INTERVAL.as_secs()
+            );
+        }
+        *last_refetch = Some(Instant::now());
+        self.refresh(source).await?;
+        Ok(())
+    }
+
+    // Fetches the keys and returns how long until they should be fetched again.
+    async fn refresh(&self, source: &JwksSource) -> Result<Duration> {
+        let (jwks, max_age) = source.fetch().await?;
+        let mut cached = self.cache.write().await;
+        cached.jwks = jwks;
+        cached.expires_at = Some(SystemTime::now() + max_age);
+        Ok(max_age)
+    }
+}
+
+async fn refresh_periodically(manager: Weak<JwkManager>, mut delay: Duration) {
+    loop {
+        tokio::time::sleep(delay).await;
+        let Some(manager) = manager.upgrade() else {
+            return;
+        };
+        let Some(source) = &manager.source else {
+            return;
+        };
+        delay = match manager.refresh(source).await {
+            Ok(max_age) => max_age,
+            Err(e) => {
+                // Keep serving the keys we have, the issuer may just be briefly unavailable.
+                warn!(jwks_url = %source.url, error = format!("{:#}", e), "failed to refresh JWKs");
+                RETRY_INTERVAL
+            }
+        };
+    }
+}
+
+impl JwksSource {
+    async fn fetch(&self) -> Result<(JsonWebKeySet, Duration)> {
+        let response = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .with_context(|| format!("Error obtaining JWKs for the OIDC authority {}", self.url))?
+            .error_for_status()
+            .with_context(|| format!("Error obtaining JWKs for the OIDC authority {}", self.url))?;
+        let max_age = cache_lifetime(response.headers(), Utc::now())
+            .unwrap_or(DEFAULT_MAX_AGE)
+            .clamp(MIN_MAX_AGE, MAX_MAX_AGE);
+        let jwks: JsonWebKeySet = response
+            .json()
+            .await
+            .with_context(|| format!("Error parsing JWKs for the OIDC authority {}", self.url))?;
+
+        // Only log the key IDs, there is no need to dump the whole key set.
+        info!(kids = ?jwks.kids(), jwks_url = %self.url, max_age_secs = max_age.as_secs(), "Fetched JWKs");
+        Ok((jwks, max_age))
+    }
+}
+
+// Works out how long a response may be cached for from its Cache-Control or Expires headers.
+fn cache_lifetime(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(cache_control) = header(CACHE_CONTROL) {
+        let mut max_age = None;
+        for directive in cache_control.split(',').map(str::trim) {
+            let directive = directive.to_ascii_lowercase();
+            if directive == "no-cache" || directive == "no-store" {
+                return Some(Duration::ZERO);
+            }
+            if let Some(secs) = directive.strip_prefix("max-age=") {
+                max_age = secs.trim_matches('"').parse::<u64>().ok();
+            }
+        }
+        if let Some(max_age) = max_age {
+            // The response may have been sitting in a shared cache already.
+            let age = header(AGE).and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+            return Some(Duration::from_secs(max_age.saturating_sub(age)));
+        }
+    }
+
+    let expires = DateTime::parse_from_rfc2822(header(EXPIRES)?).ok()?;
+    let date = header(DATE)
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+        .map(|d| d.with_timezone(&Utc))
+        .unwrap_or(now);
+    Some(
+        (expires.with_timezone(&Utc) - date)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+    use serde_json::json;
+    use std::sync::Mutex as StdMutex;
+
+    #[derive(Default)]
+    struct Issuer {
+        kids: Vec<&'static str>,
+        status: u16,
+        hits: usize,
+    }
+
+    type SharedIssuer = Arc<StdMutex<Issuer>>;
+
+    async fn serve_jwks(State(issuer): State<SharedIssuer>) -> (StatusCode, Json<Value>) {
+        let mut issuer = issuer.lock().unwrap();
+        issuer.hits += 1;
+        let keys: Vec<Value> = issuer
+            .kids
+            .iter()
+            .map(|kid| json!({ "kty": "oct", "k": "c2VjcmV0", "kid": kid }))
+            .collect();
+        (
+            StatusCode::from_u16(issuer.status).unwrap(),
+            Json(json!({ "keys": keys })),
+        )
+    }
+
+    async fn start_issuer(kids: Vec<&'static str>) -> (String, SharedIssuer) {
+        let issuer = Arc::new(StdMutex::new(Issuer {
+            kids,
+            status: 200,
+            hits: 0,
+        }));
+        let app = Router::new()
+            .route("/jwks", get(serve_jwks))
+            .with_state(Arc::clone(&issuer));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/jwks", addr), issuer)
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn reads_the_cache_lifetime_from_the_response_headers() {
+        let now = Utc::now();
+        let lifetime = |pairs: &[(&'static str, &str)]| cache_lifetime(&headers(pairs), now);
+
+        assert_eq!(lifetime(&[]), None);
+        assert_eq!(
+            lifetime(&[("cache-control", "public, max-age=3600")]),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            lifetime(&[("cache-control", "max-age=3600"), ("age", "600")]),
+            Some(Duration::from_secs(3000))
+        );
+        assert_eq!(
+            lifetime(&[("cache-control", "no-cache, max-age=3600")]),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            lifetime(&[
+                ("date", "Wed, 21 Oct 2015 07:28:00 GMT"),
+                ("expires", "Wed, 21 Oct 2015 09:28:00 GMT"),
+            ]),
+            Some(Duration::from_secs(7200))
+        );
+        assert_eq!(lifetime(&[("expires", "0")]), None);
+    }
+
+    #[tokio::test]
+    async fn refetches_when_a_key_is_unknown() {
+        let (url, issuer) = start_issuer(vec!["old"]).await;
+        let manager = JwkManager::new(url).await.unwrap();
+        assert!(manager.find("old").await.unwrap().is_some());
+        assert_eq!(issuer.lock().unwrap().hits, 1);
+
+        // The issuer rotates its keys.
+        issuer.lock().unwrap().kids = vec!["old", "new"];
+        assert!(manager.find("new").await.unwrap().is_some());
+        assert_eq!(issuer.lock().unwrap().hits, 2);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_refetches_for_unknown_keys() {
+        let (url, issuer) = start_issuer(vec!["kid"]).await;
+        let manager = JwkManager::new(url).await.unwrap();
+
+        for _ in 0..5 {
+            assert!(manager.find("bogus").await.unwrap().is_none());
+        }
+        // The initial fetch plus a single refetch.
+        assert_eq!(issuer.lock().unwrap().hits, 2);
+    }
+
+    #[tokio::test]
+    async fn serves_stale_keys_while_the_issuer_is_unavailable() {
+        let (url, issuer) = start_issuer(vec!["kid"]).await;
+        let manager = JwkManager::new(url).await.unwrap();
+        issuer.lock().unwrap().status = 503;
+
+        let source = manager.source.as_ref().unwrap();
+        assert!(manager.refresh(source).await.is_err());
+        assert!(manager.find("kid").await.unwrap().is_some());
+
+        // Past the staleness limit the keys can't be used anymore.
+        manager.cache.write().await.expires_at =
+            Some(SystemTime::now() - MAX_STALENESS - Duration::from_secs(1));
+        assert!(manager.find("kid").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rate_limits_refetches_of_stale_keys() {
+        let (url, issuer) = start_issuer(vec!["kid"]).await;
+        let manager = JwkManager::new(url).await.unwrap();
+        issuer.lock().unwrap().status = 503;
+        manager.cache.write().await.expires_at =
+            Some(SystemTime::now() - MAX_STALENESS - Duration::from_secs(1));
+
+        for _ in 0..5 {
+            assert!(manager.find("kid").await.is_err());
+        }
+        // The initial fetch plus a single refetch.
+        assert_eq!(issuer.lock().unwrap().hits, 2);
+
+        // Once the interval has passed and the issuer is back, the keys can be used again.
+        *manager
+            .source
+            .as_ref()
+            .unwrap()
+            .last_stale_refetch
+            .lock()
+            .await = Some(Instant::now() - STALE_REFETCH_INTERVAL);
+        issuer.lock().unwrap().status = 200;
+        assert!(manager.find("kid").await.unwrap().is_some());
+        assert_eq!(issuer.lock().unwrap().hits, 3);
+    }
+
+    #[tokio::test]
+    async fn never_refreshes_static_keys() {
+        let jwks: JsonWebKeySet = serde_json::from_value(
+            json!({ "keys": [{ "kty": "oct", "k": "c2VjcmV0", "kid": "kid" }] }),
+        )
+        .unwrap();
+        let manager = JwkManager::with_keys(jwks);
+        assert!(manager.find("kid").await.unwrap().is_some());
+        assert!(manager.find("other").await.unwrap().is_none());
+    }
+}
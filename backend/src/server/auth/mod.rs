@@ -0,0 +1,77 @@
+mod api_keys; // API keys for automation.
+mod claims; // Provider-agnostic token claims.
+mod dev; // Built-in issuer for local development.
+mod jwks; // JWKs fetching and caching.
+mod oidc; // Trusted issuers and OIDC discovery.
+mod policy; // Per-route authorization.
+mod revocation; // Revoked tokens.
+mod verifier; // Token validation.
+
+#[cfg(test)]
+mod tests;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+pub use api_keys::{ApiKey, ApiKeyStore, NewApiKey};
+pub use claims::{ClaimMapping, Claims};
+pub use dev::{DevIssuer, DevToken, DevTokenError, DevTokenRequest};
+pub use oidc::{IssuerConfig, OidcConfig};
+pub use policy::{authorize, groups, scopes, AuthenticatedUser, Policy};
+pub use revocation::{RevocationList, Revocations};
+pub use verifier::{AuthError, TokenVerifier};
+
+// Header scripts can send their API key in, as an alternative to `Authorization: Bearer`.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+fn extract_token(req: &Request) -> Result<&str, AuthError> {
+    if let Some(api_key) = req.headers().get(API_KEY_HEADER) {
+        return api_key.to_str().map_err(|_| AuthError::MissingToken);
+    }
+
+    let auth_header = req
+        .headers()
+        .get("Authorization")
+        .ok_or(AuthError::MissingToken)?
+        .to_str()
+        .map_err(|_| AuthError::MissingToken)?;
+
+    auth_header
+        .strip_prefix("Bearer ")
+        .ok_or(AuthError::MissingToken)
+}
+
+#[instrument(
+    name = "verify_jwt",
+    skip(verifier, req, next),  // Skip complex types
+    err
+)]
+pub async fn verify_jwt(
+    State(verifier): State<Arc<TokenVerifier>>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let token = match extract_token(&req) {
+        Ok(t) => {
+            info!("token extracted successfully");
+            t
+        }
+        Err(e) => {
+            warn!("token extraction failed");
+            return Err(e);
+        }
+    };
+
+    let claims = verifier.verify(token).await.inspect_err(|e| {
+        warn!(error = %e, "token rejected");
+    })?;
+
+    // Store claims in request extensions for handlers to access.
+    req.extensions_mut().insert(claims);
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,206 @@
+use anyhow::{bail, Context, Result};
+use jsonwebtoken::Algorithm;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::claims::ClaimMapping;
+use super::jwks::JsonWebKeySet;
+
+/// The OIDC issuers we accept tokens from.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OidcConfig {
+    pub issuers: Vec<IssuerConfig>,
+}
+
+/// Everything we need to know to trust the tokens of one issuer.
+///
+/// For example, a self-hosted Keycloak realm:
+///
+/// ```json
+/// {
+///   "issuer": "http://localhost:8081/realms/kamekai",
+///   "allowed_algorithms": ["RS256", "ES256"],
+///   "leeway": 30,
+///   "client_ids": ["kamekai-dev"],
+///   "audiences": [],
+///   "expected_claims": { "typ": "Bearer" },
+///   "claim_mapping": {
+///     "client_id": "azp",
+///     "username": "preferred_username",
+///     "groups": "realm_access.roles"
+///   }
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IssuerConfig {
+    /// The issuer URL, as it shows up in the `iss` claim.
+    pub issuer: String,
+
+    /// Skip discovery and fetch the keys from here instead.
+    #[serde(default)]
+    pub jwks_uri: Option<String>,
+
+    /// Trust exactly these keys, without discovery nor fetching anything.
+    #[serde(default)]
+    pub jwks: Option<JsonWebKeySet>,
+
+    /// Signing algorithms accepted from this issuer. Symmetric (HS*) algorithms are not allowed.
+    #[serde(default = "default_allowed_algorithms")]
+    pub allowed_algorithms: Vec<Algorithm>,
+
+    /// Clock skew tolerated when checking `exp` and `nbf`, in seconds.
+    #[serde(default = "default_leeway")]
+    pub leeway: u64,
+
+    /// Accepted values for the client ID claim. An empty list accepts any client.
+    #[serde(default)]
+    pub client_ids: Vec<String>,
+
+    /// Accepted values for the `aud` claim. An empty list skips the audience check.
+    #[serde(default)]
+    pub audiences: Vec<String>,
+
+    /// Claims that must be present in every token.
+    #[serde(default = "default_required_claims")]
+    pub required_claims: Vec<String>,
+
+    /// Claims that must be present and hold exactly these values.
+    #[serde(default)]
+    pub expected_claims: HashMap<String, Value>,
+
+    #[serde(default)]
+    pub claim_mapping: ClaimMapping,
+}
+
+fn default_allowed_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::RS256]
+}
+
+fn default_leeway() -> u64 {
+    60
+}
+
+fn default_required_claims() -> Vec<String> {
+    ["sub", "iss", "exp", "iat"]
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+}
+
+impl OidcConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Error reading OIDC config {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Error parsing OIDC config {}", path.display()))
+    }
+}
+
+impl IssuerConfig {
+    /// Settings for the access tokens issued by a Cognito user pool.
+    /// `user_pool` is the pool endpoint, e.g. `cognito-idp.us-east-1.amazonaws.com/us-east-1_abc`.
+    pub fn cognito(user_pool: &str, client_id: String) -> Self {
+        Self {
+            issuer: format!("https://{}", user_pool.trim_end_matches('/')),
+            jwks_uri: None,
+            jwks: None,
+            allowed_algorithms: default_allowed_algorithms(),
+            leeway: default_leeway(),
+            client_ids: vec![client_id],
+            audiences: vec![],
+            required_claims: [
+                "sub",
+                "iss",
+                "client_id",
+                "origin_jti",
+                "event_id",
+                "token_use",
+                "scope",
+                "auth_time",
+                "exp",
+                "iat",
+                "username",
+            ]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+            expected_claims: HashMap::from([(
+                "token_use".to_string(),
+                Value::String("access".to_string()),
+            )]),
+            claim_mapping: ClaimMapping {
+                groups: "cognito:groups".to_string(),
+                ..ClaimMapping::default()
+            },
+        }
+    }
+
+    /// Catches settings that would make token validation unsafe.
+    pub fn validate(&self) -> Result<()> {
+        if self.allowed_algorithms.is_empty() {
+            bail!("Issuer {} allows no signing algorithms", self.issuer);
+        }
+        // Verifying HMAC signatures with keys published in a JWKS would let anyone forge tokens.
+        if let Some(alg) = self
+            .allowed_algorithms
+            .iter()
+            .find(|alg| matches!(alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512))
+        {
+            bail!(
+                "Issuer {} allows the symmetric algorithm {:?}",
+                self.issuer,
+                alg
+            );
+        }
+        Ok(())
+    }
+
+    /// Figures out where the issuer publishes its keys, using OIDC discovery unless
+    /// the JWKS URI was configured explicitly.
+    pub async fn resolve_jwks_uri(&self, client: &Client) -> Result<String> {
+        if let Some(jwks_uri) = &self.jwks_uri {
+            return Ok(jwks_uri.clone());
+        }
+
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let document: DiscoveryDocument = client
+            .get(&discovery_url)
+            .send()
+            .await
+            .with_context(|| format!("Error fetching OIDC discovery document {}", discovery_url))?
+            .error_for_status()
+            .with_context(|| format!("Error fetching OIDC discovery document {}", discovery_url))?
+            .json()
+            .await
+            .with_context(|| format!("Error parsing OIDC discovery document {}", discovery_url))?;
+
+        // The discovery spec requires the issuer in the document to match exactly the one we
+        // asked about, otherwise someone could be impersonating it.
+        if document.issuer != self.issuer {
+            bail!(
+                "OIDC discovery document {} is for issuer {} instead of {}",
+                discovery_url,
+                document.issuer,
+                self.issuer
+            );
+        }
+
+        tracing::info!(issuer = %self.issuer, jwks_uri = %document.jwks_uri, "discovered OIDC issuer");
+        Ok(document.jwks_uri)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscoveryDocument {
+    issuer: String,
+    jwks_uri: String,
+}
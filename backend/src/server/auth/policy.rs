@@ -0,0 +1,129 @@
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+use std::ops::Deref;
+use std::sync::Arc;
+use tracing::warn;
+
+use super::claims::Claims;
+use super::verifier::AuthError;
+
+/// The scopes our own API defines.
+pub mod scopes {
+    /// Translating text, synchronously or through jobs.
+    pub const TRANSLATE: &str = "kamekai/translate";
+    /// Lessons and the other learning features.
+    pub const LEARN: &str = "kamekai/learn";
+}
+
+/// The groups with special privileges.
+pub mod groups {
+    pub const ADMIN: &str = "admin";
+}
+
+/// The caller of a protected route, as vouched for by its access token.
+///
+/// Extracting it from a request that didn't go through `verify_jwt` is a 401.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser(Claims);
+
+impl AuthenticatedUser {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.0.scopes.iter().any(|s| s == scope)
+    }
+
+    pub fn in_group(&self, group: &str) -> bool {
+        self.0.groups.iter().any(|g| g == group)
+    }
+}
+
+impl Deref for AuthenticatedUser {
+    type Target = Claims;
+
+    fn deref(&self) -> &Claims {
+        &self.0
+    }
+}
+
+impl From<Claims> for AuthenticatedUser {
+    fn from(claims: Claims) -> Self {
+        Self(claims)
+    }
+}
+
+impl<S> FromRequestParts<S> for AuthenticatedUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Claims>()
+            .cloned()
+            .map(Self)
+            .ok_or(AuthError::MissingToken)
+    }
+}
+
+/// What a caller needs in order to use a route.
+///
+/// Every listed scope is required, and when groups are listed the caller must belong
+/// to at least one of them.
+///
+/// ```ignore
+/// let admins_only = Policy::new().require_any_group(&[groups::ADMIN]);
+/// router.route_layer(middleware::from_fn_with_state(Arc::new(admins_only), authorize))
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    scopes: Vec<String>,
+    groups: Vec<String>,
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require_scope(mut self, scope: &str) -> Self {
+        self.scopes.push(scope.to_string());
+        self
+    }
+
+    pub fn require_any_group(mut self, groups: &[&str]) -> Self {
+        self.groups.extend(groups.iter().map(|g| g.to_string()));
+        self
+    }
+
+    pub fn check(&self, user: &AuthenticatedUser) -> Result<(), AuthError> {
+        if let Some(missing) = self.scopes.iter().find(|s| !user.has_scope(s)) {
+            return Err(AuthError::InsufficientScope(missing.clone()));
+        }
+        if !self.groups.is_empty() && !self.groups.iter().any(|g| user.in_group(g)) {
+            return Err(AuthError::Forbidden(format!(
+                "requires membership in one of {:?}",
+                self.groups
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Rejects callers that don't satisfy the route's policy.
+/// Must run after `verify_jwt`, i.e., be added as an inner layer.
+pub async fn authorize(
+    State(policy): State<Arc<Policy>>,
+    user: AuthenticatedUser,
+    req: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    policy.check(&user).inspect_err(|e| {
+        warn!(user.id = %user.sub, error = %e, "request not authorized");
+    })?;
+    Ok(next.run(req).await)
+}
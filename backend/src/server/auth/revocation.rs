@@ -0,0 +1,146 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use super::claims::Claims;
+use crate::json_file;
+
+/// A revoked token, or every token refreshed from the same sign in (`origin_jti`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RevokedToken {
+    pub revoked_at: DateTime<Utc>,
+    /// The entry is dropped after this, once the tokens it covers have expired anyway.
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Revocations {
+    /// Revoked `jti` and `origin_jti` values.
+    pub tokens: HashMap<String, RevokedToken>,
+    /// Per user, tokens issued before this time are rejected.
+    pub users: HashMap<String, DateTime<Utc>>,
+}
+
+/// Tokens that are no longer accepted even though they haven't expired.
+///
+/// Persisted to a JSON file so that revocations survive restarts.
+#[derive(Debug)]
+pub struct RevocationList {
+    path: Option<PathBuf>,
+    revocations: RwLock<Revocations>,
+    // Held from reading the revocations to be updated until they're written, so that no update
+    // is lost. Checks don't wait on it, only on the new revocations being swapped in.
+    writer: Mutex<()>,
+}
+
+impl RevocationList {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let revocations = json_file::read(&path)?;
+        Ok(Self {
+            path: Some(path),
+            revocations: RwLock::new(revocations),
+            writer: Mutex::new(()),
+        })
+    }
+
+    /// A list that is forgotten on restart.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            revocations: RwLock::new(Revocations::default()),
+            writer: Mutex::new(()),
+        }
+    }
+
+    pub fn revocations(&self) -> Revocations {
+        self.revocations
+            .read()
+            .expect("revocation list lock poisoned")
+            .clone()
+    }
+
+    pub async fn revoke_token(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<()> {
+        info!(jti = %jti, "revoking token");
+        self.update(|revocations| {
+            revocations.tokens.insert(
+                jti.to_string(),
+                RevokedToken {
+                    revoked_at: Utc::now(),
+                    expires_at,
+                },
+            );
+        })
+        .await
+    }
+
+    /// Rejects every token of `sub` issued before `issued_before`.
+    pub async fn revoke_user(&self, sub: &str, issued_before: DateTime<Utc>) -> Result<()> {
+        info!(user.id = %sub, issued_before = %issued_before, "revoking user tokens");
+        self.update(|revocations| {
+            revocations.users.insert(sub.to_string(), issued_before);
+        })
+        .await
+    }
+
+    /// Returns whether there was a cutoff to remove.
+    pub async fn clear_user(&self, sub: &str) -> Result<bool> {
+        let mut removed = false;
+        self.update(|revocations| {
+            removed = revocations.users.remove(sub).is_some();
+        })
+        .await?;
+        Ok(removed)
+    }
+
+    /// Tells why the token is revoked, if it is.
+    pub fn check(&self, claims: &Claims) -> Option<String> {
+        let revocations = self
+            .revocations
+            .read()
+            .expect("revocation list lock poisoned");
+
+        for id in [&claims.jti, &claims.origin_jti].into_iter().flatten() {
+            if revocations.tokens.contains_key(id) {
+                return Some(format!("token {} was revoked", id));
+            }
+        }
+        if let Some(cutoff) = revocations.users.get(&claims.sub) {
+            // Without an issue date there is no telling whether the token predates the cutoff.
+            if claims.iat.is_none_or(|iat| iat < cutoff.timestamp()) {
+                return Some(format!("tokens issued before {} were revoked", cutoff));
+            }
+        }
+        None
+    }
+
+    // Writes the updated revocations off the async runtime, then swaps them in.
+    async fn update(&self, f: impl FnOnce(&mut Revocations)) -> Result<()> {
+        let _writer = self.writer.lock().await;
+        let mut updated = self.revocations();
+        f(&mut updated);
+        let now = Utc::now();
+        updated.tokens.retain(|_, token| token.expires_at > now);
+
+        let updated = match &self.path {
+            Some(path) => {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    json_file::write(&path, &updated).map(|_| updated)
+                })
+                .await??
+            }
+            None => updated,
+        };
+        *self
+            .revocations
+            .write()
+            .expect("revocation list lock poisoned") = updated;
+        Ok(())
+    }
+}
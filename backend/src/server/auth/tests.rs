@@ -0,0 +1,798 @@
+//! Token validation and authorization tests.
+//!
+//! Keys are generated locally: EC keys on the fly and RSA from a throwaway test fixture.
+
+use axum::body::Body;
+use axum::http::{header::WWW_AUTHENTICATE, Request, StatusCode};
+use axum::{middleware, routing::get, Router};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING, ECDSA_P384_SHA384_FIXED_SIGNING,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use super::dev::MAX_EXPIRES_IN;
+use super::jwks::JsonWebKeySet;
+use super::{
+    authorize, extract_token, groups, verify_jwt, ApiKeyStore, AuthError, AuthenticatedUser,
+    ClaimMapping, DevIssuer, DevTokenError, DevTokenRequest, IssuerConfig, NewApiKey, OidcConfig,
+    Policy, RevocationList, TokenVerifier, API_KEY_HEADER,
+};
+
+const ISSUER: &str = "https://issuer.example.com/realm";
+const CLIENT_ID: &str = "client-1";
+
+const RSA_PRIVATE_KEY: &str = include_str!("testdata/rsa_private.pem");
+const RSA_PUBLIC_KEY: &str = include_str!("testdata/rsa_public.json");
+
+struct Signer {
+    kid: String,
+    alg: Algorithm,
+    key: EncodingKey,
+    jwk: Value,
+}
+
+impl Signer {
+    fn rsa(kid: &str, alg: Algorithm) -> Self {
+        let public: Value = serde_json::from_str(RSA_PUBLIC_KEY).unwrap();
+        Self {
+            kid: kid.to_string(),
+            alg,
+            key: EncodingKey::from_rsa_pem(RSA_PRIVATE_KEY.as_bytes()).unwrap(),
+            jwk: json!({
+                "kty": "RSA",
+                "kid": kid,
+                "use": "sig",
+                "alg": format!("{:?}", alg),
+                "n": public["n"],
+                "e": public["e"],
+            }),
+        }
+    }
+
+    fn ec(kid: &str, alg: Algorithm) -> Self {
+        let (signing_alg, curve) = match alg {
+            Algorithm::ES256 => (&ECDSA_P256_SHA256_FIXED_SIGNING, "P-256"),
+            Algorithm::ES384 => (&ECDSA_P384_SHA384_FIXED_SIGNING, "P-384"),
+            _ => panic!("not an EC algorithm: {:?}", alg),
+        };
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(signing_alg, &rng).unwrap();
+        let key_pair = EcdsaKeyPair::from_pkcs8(signing_alg, pkcs8.as_ref(), &rng).unwrap();
+
+        // Uncompressed point: 0x04 || x || y.
+        let point = &key_pair.public_key().as_ref()[1..];
+        let (x, y) = point.split_at(point.len() / 2);
+        Self {
+            kid: kid.to_string(),
+            alg,
+            key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+            jwk: json!({
+                "kty": "EC",
+                "kid": kid,
+                "use": "sig",
+                "crv": curve,
+                "x": URL_SAFE_NO_PAD.encode(x),
+                "y": URL_SAFE_NO_PAD.encode(y),
+            }),
+        }
+    }
+
+    fn sign(&self, claims: &Value) -> String {
+        self.sign_as(self.alg, claims)
+    }
+
+    // Signs with this key but claims a different algorithm in the header.
+    fn sign_as(&self, alg: Algorithm, claims: &Value) -> String {
+        let mut header = Header::new(alg);
+        header.kid = Some(self.kid.clone());
+        encode(&header, claims, &self.key).unwrap()
+    }
+}
+
+fn issuer_config(signers: &[&Signer]) -> IssuerConfig {
+    let keys: Vec<Value> = signers.iter().map(|s| s.jwk.clone()).collect();
+    let jwks: JsonWebKeySet = serde_json::from_value(json!({ "keys": keys })).unwrap();
+    IssuerConfig {
+        issuer: ISSUER.to_string(),
+        jwks_uri: None,
+        jwks: Some(jwks),
+        allowed_algorithms: vec![
+            Algorithm::RS256,
+            Algorithm::PS256,
+            Algorithm::ES256,
+            Algorithm::ES384,
+        ],
+        leeway: 60,
+        client_ids: vec![CLIENT_ID.to_string()],
+        audiences: vec![],
+        required_claims: vec!["sub".to_string(), "iat".to_string()],
+        expected_claims: HashMap::new(),
+        claim_mapping: ClaimMapping::default(),
+    }
+}
+
+async fn verifier(config: IssuerConfig) -> TokenVerifier {
+    TokenVerifier::new(OidcConfig {
+        issuers: vec![config],
+    })
+    .await
+    .unwrap()
+}
+
+fn claims() -> Value {
+    let now = get_current_timestamp();
+    json!({
+        "sub": "user-1",
+        "iss": ISSUER,
+        "client_id": CLIENT_ID,
+        "scope": "kamekai/translate kamekai/lessons",
+        "jti": "token-1",
+        "iat": now,
+        "exp": now + 300,
+    })
+}
+
+fn with(mut claims: Value, name: &str, value: Value) -> Value {
+    claims[name] = value;
+    claims
+}
+
+fn without(mut claims: Value, name: &str) -> Value {
+    claims.as_object_mut().unwrap().remove(name);
+    claims
+}
+
+#[tokio::test]
+async fn accepts_every_supported_algorithm() {
+    let signers = [
+        Signer::rsa("rs256", Algorithm::RS256),
+        Signer::rsa("ps256", Algorithm::PS256),
+        Signer::ec("es256", Algorithm::ES256),
+        Signer::ec("es384", Algorithm::ES384),
+    ];
+    let verifier = verifier(issuer_config(&signers.iter().collect::<Vec<_>>())).await;
+
+    for signer in &signers {
+        let claims = verifier.verify(&signer.sign(&claims())).await.unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.iss, ISSUER);
+        assert_eq!(claims.scopes, vec!["kamekai/translate", "kamekai/lessons"]);
+        assert_eq!(claims.jti.as_deref(), Some("token-1"));
+    }
+}
+
+#[tokio::test]
+async fn maps_provider_specific_claims() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let mut config = issuer_config(&[&signer]);
+    config.claim_mapping = ClaimMapping {
+        client_id: "azp".to_string(),
+        username: "preferred_username".to_string(),
+        groups: "realm_access.roles".to_string(),
+        ..ClaimMapping::default()
+    };
+    let verifier = verifier(config).await;
+
+    let token = signer.sign(&json!({
+        "sub": "user-1",
+        "iss": ISSUER,
+        "azp": CLIENT_ID,
+        "preferred_username": "dev",
+        "realm_access": { "roles": ["admin", "learner"] },
+        "iat": get_current_timestamp(),
+        "exp": get_current_timestamp() + 300,
+    }));
+    let claims = verifier.verify(&token).await.unwrap();
+    assert_eq!(claims.client_id.as_deref(), Some(CLIENT_ID));
+    assert_eq!(claims.username.as_deref(), Some("dev"));
+    assert_eq!(claims.groups, vec!["admin", "learner"]);
+}
+
+#[test]
+fn rejects_requests_without_a_bearer_token() {
+    let request = axum::http::Request::builder()
+        .body(axum::body::Body::empty())
+        .unwrap();
+    assert!(matches!(
+        extract_token(&request),
+        Err(AuthError::MissingToken)
+    ));
+
+    let request = axum::http::Request::builder()
+        .header("Authorization", "Basic dXNlcjpwYXNz")
+        .body(axum::body::Body::empty())
+        .unwrap();
+    assert!(matches!(
+        extract_token(&request),
+        Err(AuthError::MissingToken)
+    ));
+}
+
+#[tokio::test]
+async fn rejects_malformed_tokens() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    let result = verifier.verify("not-a-jwt").await;
+    assert!(matches!(result, Err(AuthError::MalformedToken(_))));
+}
+
+#[tokio::test]
+async fn rejects_unsigned_tokens() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"none","kid":"kid"}"#);
+    let payload = URL_SAFE_NO_PAD.encode(claims().to_string());
+    let result = verifier.verify(&format!("{}.{}.", header, payload)).await;
+    assert!(matches!(result, Err(AuthError::MalformedToken(_))));
+}
+
+#[tokio::test]
+async fn rejects_tokens_without_a_kid() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    let token = encode(&Header::new(Algorithm::ES256), &claims(), &signer.key).unwrap();
+    let result = verifier.verify(&token).await;
+    assert!(matches!(result, Err(AuthError::MalformedToken(_))));
+}
+
+#[tokio::test]
+async fn rejects_tokens_without_an_issuer() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    let result = verifier
+        .verify(&signer.sign(&without(claims(), "iss")))
+        .await;
+    assert!(matches!(result, Err(AuthError::MissingClaim(c)) if c == "iss"));
+}
+
+#[tokio::test]
+async fn rejects_untrusted_issuers() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    for iss in [
+        "https://evil.example.com/realm",
+        "https://issuer.example.com/realm/",
+        "http://issuer.example.com/realm",
+    ] {
+        let token = signer.sign(&with(claims(), "iss", json!(iss)));
+        let result = verifier.verify(&token).await;
+        assert!(
+            matches!(result, Err(AuthError::UntrustedIssuer(_))),
+            "accepted issuer {}",
+            iss
+        );
+    }
+}
+
+#[tokio::test]
+async fn rejects_algorithms_outside_the_allowlist() {
+    let signer = Signer::rsa("kid", Algorithm::RS256);
+    let mut config = issuer_config(&[&signer]);
+    config.allowed_algorithms = vec![Algorithm::PS256];
+    let verifier = verifier(config).await;
+
+    let result = verifier.verify(&signer.sign(&claims())).await;
+    assert!(matches!(
+        result,
+        Err(AuthError::DisallowedAlgorithm(Algorithm::RS256))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_hmac_tokens() {
+    let signer = Signer::rsa("kid", Algorithm::RS256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    // The classic algorithm confusion attack: use the public key as an HMAC secret.
+    let mut header = Header::new(Algorithm::HS256);
+    header.kid = Some("kid".to_string());
+    let key = EncodingKey::from_secret(RSA_PUBLIC_KEY.as_bytes());
+    let token = encode(&header, &claims(), &key).unwrap();
+
+    let result = verifier.verify(&token).await;
+    assert!(matches!(
+        result,
+        Err(AuthError::DisallowedAlgorithm(Algorithm::HS256))
+    ));
+}
+
+#[tokio::test]
+async fn refuses_to_allow_symmetric_algorithms() {
+    let signer = Signer::rsa("kid", Algorithm::RS256);
+    let mut config = issuer_config(&[&signer]);
+    config.allowed_algorithms.push(Algorithm::HS256);
+
+    let result = TokenVerifier::new(OidcConfig {
+        issuers: vec![config],
+    })
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn rejects_unknown_keys() {
+    let trusted = Signer::ec("trusted", Algorithm::ES256);
+    let unknown = Signer::ec("unknown", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&trusted])).await;
+
+    let result = verifier.verify(&unknown.sign(&claims())).await;
+    assert!(matches!(result, Err(AuthError::UnknownKey(kid)) if kid == "unknown"));
+}
+
+#[tokio::test]
+async fn rejects_keys_of_the_wrong_type() {
+    let rsa = Signer::rsa("rsa", Algorithm::RS256);
+    let ec = Signer::ec("ec", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&rsa, &ec])).await;
+
+    // An EC signature claiming to come from the RSA key.
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some("rsa".to_string());
+    let token = encode(&header, &claims(), &ec.key).unwrap();
+
+    let result = verifier.verify(&token).await;
+    assert!(matches!(result, Err(AuthError::KeyMismatch(_))));
+}
+
+#[tokio::test]
+async fn rejects_curves_that_do_not_match_the_algorithm() {
+    let p256 = Signer::ec("p256", Algorithm::ES256);
+    let p384 = Signer::ec("p384", Algorithm::ES384);
+    let verifier = verifier(issuer_config(&[&p256])).await;
+
+    // A P-384 signature claiming to come from the P-256 key.
+    let mut header = Header::new(Algorithm::ES384);
+    header.kid = Some("p256".to_string());
+    let token = encode(&header, &claims(), &p384.key).unwrap();
+
+    let result = verifier.verify(&token).await;
+    assert!(matches!(result, Err(AuthError::KeyMismatch(_))));
+}
+
+#[tokio::test]
+async fn rejects_algorithms_the_key_is_not_declared_for() {
+    // The JWK says RS256, the token says PS256. Both are allowed for the issuer.
+    let signer = Signer::rsa("kid", Algorithm::RS256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    let result = verifier
+        .verify(&signer.sign_as(Algorithm::PS256, &claims()))
+        .await;
+    assert!(matches!(result, Err(AuthError::KeyMismatch(_))));
+}
+
+#[tokio::test]
+async fn rejects_encryption_keys() {
+    let mut signer = Signer::ec("kid", Algorithm::ES256);
+    signer.jwk["use"] = json!("enc");
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    let result = verifier.verify(&signer.sign(&claims())).await;
+    assert!(matches!(result, Err(AuthError::KeyMismatch(_))));
+}
+
+#[tokio::test]
+async fn rejects_forged_signatures() {
+    let trusted = Signer::ec("kid", Algorithm::ES256);
+    let forger = Signer::ec("kid", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&trusted])).await;
+
+    let result = verifier.verify(&forger.sign(&claims())).await;
+    assert!(matches!(result, Err(AuthError::InvalidToken(_))));
+}
+
+#[tokio::test]
+async fn applies_the_configured_leeway_to_expiration() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let mut config = issuer_config(&[&signer]);
+    config.leeway = 60;
+    let verifier = verifier(config).await;
+    let now = get_current_timestamp();
+
+    let within_leeway = signer.sign(&with(claims(), "exp", json!(now - 30)));
+    assert!(verifier.verify(&within_leeway).await.is_ok());
+
+    let expired = signer.sign(&with(claims(), "exp", json!(now - 120)));
+    let result = verifier.verify(&expired).await;
+    assert!(matches!(result, Err(AuthError::InvalidToken(_))));
+}
+
+#[tokio::test]
+async fn rejects_tokens_without_an_expiration() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    let result = verifier
+        .verify(&signer.sign(&without(claims(), "exp")))
+        .await;
+    assert!(matches!(result, Err(AuthError::InvalidToken(_))));
+}
+
+#[tokio::test]
+async fn rejects_tokens_that_are_not_valid_yet() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let mut config = issuer_config(&[&signer]);
+    config.leeway = 10;
+    let verifier = verifier(config).await;
+    let now = get_current_timestamp();
+
+    let within_leeway = signer.sign(&with(claims(), "nbf", json!(now + 5)));
+    assert!(verifier.verify(&within_leeway).await.is_ok());
+
+    let immature = signer.sign(&with(claims(), "nbf", json!(now + 300)));
+    let result = verifier.verify(&immature).await;
+    assert!(matches!(result, Err(AuthError::InvalidToken(_))));
+}
+
+#[tokio::test]
+async fn enforces_the_audience_when_configured() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let mut config = issuer_config(&[&signer]);
+    config.audiences = vec!["kamekai-api".to_string()];
+    let verifier = verifier(config).await;
+
+    let good = signer.sign(&with(claims(), "aud", json!(["account", "kamekai-api"])));
+    assert!(verifier.verify(&good).await.is_ok());
+
+    let wrong = signer.sign(&with(claims(), "aud", json!("account")));
+    let result = verifier.verify(&wrong).await;
+    assert!(matches!(result, Err(AuthError::InvalidToken(_))));
+
+    let missing = signer.sign(&claims());
+    let result = verifier.verify(&missing).await;
+    assert!(matches!(result, Err(AuthError::InvalidToken(_))));
+}
+
+#[tokio::test]
+async fn rejects_tokens_missing_required_claims() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let mut config = issuer_config(&[&signer]);
+    config.required_claims.push("jti".to_string());
+    let verifier = verifier(config).await;
+
+    let result = verifier
+        .verify(&signer.sign(&without(claims(), "jti")))
+        .await;
+    assert!(matches!(result, Err(AuthError::MissingClaim(c)) if c == "jti"));
+
+    let result = verifier
+        .verify(&signer.sign(&without(claims(), "sub")))
+        .await;
+    assert!(matches!(
+        result,
+        Err(AuthError::InvalidToken(_) | AuthError::MissingClaim(_))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_unexpected_claim_values() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let mut config = issuer_config(&[&signer]);
+    config
+        .expected_claims
+        .insert("token_use".to_string(), json!("access"));
+    let verifier = verifier(config).await;
+
+    let access = signer.sign(&with(claims(), "token_use", json!("access")));
+    assert!(verifier.verify(&access).await.is_ok());
+
+    let id = signer.sign(&with(claims(), "token_use", json!("id")));
+    let result = verifier.verify(&id).await;
+    assert!(matches!(result, Err(AuthError::UnexpectedClaim(c)) if c == "token_use"));
+}
+
+#[tokio::test]
+async fn rejects_tokens_issued_to_other_clients() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let verifier = verifier(issuer_config(&[&signer])).await;
+
+    let other = signer.sign(&with(claims(), "client_id", json!("client-2")));
+    let result = verifier.verify(&other).await;
+    assert!(matches!(result, Err(AuthError::UnexpectedClaim(c)) if c == "client_id"));
+
+    let missing = signer.sign(&without(claims(), "client_id"));
+    let result = verifier.verify(&missing).await;
+    assert!(matches!(result, Err(AuthError::UnexpectedClaim(_))));
+}
+
+// A route that requires the given policy, behind the token verification middleware.
+async fn protected_app(signer: &Signer, policy: Policy) -> Router {
+    let verifier = Arc::new(verifier(issuer_config(&[signer])).await);
+    Router::new()
+        .route(
+            "/",
+            get(|user: AuthenticatedUser| async move { user.sub.clone() }),
+        )
+        .route_layer(middleware::from_fn_with_state(Arc::new(policy), authorize))
+        .layer(middleware::from_fn_with_state(verifier, verify_jwt))
+}
+
+async fn call(app: Router, token: Option<&str>) -> (StatusCode, Option<String>) {
+    let mut request = Request::builder().uri("/");
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+    let response = app
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let challenge = response
+        .headers()
+        .get(WWW_AUTHENTICATE)
+        .map(|v| v.to_str().unwrap().to_string());
+    (response.status(), challenge)
+}
+
+#[tokio::test]
+async fn authorizes_callers_with_the_required_scope() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let app = protected_app(&signer, Policy::new().require_scope("kamekai/translate")).await;
+
+    let (status, _) = call(app, Some(&signer.sign(&claims()))).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn forbids_callers_without_the_required_scope() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let app = protected_app(&signer, Policy::new().require_scope("kamekai/admin")).await;
+
+    let (status, challenge) = call(app, Some(&signer.sign(&claims()))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        challenge.as_deref(),
+        Some(r#"Bearer error="insufficient_scope", scope="kamekai/admin""#)
+    );
+}
+
+#[tokio::test]
+async fn authorizes_callers_by_group() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let policy = Policy::new().require_any_group(&[groups::ADMIN, "support"]);
+
+    let admin = signer.sign(&with(claims(), "groups", json!(["learner", "admin"])));
+    let (status, _) = call(protected_app(&signer, policy.clone()).await, Some(&admin)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let learner = signer.sign(&with(claims(), "groups", json!(["learner"])));
+    let (status, _) = call(protected_app(&signer, policy.clone()).await, Some(&learner)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = call(
+        protected_app(&signer, policy).await,
+        Some(&signer.sign(&claims())),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn challenges_unauthenticated_callers() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+
+    let app = protected_app(&signer, Policy::new()).await;
+    let (status, challenge) = call(app, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(challenge.as_deref(), Some("Bearer"));
+
+    let expired = signer.sign(&with(
+        claims(),
+        "exp",
+        json!(get_current_timestamp() - 3600),
+    ));
+    let app = protected_app(&signer, Policy::new()).await;
+    let (status, challenge) = call(app, Some(&expired)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        challenge.as_deref(),
+        Some(r#"Bearer error="invalid_token""#)
+    );
+}
+
+#[tokio::test]
+async fn rejects_extracting_the_user_without_a_verified_token() {
+    let app = Router::new().route(
+        "/",
+        get(|user: AuthenticatedUser| async move { user.sub.clone() }),
+    );
+    let (status, _) = call(app, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn accepts_api_keys_through_the_same_middleware() {
+    let path = std::env::temp_dir().join(format!("kamekai-{}.json", uuid::Uuid::new_v4()));
+    let store = ApiKeyStore::open(&path).unwrap();
+    let (plain, _) = store
+        .mint(NewApiKey {
+            name: "batch".to_string(),
+            sub: "script-user".to_string(),
+            scopes: vec!["kamekai/translate".to_string()],
+            expires_at: None,
+        })
+        .unwrap();
+
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let verifier = Arc::new(
+        verifier(issuer_config(&[&signer]))
+            .await
+            .with_api_keys(store),
+    );
+    let app = Router::new()
+        .route(
+            "/",
+            get(|user: AuthenticatedUser| async move { user.sub.clone() }),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(Policy::new().require_scope("kamekai/translate")),
+            authorize,
+        ))
+        .layer(middleware::from_fn_with_state(verifier, verify_jwt));
+
+    let (status, _) = call(app.clone(), Some(&plain)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let request = Request::builder()
+        .uri("/")
+        .header(API_KEY_HEADER, &plain)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (status, _) = call(app, Some("kmk_0000000000000000_nope")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn trusts_tokens_from_the_dev_issuer_offline() {
+    let dev = DevIssuer::generate("http://localhost:8080").unwrap();
+    let verifier = TokenVerifier::new(OidcConfig {
+        issuers: vec![dev.issuer_config()],
+    })
+    .await
+    .unwrap();
+
+    let token = dev
+        .mint(&DevTokenRequest {
+            sub: "dev".to_string(),
+            scopes: vec!["kamekai/translate".to_string()],
+            groups: vec!["admin".to_string()],
+            expires_in: 60,
+        })
+        .unwrap();
+    let claims = verifier.verify(&token.access_token).await.unwrap();
+    assert_eq!(claims.sub, "dev");
+    assert_eq!(claims.scopes, vec!["kamekai/translate"]);
+    assert_eq!(claims.groups, vec!["admin"]);
+
+    // Another dev server's tokens are not trusted.
+    let other = DevIssuer::generate("http://localhost:8080").unwrap();
+    let token = other
+        .mint(&DevTokenRequest {
+            sub: "dev".to_string(),
+            scopes: vec![],
+            groups: vec![],
+            expires_in: 60,
+        })
+        .unwrap();
+    assert!(matches!(
+        verifier.verify(&token.access_token).await,
+        Err(AuthError::UnknownKey(_))
+    ));
+}
+
+#[test]
+fn caps_the_lifetime_of_dev_tokens() {
+    let dev = DevIssuer::generate("http://localhost:8080").unwrap();
+    let request = |expires_in| DevTokenRequest {
+        sub: "dev".to_string(),
+        scopes: vec![],
+        groups: vec![],
+        expires_in,
+    };
+    assert!(dev.mint(&request(MAX_EXPIRES_IN)).is_ok());
+    for expires_in in [MAX_EXPIRES_IN + 1, u64::MAX] {
+        assert!(matches!(
+            dev.mint(&request(expires_in)),
+            Err(DevTokenError::TooLong)
+        ));
+    }
+}
+
+#[tokio::test]
+async fn rejects_revoked_tokens() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let revocations = Arc::new(RevocationList::in_memory());
+    let verifier = verifier(issuer_config(&[&signer]))
+        .await
+        .with_revocations(Arc::clone(&revocations));
+    let later = chrono::Utc::now() + chrono::Duration::hours(1);
+
+    let token = signer.sign(&with(claims(), "origin_jti", json!("session-1")));
+    assert!(verifier.verify(&token).await.is_ok());
+
+    revocations.revoke_token("token-1", later).await.unwrap();
+    assert!(matches!(
+        verifier.verify(&token).await,
+        Err(AuthError::Revoked(_))
+    ));
+
+    // Revoking the sign in covers every token refreshed from it.
+    let refreshed = signer.sign(&with(
+        with(claims(), "jti", json!("token-2")),
+        "origin_jti",
+        json!("session-1"),
+    ));
+    assert!(verifier.verify(&refreshed).await.is_ok());
+    revocations.revoke_token("session-1", later).await.unwrap();
+    assert!(matches!(
+        verifier.verify(&refreshed).await,
+        Err(AuthError::Revoked(_))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_tokens_issued_before_the_user_cutoff() {
+    let signer = Signer::ec("kid", Algorithm::ES256);
+    let revocations = Arc::new(RevocationList::in_memory());
+    let verifier = verifier(issuer_config(&[&signer]))
+        .await
+        .with_revocations(Arc::clone(&revocations));
+    let now = get_current_timestamp();
+
+    let old = signer.sign(&with(claims(), "iat", json!(now - 120)));
+    let new = signer.sign(&with(claims(), "iat", json!(now + 1)));
+    let cutoff = chrono::Utc::now();
+    revocations.revoke_user("user-1", cutoff).await.unwrap();
+
+    assert!(matches!(
+        verifier.verify(&old).await,
+        Err(AuthError::Revoked(_))
+    ));
+    assert!(verifier.verify(&new).await.is_ok());
+
+    // Other users are not affected.
+    let other = signer.sign(&with(
+        with(claims(), "sub", json!("user-2")),
+        "iat",
+        json!(now - 120),
+    ));
+    assert!(verifier.verify(&other).await.is_ok());
+
+    assert!(revocations.clear_user("user-1").await.unwrap());
+    assert!(verifier.verify(&old).await.is_ok());
+}
+
+#[tokio::test]
+async fn persists_revocations() {
+    let path = std::env::temp_dir().join(format!("kamekai-{}.json", uuid::Uuid::new_v4()));
+    let later = chrono::Utc::now() + chrono::Duration::hours(1);
+    let earlier = chrono::Utc::now() - chrono::Duration::hours(1);
+
+    let revocations = RevocationList::open(&path).unwrap();
+    revocations.revoke_token("token-1", later).await.unwrap();
+    revocations.revoke_token("expired", earlier).await.unwrap();
+    revocations.revoke_user("user-1", later).await.unwrap();
+
+    let reopened = RevocationList::open(&path).unwrap().revocations();
+    assert!(reopened.tokens.contains_key("token-1"));
+    assert!(!reopened.tokens.contains_key("expired"));
+    assert!(reopened.users.contains_key("user-1"));
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,359 @@
+use anyhow::{bail, Context, Result};
+use axum::http::{header::WWW_AUTHENTICATE, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, PublicKeyUse};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Client;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use super::api_keys::{ApiKeyError, ApiKeyStore, API_KEY_PREFIX};
+use super::claims::{lookup, Claims};
+use super::jwks::JwkManager;
+use super::oidc::{IssuerConfig, OidcConfig};
+use super::revocation::RevocationList;
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("missing bearer token")]
+    MissingToken,
+
+    #[error("malformed token: {0}")]
+    MalformedToken(String),
+
+    #[error("token issuer {0} is not trusted")]
+    UntrustedIssuer(String),
+
+    #[error("no key {0} for the token issuer")]
+    UnknownKey(String),
+
+    #[error("signing algorithm {0:?} is not allowed")]
+    DisallowedAlgorithm(Algorithm),
+
+    #[error("key {0} can't be used to verify this token")]
+    KeyMismatch(String),
+
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+
+    #[error("token is missing required claim {0}")]
+    MissingClaim(String),
+
+    #[error("token claim {0} has an unexpected value")]
+    UnexpectedClaim(String),
+
+    #[error("could not load the issuer keys: {0}")]
+    KeysUnavailable(String),
+
+    #[error("token revoked: {0}")]
+    Revoked(String),
+
+    #[error("token lacks the {0} scope")]
+    InsufficientScope(String),
+
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+}
+
+impl AuthError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::MalformedToken(_) => StatusCode::BAD_REQUEST,
+            AuthError::KeysUnavailable(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthError::InsufficientScope(_) | AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    // See RFC 6750, section 3.
+    fn challenge(&self) -> Option<String> {
+        match self {
+            AuthError::MissingToken => Some("Bearer".to_string()),
+            AuthError::MalformedToken(_) => Some(r#"Bearer error="invalid_request""#.to_string()),
+            AuthError::InsufficientScope(scope) => Some(format!(
+                r#"Bearer error="insufficient_scope", scope="{}""#,
+                scope
+            )),
+            AuthError::KeysUnavailable(_) | AuthError::Forbidden(_) => None,
+            _ => Some(r#"Bearer error="invalid_token""#.to_string()),
+        }
+    }
+}
+
+// The body is left empty, the request context middleware fills in the standard error.
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let mut response = self.status().into_response();
+        if let Some(value) = self
+            .challenge()
+            .and_then(|c| HeaderValue::from_str(&c).ok())
+        {
+            response.headers_mut().insert(WWW_AUTHENTICATE, value);
+        }
+        response
+    }
+}
+
+#[derive(Debug)]
+struct TrustedIssuer {
+    config: IssuerConfig,
+    jwks: Arc<JwkManager>,
+}
+
+/// Validates access tokens against a set of trusted OIDC issuers,
+/// and API keys against the key store when there is one.
+#[derive(Debug)]
+pub struct TokenVerifier {
+    issuers: HashMap<String, TrustedIssuer>,
+    api_keys: Option<Arc<ApiKeyStore>>,
+    revocations: Option<Arc<RevocationList>>,
+}
+
+impl TokenVerifier {
+    /// Discovers every configured issuer and loads its keys.
+    pub async fn new(config: OidcConfig) -> Result<Self> {
+        if config.issuers.is_empty() {
+            bail!("At least one OIDC issuer must be configured");
+        }
+
+        let client = Client::new();
+        let mut issuers = HashMap::new();
+        for issuer in config.issuers {
+            issuer.validate()?;
+            let jwks = match &issuer.jwks {
+                Some(keys) => JwkManager::with_keys(keys.clone()),
+                None => {
+                    let jwks_uri = issuer.resolve_jwks_uri(&client).await.with_context(|| {
+                        format!("Error discovering OIDC issuer {}", issuer.issuer)
+                    })?;
+                    JwkManager::new(jwks_uri).await?
+                }
+            };
+            issuers.insert(
+                issuer.issuer.clone(),
+                TrustedIssuer {
+                    config: issuer,
+                    jwks,
+                },
+            );
+        }
+
+        Ok(Self {
+            issuers,
+            api_keys: None,
+            revocations: None,
+        })
+    }
+
+    /// Also accept the API keys in `store`, and start reloading them in the background.
+    /// Must be called from within a Tokio runtime.
+    pub fn with_api_keys(mut self, store: ApiKeyStore) -> Self {
+        let store = Arc::new(store);
+        store.reload_periodically();
+        self.api_keys = Some(store);
+        self
+    }
+
+    /// Reject the tokens in the revocation list.
+    pub fn with_revocations(mut self, revocations: Arc<RevocationList>) -> Self {
+        self.revocations = Some(revocations);
+        self
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<Claims, AuthError> {
+        let claims = if token.starts_with(API_KEY_PREFIX) {
+            self.verify_api_key(token)?
+        } else {
+            self.verify_token(token).await?
+        };
+
+        if let Some(reason) = self.revocations.as_ref().and_then(|r| r.check(&claims)) {
+            warn!(sub = %claims.sub, reason = %reason, "token was revoked");
+            return Err(AuthError::Revoked(reason));
+        }
+        Ok(claims)
+    }
+
+    async fn verify_token(&self, token: &str) -> Result<Claims, AuthError> {
+        let header = decode_header(token).map_err(|e| {
+            warn!("failed to decode JWT header");
+            AuthError::MalformedToken(e.to_string())
+        })?;
+
+        let kid = header.kid.ok_or_else(|| {
+            warn!("no kid in JWT header");
+            AuthError::MalformedToken("no kid in header".to_string())
+        })?;
+
+        // Work out which issuer the token claims to come from, so that we know which keys
+        // to check the signature against. Nothing in the payload is trusted until then.
+        let iss = unverified_issuer(token)?;
+        let issuer = self.issuers.get(&iss).ok_or_else(|| {
+            warn!(iss = %iss, "token from an untrusted issuer");
+            AuthError::UntrustedIssuer(iss.clone())
+        })?;
+
+        let config = &issuer.config;
+        if !config.allowed_algorithms.contains(&header.alg) {
+            warn!(alg = ?header.alg, "token signed with a disallowed algorithm");
+            return Err(AuthError::DisallowedAlgorithm(header.alg));
+        }
+
+        let jwk = issuer
+            .jwks
+            .find(&kid)
+            .await
+            .map_err(|e| AuthError::KeysUnavailable(e.to_string()))?;
+
+        let jwk = match jwk {
+            Some(k) => {
+                info!(
+                    kid = %kid,
+                    key_use = ?k.common.public_key_use,
+                    key_alg = ?k.common.key_algorithm,
+                    "found matching JWK"
+                );
+                k
+            }
+            None => {
+                warn!(kid = %kid, "no matching JWK found");
+                return Err(AuthError::UnknownKey(kid));
+            }
+        };
+        check_key(&jwk, header.alg).inspect_err(|_| {
+            warn!(kid = %kid, alg = ?header.alg, "JWK can't verify the token");
+        })?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = config.leeway;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&config.issuer]);
+        let mut spec_claims = config.required_claims.clone();
+        spec_claims.extend(["iss".to_string(), "exp".to_string()]);
+        if config.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&config.audiences);
+            spec_claims.push("aud".to_string());
+        }
+        validation.set_required_spec_claims(&spec_claims);
+
+        let decoding_key =
+            DecodingKey::from_jwk(&jwk).map_err(|_| AuthError::KeyMismatch(kid.clone()))?;
+
+        let raw = match decode::<Map<String, Value>>(token, &decoding_key, &validation) {
+            Ok(data) => data.claims,
+            Err(e) => {
+                warn!(error = %e, "token validation failed with error");
+                return Err(AuthError::InvalidToken(e.to_string()));
+            }
+        };
+
+        // The JWT library only checks for the presence of the registered claims.
+        for name in &config.required_claims {
+            if lookup(&raw, name).is_none() {
+                warn!(claim = %name, "token is missing a required claim");
+                return Err(AuthError::MissingClaim(name.clone()));
+            }
+        }
+        for (name, expected) in &config.expected_claims {
+            if lookup(&raw, name) != Some(expected) {
+                warn!(claim = %name, "token claim has an unexpected value");
+                return Err(AuthError::UnexpectedClaim(name.clone()));
+            }
+        }
+
+        let claims = Claims::from_raw(&raw, &config.claim_mapping)
+            .ok_or_else(|| AuthError::MissingClaim(config.claim_mapping.subject.clone()))?;
+
+        if !config.client_ids.is_empty()
+            && !claims
+                .client_id
+                .as_ref()
+                .is_some_and(|id| config.client_ids.contains(id))
+        {
+            warn!(client_id = ?claims.client_id, "token issued to an unexpected client");
+            return Err(AuthError::UnexpectedClaim(
+                config.claim_mapping.client_id.clone(),
+            ));
+        }
+
+        info!(sub = %claims.sub, iss = %claims.iss, "token validated successfully");
+        Ok(claims)
+    }
+}
+
+impl TokenVerifier {
+    fn verify_api_key(&self, key: &str) -> Result<Claims, AuthError> {
+        let store = self
+            .api_keys
+            .as_ref()
+            .ok_or_else(|| AuthError::InvalidToken("API keys are not enabled".to_string()))?;
+        let key = store.authenticate(key).map_err(|e| match e {
+            ApiKeyError::Malformed => AuthError::MalformedToken(e.to_string()),
+            _ => AuthError::InvalidToken(e.to_string()),
+        })?;
+        info!(sub = %key.sub, key_id = %key.id, "API key validated successfully");
+        Ok(key.claims())
+    }
+}
+
+// Makes sure the key is meant for signatures with the algorithm the token claims to use.
+// The JWT library checks the key family, but not the curve nor what the JWK declares.
+fn check_key(jwk: &Jwk, alg: Algorithm) -> Result<(), AuthError> {
+    let kid = jwk.common.key_id.clone().unwrap_or_default();
+
+    if matches!(&jwk.common.public_key_use, Some(key_use) if *key_use != PublicKeyUse::Signature) {
+        return Err(AuthError::KeyMismatch(kid));
+    }
+    if let Some(key_alg) = &jwk.common.key_algorithm {
+        if key_alg.to_string().parse::<Algorithm>().ok() != Some(alg) {
+            return Err(AuthError::KeyMismatch(kid));
+        }
+    }
+
+    let compatible = match (&jwk.algorithm, alg) {
+        (
+            AlgorithmParameters::RSA(_),
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512,
+        ) => true,
+        (AlgorithmParameters::EllipticCurve(params), Algorithm::ES256) => {
+            params.curve == EllipticCurve::P256
+        }
+        (AlgorithmParameters::EllipticCurve(params), Algorithm::ES384) => {
+            params.curve == EllipticCurve::P384
+        }
+        (AlgorithmParameters::OctetKeyPair(_), Algorithm::EdDSA) => true,
+        _ => false,
+    };
+    if !compatible {
+        return Err(AuthError::KeyMismatch(kid));
+    }
+    Ok(())
+}
+
+// Reads the `iss` claim without validating anything.
+// Only used to pick the keys the token is then validated against.
+fn unverified_issuer(token: &str) -> Result<String, AuthError> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims.clear();
+
+    let data = decode::<Map<String, Value>>(token, &DecodingKey::from_secret(&[]), &validation)
+        .map_err(|e| AuthError::MalformedToken(e.to_string()))?;
+    data.claims
+        .get("iss")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| AuthError::MissingClaim("iss".to_string()))
+}
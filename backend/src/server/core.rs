@@ -1,11 +1,9 @@
 use anyhow::Result;
-use axum::http::header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE};
-use axum::http::{HeaderValue, Method, Request};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderName, HeaderValue, Method, Request};
 use axum::{
-    extract::{ConnectInfo, MatchedPath},
     middleware,
-    response::Response,
-    routing::{get, post},
+    routing::{get, post, put},
     Router,
 };
 use std::{net::SocketAddr, sync::Arc, time::Duration};
@@ -14,11 +12,35 @@ use tokio::signal;
 use tower_http::cors::CorsLayer;
 use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
-use tracing::{info_span, Span};
 //use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use super::auth::{verify_jwt, JwkManager};
-use super::handlers::{handle_health, handle_translate};
+use super::auth::{
+    authorize, groups, scopes, verify_jwt, ApiKeyStore, DevIssuer, OidcConfig, Policy,
+    RevocationList, TokenVerifier, API_KEY_HEADER,
+};
+use super::handlers::{
+    handle_cancel_job, handle_clear_user_revocation, handle_correct, handle_create_cards,
+    handle_create_exercise, handle_create_quiz, handle_create_session, handle_deck_stats,
+    handle_delete_history, handle_dev_discovery, handle_dev_jwks, handle_dev_token,
+    handle_due_cards, handle_end_session, handle_get_exercise, handle_get_history, handle_get_job,
+    handle_get_lesson, handle_get_quiz, handle_get_session, handle_grade_attempt,
+    handle_grade_quiz, handle_health, handle_lesson, handle_list_exercises, handle_list_history,
+    handle_list_lessons, handle_list_revocations, handle_list_sessions, handle_review_card,
+    handle_revoke_token, handle_revoke_user, handle_session_turn, handle_submit_job,
+    handle_translate,
+};
+use super::jobs::{JobConfig, JobManager};
+use super::request_context::{request_context, REQUEST_ID_HEADER};
+use super::state::AppState;
+use super::storage::{CardRepository, HistoryRepository};
+use super::time_zone::TIME_ZONE_HEADER;
+use super::trace::{make_span, on_response, record_metrics};
+use crate::clock::SystemClock;
+use crate::lesson::LessonHistory;
+use crate::practice::PracticeStore;
+use crate::quiz::QuizStore;
+use crate::redaction::RedactionPolicy;
+use crate::roleplay::SessionStore;
 
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -79,107 +101,201 @@ async fn shutdown_signal() {
     println!("Shutting down gracefully...");
 }
 
-pub async fn run_server(
-    host: String,
-    port: u16,
-    request_timeout: u64,
-    cognito_user_pool: String,
-    cognito_client_id: String,
-) -> Result<()> {
+/// Everything the server needs to get going.
+#[derive(Debug)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// In seconds.
+    pub request_timeout: u64,
+    pub oidc: OidcConfig,
+    pub jobs: JobConfig,
+    pub redaction: RedactionPolicy,
+    pub api_keys: Option<ApiKeyStore>,
+    pub revocations: RevocationList,
+    pub history: Arc<dyn HistoryRepository>,
+    pub cards: Arc<dyn CardRepository>,
+    pub lessons: LessonHistory,
+    pub practice: PracticeStore,
+    pub quizzes: QuizStore,
+    pub sessions: SessionStore,
+    /// Issue and trust our own tokens, see `DevIssuer`. Never enable it in production.
+    pub dev_auth: bool,
+}
+
+pub async fn run_server(config: ServerConfig) -> Result<()> {
+    let ServerConfig {
+        host,
+        port,
+        request_timeout,
+        oidc: mut oidc_config,
+        jobs: job_config,
+        redaction,
+        api_keys,
+        revocations,
+        history,
+        cards,
+        lessons,
+        practice,
+        quizzes,
+        sessions,
+        dev_auth,
+    } = config;
+
     // build our application with our routes.
     let cors = CorsLayer::new()
         .allow_origin([
             HeaderValue::from_static("tauri://localhost"), // Desktop.
             HeaderValue::from_static("https://app.seafoodfry.ninja"), // Web client.
         ])
-        .allow_methods([Method::POST, Method::OPTIONS])
-        .allow_headers([AUTHORIZATION, CONTENT_TYPE])
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers([
+            AUTHORIZATION,
+            HeaderName::from_static(API_KEY_HEADER),
+            CONTENT_TYPE,
+            REQUEST_ID_HEADER,
+            TIME_ZONE_HEADER,
+            HeaderName::from_static("traceparent"),
+            HeaderName::from_static("tracestate"),
+        ])
         .allow_credentials(true)
-        .expose_headers([
-        // Add any custom headers your frontend needs to read
-    ])
+        .expose_headers([REQUEST_ID_HEADER])
         .max_age(Duration::from_secs(3600));
 
-    // Get the JWKs so that we can enforce AuthN/Z.
-    let jwk_manager = Arc::new(JwkManager::new(cognito_user_pool, cognito_client_id).await?);
+    // The dev issuer is trusted like any other, but its keys never leave this process.
+    let dev_issuer = if dev_auth {
+        let dev_issuer = Arc::new(DevIssuer::generate(format!("http://localhost:{}", port))?);
+        tracing::warn!(
+            issuer = %dev_issuer.issuer(),
+            "dev auth is enabled, anyone who can reach the server can mint tokens"
+        );
+        oidc_config.issuers.push(dev_issuer.issuer_config());
+        Some(dev_issuer)
+    } else {
+        None
+    };
 
-    let protected_routes = Router::new()
+    // Discover the trusted issuers and get their JWKs so that we can enforce AuthN/Z.
+    let revocations = Arc::new(revocations);
+    let mut token_verifier = TokenVerifier::new(oidc_config)
+        .await?
+        .with_revocations(Arc::clone(&revocations));
+    if let Some(api_keys) = api_keys {
+        token_verifier = token_verifier.with_api_keys(api_keys);
+    }
+    let token_verifier = Arc::new(token_verifier);
+
+    // Translation jobs are processed in the background so that long documents are not
+    // bound by the request timeout.
+    let redaction = Arc::new(redaction);
+    let state = AppState {
+        cards,
+        clock: Arc::new(SystemClock),
+        history,
+        jobs: JobManager::start(job_config, Arc::clone(&redaction)),
+        redaction: Arc::clone(&redaction),
+        revocations,
+        lessons: Arc::new(lessons),
+        practice: Arc::new(practice),
+        quizzes: Arc::new(quizzes),
+        sessions: Arc::new(sessions),
+    };
+    let span_redaction = Arc::clone(&redaction);
+
+    // Policies are route layers so that they run after the token has been verified.
+    let translate_policy = Arc::new(Policy::new().require_scope(scopes::TRANSLATE));
+    let translation_routes = Router::new()
         .route("/translate", post(handle_translate))
+        .route("/history", get(handle_list_history))
+        .route(
+            "/history/{id}",
+            get(handle_get_history).delete(handle_delete_history),
+        )
+        .route("/jobs", post(handle_submit_job))
+        .route("/jobs/{id}", get(handle_get_job).delete(handle_cancel_job))
+        .route_layer(middleware::from_fn_with_state(translate_policy, authorize));
+
+    let learn_policy = Arc::new(Policy::new().require_scope(scopes::LEARN));
+    let learn_routes = Router::new()
+        .route("/lesson", post(handle_lesson))
+        .route("/cards", post(handle_create_cards))
+        .route("/cards/due", get(handle_due_cards))
+        .route("/cards/stats", get(handle_deck_stats))
+        .route("/cards/{id}/reviews", post(handle_review_card))
+        .route("/correct", post(handle_correct))
+        .route("/lessons", get(handle_list_lessons))
+        .route("/lessons/{id}", get(handle_get_lesson))
+        .route("/quizzes", post(handle_create_quiz))
+        .route("/quizzes/{id}", get(handle_get_quiz))
+        .route("/quizzes/{id}/answers", post(handle_grade_quiz))
+        .route(
+            "/practice",
+            post(handle_create_exercise).get(handle_list_exercises),
+        )
+        .route("/practice/{id}", get(handle_get_exercise))
+        .route("/practice/{id}/attempts", post(handle_grade_attempt))
+        .route(
+            "/sessions",
+            post(handle_create_session).get(handle_list_sessions),
+        )
+        .route("/sessions/{id}", get(handle_get_session))
+        .route("/sessions/{id}/turns", post(handle_session_turn))
+        .route("/sessions/{id}/end", post(handle_end_session))
+        .route_layer(middleware::from_fn_with_state(learn_policy, authorize));
+
+    let admin_policy = Arc::new(Policy::new().require_any_group(&[groups::ADMIN]));
+    let admin_routes = Router::new()
+        .route("/admin/revocations", get(handle_list_revocations))
+        .route("/admin/revocations/tokens", post(handle_revoke_token))
+        .route(
+            "/admin/revocations/users/{sub}",
+            put(handle_revoke_user).delete(handle_clear_user_revocation),
+        )
+        .route_layer(middleware::from_fn_with_state(admin_policy, authorize));
+
+    let protected_routes = Router::new()
+        .merge(translation_routes)
+        .merge(learn_routes)
+        .merge(admin_routes)
         .layer(middleware::from_fn_with_state(
-            Arc::clone(&jwk_manager),
+            Arc::clone(&token_verifier),
             verify_jwt,
-        ));
+        ))
+        .with_state(state);
 
-    let app = Router::new()
+    let mut app = Router::new()
         .merge(protected_routes)
-        .route("/healthz", get(handle_health))
+        .route("/healthz", get(handle_health));
+    if let Some(dev_issuer) = dev_issuer {
+        app = app.merge(
+            Router::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    get(handle_dev_discovery),
+                )
+                .route("/.well-known/jwks.json", get(handle_dev_jwks))
+                .route("/dev/token", post(handle_dev_token))
+                .with_state(dev_issuer),
+        );
+    }
+
+    let app = app
         .layer(cors) // Need to respond to preflight requests before other middleware interferes/changes headers.
         .layer(TimeoutLayer::new(Duration::from_secs(request_timeout)))
+        .layer(middleware::from_fn(record_metrics))
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(|request: &Request<_>| {
-                    let matched_path = request
-                        .extensions()
-                        .get::<MatchedPath>()
-                        .map(MatchedPath::as_str);
-
-                    let client_ip = request
-                        .extensions()
-                        .get::<ConnectInfo<SocketAddr>>()
-                        .map(|connect_info| connect_info.0.to_string());
-
-                    let headers: Vec<(String, String)> = request
-                        .headers()
-                        .iter()
-                        .map(|(key, value)| {
-                            (
-                                key.as_str().to_owned(),
-                                value.to_str().unwrap_or("invalid").to_owned(),
-                            )
-                        })
-                        .collect();
-
-                    info_span!(
-                        "http_request",
-                        method = ?request.method(),
-                        path = %request.uri().path(),
-                        matched_path,
-                        client_ip = client_ip.as_deref(),
-                        headers = ?headers,
-                        response.status = tracing::field::Empty,
-                        response.size = tracing::field::Empty,
-                        response.content_type = tracing::field::Empty,
-                        response.latency = tracing::field::Empty,
-                    )
-                })
-                .on_response(|response: &Response, latency: Duration, span: &Span| {
-                    let size = response
-                        .headers()
-                        .get(CONTENT_LENGTH)
-                        .and_then(|v| v.to_str().ok())
-                        .and_then(|v| v.parse::<usize>().ok())
-                        .unwrap_or(0);
-
-                    let content_type = response
-                        .headers()
-                        .get(CONTENT_TYPE)
-                        .and_then(|v| v.to_str().ok())
-                        .unwrap_or("unknown");
-
-                    span.record(
-                        "response.status",
-                        tracing::field::display(response.status()),
-                    );
-                    span.record("response.size", size);
-                    span.record("response.content_type", content_type);
-                    span.record("response.latency_ms", latency.as_millis());
-
-                    tracing::info!(
-                        parent: span,
-                        "finished processing request"
-                    );
-                }),
-        );
+                .make_span_with(move |request: &Request<_>| make_span(request, &span_redaction))
+                .on_response(on_response),
+        )
+        // Outermost so that the request ID is available to the trace span and every handler.
+        .layer(middleware::from_fn(request_context));
 
     let addr = format!("{}:{}", host, port);
     let listener = TcpListener::bind(addr).await?;
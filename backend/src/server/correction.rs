@@ -0,0 +1,177 @@
+//! Corrections of what users write. The model corrects the text, the diff is worked out here so
+//! that it always matches the two texts.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::instrument;
+
+use super::models::{CorrectionResponse, DiffSegment, WritingError};
+use crate::aws::{self, bedrock::parse_model_json};
+use crate::{ConversationBuilder, Language};
+
+pub const MAX_CORRECTION_CHARS: usize = 500;
+
+#[derive(Deserialize)]
+struct ModelCorrection {
+    corrected: String,
+    #[serde(default)]
+    errors: Vec<WritingError>,
+    naturalness: f64,
+}
+
+#[instrument(
+    name = "correct_writing",
+    fields(correction.language = %language, text.length = text.len()),
+    skip(text),
+)]
+pub async fn correct_writing(
+    language: Language,
+    text: &str,
+    capture_content: bool,
+) -> Result<CorrectionResponse> {
+    let aws_client = aws::AWSClient::new(Some(aws::InferenceParameters {
+        temperature: 0.0,
+        max_tokens: 2048,
+        top_p: 0.95,
+    }))
+    .await
+    .context("Error creating AWS client")?
+    .with_content_capture(capture_content);
+
+    let system_prompt = format!(
+        "You are a {} teacher correcting what a student wrote. \
+        Correct the grammar and the word choices, keeping to what the student meant and changing \
+        as little as possible. If nothing needs correcting, return the text as it is. \
+        List each mistake with the phrase as written, the corrected phrase and a short \
+        explanation in English, in one of these categories: particle, word_order, \
+        character_choice (the wrong character, e.g., from a mixed-up reading or tone), \
+        politeness or other. \
+        Also score how natural the text sounds to a native speaker, from 0 to 100. \
+        Respond only with JSON in the following format, without any other text:\n\
+        {{\"corrected\": \"...\", \"errors\": [{{\"category\": \"particle\", \"original\": \"...\", \
+        \"corrected\": \"...\", \"explanation\": \"...\"}}], \"naturalness\": 80}}",
+        language
+    );
+    let conversation = ConversationBuilder::new()
+        .with_system_prompt(system_prompt)
+        .add_user_message(text)
+        .build()
+        .context("Error creating messages for AWS Bedrock")?;
+
+    let output = aws_client
+        .create_conversation(conversation)
+        .await
+        .context("Error creating conversation with AWS Bedrock")?;
+    let correction: ModelCorrection =
+        parse_model_json(&output).context("Error parsing the correction")?;
+
+    let corrected = correction.corrected.trim().to_string();
+    Ok(CorrectionResponse {
+        diff: char_diff(text, &corrected),
+        original: text.to_string(),
+        corrected,
+        errors: correction.errors,
+        naturalness: correction.naturalness.round().clamp(0.0, 100.0) as u8,
+    })
+}
+
+/// The character-level changes from `original` to `corrected`, along the longest common
+/// subsequence. Where characters are replaced, the deletion comes first.
+pub fn char_diff(original: &str, corrected: &str) -> Vec<DiffSegment> {
+    let a: Vec<char> = original.chars().collect();
+    let b: Vec<char> = corrected.chars().collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut segments = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            push(&mut segments, DiffSegment::Equal { text: a[i].into() });
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            push(&mut segments, DiffSegment::Delete { text: a[i].into() });
+            i += 1;
+        } else {
+            push(&mut segments, DiffSegment::Insert { text: b[j].into() });
+            j += 1;
+        }
+    }
+    segments
+}
+
+// Adds `segment`, merging it into the last one if they are the same operation.
+fn push(segments: &mut Vec<DiffSegment>, segment: DiffSegment) {
+    use DiffSegment::*;
+    match (segments.last_mut(), segment) {
+        (Some(Equal { text }), Equal { text: more })
+        | (Some(Insert { text }), Insert { text: more })
+        | (Some(Delete { text }), Delete { text: more }) => text.push_str(&more),
+        (_, segment) => segments.push(segment),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::models::ErrorCategory;
+
+    fn equal(text: &str) -> DiffSegment {
+        DiffSegment::Equal { text: text.into() }
+    }
+
+    fn insert(text: &str) -> DiffSegment {
+        DiffSegment::Insert { text: text.into() }
+    }
+
+    fn delete(text: &str) -> DiffSegment {
+        DiffSegment::Delete { text: text.into() }
+    }
+
+    #[test]
+    fn diffs_replaced_particles() {
+        assert_eq!(
+            char_diff("私が学生です", "私は学生です"),
+            [equal("私"), delete("が"), insert("は"), equal("学生です")]
+        );
+    }
+
+    #[test]
+    fn diffs_additions_and_removals() {
+        assert_eq!(
+            char_diff("我昨天去商店", "我昨天去了商店。"),
+            [equal("我昨天去"), insert("了"), equal("商店"), insert("。")]
+        );
+        assert_eq!(
+            char_diff("とてもとても高い", "とても高い"),
+            [equal("とても"), delete("とても"), equal("高い")]
+        );
+        assert_eq!(char_diff("", "はい"), [insert("はい")]);
+        assert_eq!(char_diff("同じ", "同じ"), [equal("同じ")]);
+    }
+
+    #[test]
+    fn unknown_categories_are_other() {
+        let error: WritingError = serde_json::from_str(
+            r#"{"category": "spelling", "original": "a", "corrected": "b", "explanation": "c"}"#,
+        )
+        .unwrap();
+        assert_eq!(error.category, ErrorCategory::Other);
+        let error: WritingError = serde_json::from_str(
+            r#"{"category": "word_order", "original": "a", "corrected": "b", "explanation": "c"}"#,
+        )
+        .unwrap();
+        assert_eq!(error.category, ErrorCategory::WordOrder);
+    }
+}
@@ -1,52 +1,101 @@
 use anyhow::{Context, Result};
 use axum::{
-    extract::{Extension, Json},
+    extract::{Json, Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
 };
+use chrono::{NaiveTime, TimeZone, Utc};
 use serde_json::json;
-use tracing::{error, info, instrument};
+use std::sync::Arc;
+use tracing::{debug, error, info, instrument};
+use uuid::Uuid;
 
 use crate::{
     aws,
     conversation::ConversationBuilder,
+    language::SourceLanguage,
+    lesson::{generate_lesson, LessonParams},
+    per_user_store::{Item, PerUserStore, UpdateError},
+    practice::{generate_exercise, grade_attempt, MAX_TRANSLATION_LEN},
+    quiz::{generate_quiz, grade_quiz, QuizParams, MAX_QUESTIONS},
+    redaction::RedactionPolicy,
+    roleplay::{end_session, take_turn, Session, SessionError},
     server::models::{
-        BuilderError, Example, ExampleBuilder, LanguageTranslation, Translation,
-        TranslationRequest, TranslationResponse,
+        AttemptRequest, BuilderError, CardSource, CorrectionRequest, CreateCardsRequest,
+        CreateSessionRequest, CreatedCards, DeckStatsQuery, DueCardsQuery, Example, ExampleBuilder,
+        GradeQuizRequest, LanguageTranslation, ListExercisesQuery, ListHistoryQuery,
+        ListLessonsQuery, QuizRequest, QuizSource, ReviewRequest, RevokeTokenRequest,
+        RevokeUserRequest, SessionTurnRequest, Translation, TranslationRequest,
+        TranslationResponse,
     },
 };
 
-use super::auth::CognitoClaims;
+use super::auth::{AuthenticatedUser, DevIssuer, DevTokenError, DevTokenRequest, Revocations};
+use super::correction::{correct_writing, MAX_CORRECTION_CHARS};
+use super::jobs::{JobError, JobView};
+use super::request_context::current_request_id;
+use super::state::AppState;
+use super::storage::TranslationRecord;
+use super::time_zone::UserTimeZone;
 
 pub async fn handle_health() -> impl IntoResponse {
     (StatusCode::OK, Json(json!({ "status": "healthy" }))).into_response()
 }
 
 #[derive(serde::Serialize)]
-struct ApiResponse<T> {
+pub(super) struct ApiResponse<T> {
     data: Option<T>,
     error: Option<String>,
+    request_id: Option<String>,
+}
+
+impl<T> ApiResponse<T> {
+    pub(super) fn data(data: T) -> Self {
+        Self {
+            data: Some(data),
+            error: None,
+            request_id: current_request_id(),
+        }
+    }
+
+    pub(super) fn error(message: impl Into<String>) -> Self {
+        Self {
+            data: None,
+            error: Some(message.into()),
+            request_id: current_request_id(),
+        }
+    }
 }
 
 #[instrument(
     name = "handle_translate",
-    fields(user.id = %claims.sub, text.length = %payload.text.len()),
+    fields(user.id = %user.sub, text.length = %payload.text.len()),
     skip_all,
 )]
 pub async fn handle_translate(
-    Extension(claims): Extension<CognitoClaims>,
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
     Json(payload): Json<TranslationRequest>,
 ) -> impl IntoResponse {
-    match process_translation(&payload.text).await {
+    match process_translation(&payload.text, &state.redaction).await {
         Ok(response) => {
-            info!("processing request from {}", claims.sub);
-            (
-                StatusCode::OK,
-                Json(ApiResponse {
-                    data: Some(response),
-                    error: None,
-                }),
-            )
+            info!("processing request from {}", user.sub);
+            let record = TranslationRecord {
+                id: Uuid::new_v4(),
+                detected_language: SourceLanguage::detect(&payload.text),
+                request: payload.text,
+                model: aws::MODEL_ID.to_string(),
+                response,
+                created_at: state.clock.now(),
+            };
+            // Like lessons, the user still gets their translation if it can't be kept.
+            if let Err(e) = state.history.insert(&user.sub, &record).await {
+                error!(
+                    error = format!("{:#}", e),
+                    "Failed to record the translation"
+                );
+            }
+            (StatusCode::OK, Json(ApiResponse::data(record.response)))
         }
         Err(e) => {
             // Log the full error chain.
@@ -56,16 +105,1007 @@ pub async fn handle_translate(
             );
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
-                    data: None,
-                    error: Some("Failed to create translation response".to_string()),
-                }),
+                Json(ApiResponse::error("Failed to create translation response")),
             )
         }
     }
 }
 
-async fn process_translation(text: &str) -> Result<TranslationResponse> {
+#[instrument(
+    name = "handle_create_cards",
+    fields(user.id = %user.sub, cards.language = %payload.language),
+    skip_all,
+)]
+pub async fn handle_create_cards(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(payload): Json<CreateCardsRequest>,
+) -> impl IntoResponse {
+    let now = state.clock.now();
+    let cards = match payload.source {
+        CardSource::History { history_id } => {
+            match state.history.get(&user.sub, history_id).await {
+                Ok(Some(record)) => record
+                    .response
+                    .into_translations()
+                    .iter()
+                    .flat_map(|translation| translation.cards(payload.language, now))
+                    .collect(),
+                Ok(None) => {
+                    return (
+                        StatusCode::NOT_FOUND,
+                        Json(ApiResponse::error("Translation not found")),
+                    )
+                }
+                Err(e) => {
+                    error!(error = format!("{:#}", e), "Failed to get the translation");
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(ApiResponse::error("Failed to get the translation")),
+                    );
+                }
+            }
+        }
+        CardSource::Translation { translation } => translation.cards(payload.language, now),
+        CardSource::Example { example } => vec![example.card(payload.language, now)],
+    };
+    let cards: Vec<_> = cards
+        .into_iter()
+        .filter(|card| !card.front.is_empty() && !card.back.is_empty())
+        .collect();
+    if cards.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("There is nothing to make cards of")),
+        );
+    }
+
+    match state.cards.insert_cards(&user.sub, &cards).await {
+        Ok(created) => {
+            let skipped = cards.len() - created.len();
+            (
+                StatusCode::CREATED,
+                Json(ApiResponse::data(CreatedCards {
+                    cards: created,
+                    skipped,
+                })),
+            )
+        }
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to store the cards");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to store the cards")),
+            )
+        }
+    }
+}
+
+// Caps how many due cards are fetched at once.
+const MAX_DUE_CARDS_LIMIT: usize = 100;
+
+#[instrument(name = "handle_due_cards", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_due_cards(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Query(query): Query<DueCardsQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.min(MAX_DUE_CARDS_LIMIT);
+    match state
+        .cards
+        .due_cards(&user.sub, query.language, state.clock.now(), limit)
+        .await
+    {
+        Ok(cards) => (StatusCode::OK, Json(ApiResponse::data(cards))),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to get the due cards");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to get the due cards")),
+            )
+        }
+    }
+}
+
+#[instrument(name = "handle_review_card", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_review_card(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<ReviewRequest>,
+) -> impl IntoResponse {
+    let mut card = match state.cards.get_card(&user.sub, id).await {
+        Ok(Some(card)) => card,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::error("Card not found")),
+            )
+        }
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to get the card");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to get the card")),
+            );
+        }
+    };
+    let previous_review = card.last_reviewed_at;
+    let review = card.review(payload.grade, state.clock.now());
+    match state
+        .cards
+        .record_review(&user.sub, &card, &review, previous_review)
+        .await
+    {
+        Ok(true) => (StatusCode::OK, Json(ApiResponse::data(card))),
+        // E.g., the same review submitted twice.
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error("The card was reviewed in the meantime")),
+        ),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to record the review");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to record the review")),
+            )
+        }
+    }
+}
+
+#[instrument(
+    name = "handle_deck_stats",
+    fields(user.id = %user.sub, user.time_zone = %time_zone),
+    skip_all,
+)]
+pub async fn handle_deck_stats(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    UserTimeZone(time_zone): UserTimeZone,
+    Query(query): Query<DeckStatsQuery>,
+) -> impl IntoResponse {
+    // Today starts at midnight for the user, or the first time after it on days it's skipped.
+    let now = state.clock.now_in(time_zone);
+    let midnight = now.date_naive().and_time(NaiveTime::MIN);
+    let day_start = time_zone
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map_or(now, |start| start.min(now))
+        .with_timezone(&Utc);
+
+    match state
+        .cards
+        .deck_stats(
+            &user.sub,
+            query.language,
+            now.with_timezone(&Utc),
+            day_start,
+        )
+        .await
+    {
+        Ok(stats) => (StatusCode::OK, Json(ApiResponse::data(stats))),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to get the deck stats");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to get the deck stats")),
+            )
+        }
+    }
+}
+
+// Caps the page size of the translation history.
+const MAX_HISTORY_LIMIT: usize = 100;
+
+#[instrument(name = "handle_list_history", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_list_history(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Query(query): Query<ListHistoryQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.min(MAX_HISTORY_LIMIT);
+    match state.history.list(&user.sub, query.offset, limit).await {
+        Ok(page) => (StatusCode::OK, Json(ApiResponse::data(page))),
+        Err(e) => {
+            error!(
+                error = format!("{:#}", e),
+                "Failed to list the translation history"
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to list the translation history")),
+            )
+        }
+    }
+}
+
+#[instrument(name = "handle_get_history", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_get_history(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.history.get(&user.sub, id).await {
+        Ok(Some(record)) => (StatusCode::OK, Json(ApiResponse::data(record))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Translation not found")),
+        ),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to get the translation");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to get the translation")),
+            )
+        }
+    }
+}
+
+#[instrument(name = "handle_delete_history", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_delete_history(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match state.history.delete(&user.sub, id).await {
+        Ok(true) => (StatusCode::OK, Json(ApiResponse::data(json!({ "id": id })))),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Translation not found")),
+        ),
+        Err(e) => {
+            error!(
+                error = format!("{:#}", e),
+                "Failed to delete the translation"
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to delete the translation")),
+            )
+        }
+    }
+}
+
+#[instrument(
+    name = "handle_correct",
+    fields(user.id = %user.sub, correction.language = %payload.language),
+    skip_all,
+)]
+pub async fn handle_correct(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(payload): Json<CorrectionRequest>,
+) -> impl IntoResponse {
+    let text = payload.text.trim();
+    if text.is_empty() || text.chars().count() > MAX_CORRECTION_CHARS {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!(
+                "The text has to be between 1 and {} characters",
+                MAX_CORRECTION_CHARS
+            ))),
+        );
+    }
+    match correct_writing(payload.language, text, state.redaction.log_user_content()).await {
+        Ok(correction) => (StatusCode::OK, Json(ApiResponse::data(correction))),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to correct the text");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to correct the text")),
+            )
+        }
+    }
+}
+
+#[instrument(
+    name = "handle_lesson",
+    fields(user.id = %user.sub, lesson.language = %params.language, user.time_zone = %time_zone),
+    skip_all,
+)]
+pub async fn handle_lesson(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    UserTimeZone(time_zone): UserTimeZone,
+    Json(params): Json<LessonParams>,
+) -> impl IntoResponse {
+    let params = match params.validate() {
+        Ok(params) => params,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(e.to_string())),
+            )
+        }
+    };
+    let progress = match state.lessons.progress(&user.sub, params.language).await {
+        Ok(progress) => progress,
+        Err(e) => {
+            error!(
+                error = format!("{:#}", e),
+                "Failed to read the lesson history"
+            );
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to generate a lesson")),
+            );
+        }
+    };
+    let now = state.clock.now_in(time_zone);
+    match generate_lesson(params, &progress, now, state.redaction.log_user_content()).await {
+        Ok(lesson) => {
+            // The user still gets their lesson, it just won't count towards the next ones.
+            if let Err(e) = state.lessons.save(&user.sub, &lesson).await {
+                error!(error = format!("{:#}", e), "Failed to record the lesson");
+            }
+            (StatusCode::OK, Json(ApiResponse::data(lesson)))
+        }
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to generate a lesson");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to generate a lesson")),
+            )
+        }
+    }
+}
+
+// Caps the page size of the lesson history.
+const MAX_LESSONS_LIMIT: usize = 100;
+
+#[instrument(name = "handle_list_lessons", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_list_lessons(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Query(query): Query<ListLessonsQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.min(MAX_LESSONS_LIMIT);
+    match state.lessons.list(&user.sub, query.language, limit).await {
+        Ok(lessons) => (StatusCode::OK, Json(ApiResponse::data(lessons))),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to list the lessons");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to list the lessons")),
+            )
+        }
+    }
+}
+
+#[instrument(name = "handle_get_lesson", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_get_lesson(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match get_item(&state.lessons, &user.sub, id, "Lesson").await {
+        Ok(lesson) => (StatusCode::OK, Json(ApiResponse::data(lesson))),
+        Err(response) => response,
+    }
+}
+
+#[instrument(name = "handle_create_quiz", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_create_quiz(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(payload): Json<QuizRequest>,
+) -> impl IntoResponse {
+    if !(1..=MAX_QUESTIONS).contains(&payload.questions) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!(
+                "A quiz has between 1 and {} questions",
+                MAX_QUESTIONS
+            ))),
+        );
+    }
+
+    let (params, material) = match payload.source {
+        QuizSource::Lesson { lesson_id } => {
+            let lesson = match get_item(&state.lessons, &user.sub, lesson_id, "Lesson").await {
+                Ok(lesson) => lesson,
+                Err(response) => return response,
+            };
+            let params = QuizParams {
+                language: lesson.metadata.params.language,
+                explanation_language: lesson.metadata.params.explanation_language.clone(),
+                questions: payload.questions,
+            };
+            (params, serde_json::to_string(&lesson.content))
+        }
+        QuizSource::Translation {
+            language,
+            translation,
+        } => {
+            let params = QuizParams {
+                language,
+                explanation_language: "English".to_string(),
+                questions: payload.questions,
+            };
+            (params, serde_json::to_string(&translation))
+        }
+    };
+    let material = material.expect("lessons and translations serialize to JSON");
+
+    let quiz = match generate_quiz(&params, &material, state.redaction.log_user_content()).await {
+        Ok(quiz) => quiz,
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to generate a quiz");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to generate a quiz")),
+            );
+        }
+    };
+    if let Err(e) = state.quizzes.save(&user.sub, &quiz).await {
+        error!(error = format!("{:#}", e), "Failed to store the quiz");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("Failed to store the quiz")),
+        );
+    }
+    (StatusCode::CREATED, Json(ApiResponse::data(quiz.view())))
+}
+
+#[instrument(name = "handle_get_quiz", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_get_quiz(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match get_item(&state.quizzes, &user.sub, id, "Quiz").await {
+        Ok(quiz) => (StatusCode::OK, Json(ApiResponse::data(quiz.view()))),
+        Err(response) => response,
+    }
+}
+
+#[instrument(name = "handle_grade_quiz", fields(user.id = %user.sub), skip(state, user, payload))]
+pub async fn handle_grade_quiz(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<GradeQuizRequest>,
+) -> impl IntoResponse {
+    let quiz = match get_item(&state.quizzes, &user.sub, id, "Quiz").await {
+        Ok(quiz) => quiz,
+        Err(response) => return response,
+    };
+    match grade_quiz(&quiz, &payload.answers, state.redaction.log_user_content()).await {
+        Ok(result) => (StatusCode::OK, Json(ApiResponse::data(result))),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to grade the quiz");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to grade the quiz")),
+            )
+        }
+    }
+}
+
+#[instrument(
+    name = "handle_create_exercise",
+    fields(user.id = %user.sub, exercise.language = %params.language),
+    skip_all,
+)]
+pub async fn handle_create_exercise(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(params): Json<LessonParams>,
+) -> impl IntoResponse {
+    let params = match params.validate() {
+        Ok(params) => params,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error(e.to_string())),
+            )
+        }
+    };
+    let recent = match state
+        .practice
+        .recent_sources(&user.sub, params.language)
+        .await
+    {
+        Ok(recent) => recent,
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to read the exercises");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to generate an exercise")),
+            );
+        }
+    };
+    let exercise =
+        match generate_exercise(params, &recent, state.redaction.log_user_content()).await {
+            Ok(exercise) => exercise,
+            Err(e) => {
+                error!(error = format!("{:#}", e), "Failed to generate an exercise");
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::error("Failed to generate an exercise")),
+                );
+            }
+        };
+    if let Err(e) = state.practice.save(&user.sub, &exercise).await {
+        error!(error = format!("{:#}", e), "Failed to store the exercise");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("Failed to store the exercise")),
+        );
+    }
+    (
+        StatusCode::CREATED,
+        Json(ApiResponse::data(exercise.view())),
+    )
+}
+
+// Caps the page size of the translation exercises.
+const MAX_EXERCISES_LIMIT: usize = 100;
+
+#[instrument(name = "handle_list_exercises", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_list_exercises(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Query(query): Query<ListExercisesQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.min(MAX_EXERCISES_LIMIT);
+    match state.practice.list(&user.sub, query.language, limit).await {
+        Ok(exercises) => (StatusCode::OK, Json(ApiResponse::data(exercises))),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to list the exercises");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to list the exercises")),
+            )
+        }
+    }
+}
+
+#[instrument(name = "handle_get_exercise", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_get_exercise(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match get_item(&state.practice, &user.sub, id, "Exercise").await {
+        Ok(exercise) => (StatusCode::OK, Json(ApiResponse::data(exercise.view()))),
+        Err(response) => response,
+    }
+}
+
+#[instrument(name = "handle_grade_attempt", fields(user.id = %user.sub), skip(state, user, payload))]
+pub async fn handle_grade_attempt(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<AttemptRequest>,
+) -> impl IntoResponse {
+    let exercise = match get_item(&state.practice, &user.sub, id, "Exercise").await {
+        Ok(exercise) => exercise,
+        Err(response) => return response,
+    };
+    let translation = payload.translation.trim();
+    if translation.is_empty() || translation.chars().count() > MAX_TRANSLATION_LEN {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!(
+                "The translation has to be between 1 and {} characters",
+                MAX_TRANSLATION_LEN
+            ))),
+        );
+    }
+    if let Err(e) = exercise.check_attempt() {
+        return (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(e.to_string())),
+        );
+    }
+
+    let attempt =
+        match grade_attempt(&exercise, translation, state.redaction.log_user_content()).await {
+            Ok(attempt) => attempt,
+            Err(e) => {
+                error!(
+                    error = format!("{:#}", e),
+                    "Failed to grade the translation"
+                );
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::error("Failed to grade the translation")),
+                );
+            }
+        };
+    // Other attempts may have been graded in the meantime.
+    match state.practice.add_attempt(&user.sub, id, attempt).await {
+        Ok(result) => (StatusCode::OK, Json(ApiResponse::data(result))),
+        Err(UpdateError::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Exercise not found")),
+        ),
+        Err(UpdateError::Rejected(e)) => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(UpdateError::Storage(e)) => {
+            error!(error = format!("{:#}", e), "Failed to store the attempt");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to store the attempt")),
+            )
+        }
+    }
+}
+
+fn session_error_status(e: &SessionError) -> StatusCode {
+    match e {
+        SessionError::Params(_) | SessionError::Invalid(_) => StatusCode::BAD_REQUEST,
+        SessionError::Ended | SessionError::TooLong => StatusCode::CONFLICT,
+    }
+}
+
+#[instrument(name = "handle_create_session", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_create_session(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(payload): Json<CreateSessionRequest>,
+) -> impl IntoResponse {
+    let session = match Session::new(payload.language, payload.level, &payload.scenario) {
+        Ok(session) => session,
+        Err(e) => {
+            return (
+                session_error_status(&e),
+                Json(ApiResponse::error(e.to_string())),
+            )
+        }
+    };
+    if let Err(e) = state.sessions.save(&user.sub, &session).await {
+        error!(error = format!("{:#}", e), "Failed to store the session");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("Failed to store the session")),
+        );
+    }
+    (StatusCode::CREATED, Json(ApiResponse::data(session)))
+}
+
+#[instrument(name = "handle_list_sessions", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_list_sessions(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+) -> impl IntoResponse {
+    match state.sessions.list(&user.sub).await {
+        Ok(sessions) => (StatusCode::OK, Json(ApiResponse::data(sessions))),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to list the sessions");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to list the sessions")),
+            )
+        }
+    }
+}
+
+#[instrument(name = "handle_get_session", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_get_session(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match get_item(&state.sessions, &user.sub, id, "Session").await {
+        Ok(session) => (StatusCode::OK, Json(ApiResponse::data(session))),
+        Err(response) => response,
+    }
+}
+
+#[instrument(name = "handle_session_turn", fields(user.id = %user.sub), skip(state, user, payload))]
+pub async fn handle_session_turn(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<SessionTurnRequest>,
+) -> impl IntoResponse {
+    let session = match get_item(&state.sessions, &user.sub, id, "Session").await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    if let Err(e) = session.check_turn(&payload.message) {
+        return (
+            session_error_status(&e),
+            Json(ApiResponse::error(e.to_string())),
+        );
+    }
+
+    let turn = match take_turn(
+        &session,
+        &payload.message,
+        state.redaction.log_user_content(),
+    )
+    .await
+    {
+        Ok(turn) => turn,
+        Err(e) => {
+            error!(
+                error = format!("{:#}", e),
+                "Failed to get the tutor's reply"
+            );
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to get the tutor's reply")),
+            );
+        }
+    };
+    // The session may have changed while the tutor was replying.
+    session_response(
+        state
+            .sessions
+            .update(&user.sub, id, move |session| session.add_turn(turn))
+            .await,
+    )
+}
+
+#[instrument(name = "handle_end_session", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_end_session(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let session = match get_item(&state.sessions, &user.sub, id, "Session").await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let summary = match end_session(&session, state.redaction.log_user_content()).await {
+        Ok(summary) => summary,
+        Err(e) => match e.downcast_ref::<SessionError>() {
+            Some(e) => {
+                return (
+                    session_error_status(e),
+                    Json(ApiResponse::error(e.to_string())),
+                )
+            }
+            None => {
+                error!(
+                    error = format!("{:#}", e),
+                    "Failed to summarize the session"
+                );
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::error("Failed to summarize the session")),
+                );
+            }
+        },
+    };
+    session_response(
+        state
+            .sessions
+            .update(&user.sub, id, move |session| {
+                session.end(summary.clone())?;
+                Ok(summary)
+            })
+            .await,
+    )
+}
+
+// One of the user's items, or the response to send when there's no such item.
+async fn get_item<T: Item, D>(
+    store: &PerUserStore<T>,
+    sub: &str,
+    id: Uuid,
+    name: &str,
+) -> Result<T, (StatusCode, Json<ApiResponse<D>>)> {
+    match store.get(sub, id).await {
+        Ok(Some(item)) => Ok(item),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!("{} not found", name))),
+        )),
+        Err(e) => {
+            let message = format!("Failed to get the {}", name.to_lowercase());
+            error!(error = format!("{:#}", e), "{}", message);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(message)),
+            ))
+        }
+    }
+}
+
+fn session_response<T>(
+    result: Result<T, UpdateError<SessionError>>,
+) -> (StatusCode, Json<ApiResponse<T>>) {
+    match result {
+        Ok(data) => (StatusCode::OK, Json(ApiResponse::data(data))),
+        Err(UpdateError::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Session not found")),
+        ),
+        Err(UpdateError::Rejected(e)) => (
+            session_error_status(&e),
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(UpdateError::Storage(e)) => {
+            error!(error = format!("{:#}", e), "Failed to store the session");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to store the session")),
+            )
+        }
+    }
+}
+
+#[instrument(
+    name = "handle_submit_job",
+    fields(user.id = %user.sub, text.length = %payload.text.len()),
+    skip_all,
+)]
+pub async fn handle_submit_job(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(payload): Json<TranslationRequest>,
+) -> impl IntoResponse {
+    job_response(
+        state.jobs.submit(&user.sub, &payload.text).await,
+        StatusCode::ACCEPTED,
+    )
+}
+
+#[instrument(name = "handle_get_job", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_get_job(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    job_response(state.jobs.get(&user.sub, id).await, StatusCode::OK)
+}
+
+#[instrument(name = "handle_cancel_job", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_cancel_job(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    job_response(state.jobs.cancel(&user.sub, id).await, StatusCode::OK)
+}
+
+#[instrument(name = "handle_dev_token", fields(user.id = %request.sub), skip_all)]
+pub async fn handle_dev_token(
+    State(issuer): State<Arc<DevIssuer>>,
+    Json(request): Json<DevTokenRequest>,
+) -> impl IntoResponse {
+    match issuer.mint(&request) {
+        Ok(token) => (StatusCode::OK, Json(ApiResponse::data(token))),
+        Err(e @ DevTokenError::TooLong) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(e.to_string())),
+        ),
+        Err(e) => {
+            error!(error = format!("{:#}", e), "Failed to mint a dev token");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to mint a dev token")),
+            )
+        }
+    }
+}
+
+pub async fn handle_dev_jwks(State(issuer): State<Arc<DevIssuer>>) -> impl IntoResponse {
+    Json(issuer.jwks().clone())
+}
+
+pub async fn handle_dev_discovery(State(issuer): State<Arc<DevIssuer>>) -> impl IntoResponse {
+    Json(json!({
+        "issuer": issuer.issuer(),
+        "jwks_uri": format!("{}/.well-known/jwks.json", issuer.issuer()),
+        "id_token_signing_alg_values_supported": ["ES256"],
+    }))
+}
+
+// Revoked tokens are remembered this long unless told when they expire.
+// It matches the default lifetime of Cognito refresh tokens, which `origin_jti` revocations cover.
+const DEFAULT_REVOCATION_TTL: chrono::Duration = chrono::Duration::days(30);
+
+#[instrument(name = "handle_list_revocations", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_list_revocations(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+) -> impl IntoResponse {
+    Json(ApiResponse::data(state.revocations.revocations()))
+}
+
+#[instrument(name = "handle_revoke_token", fields(user.id = %user.sub), skip_all)]
+pub async fn handle_revoke_token(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(payload): Json<RevokeTokenRequest>,
+) -> impl IntoResponse {
+    let expires_at = payload
+        .expires_at
+        .unwrap_or_else(|| Utc::now() + DEFAULT_REVOCATION_TTL);
+    revocation_response(
+        state
+            .revocations
+            .revoke_token(&payload.jti, expires_at)
+            .await
+            .map(|_| state.revocations.revocations()),
+    )
+}
+
+#[instrument(name = "handle_revoke_user", fields(user.id = %user.sub), skip(state, user, payload))]
+pub async fn handle_revoke_user(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(sub): Path<String>,
+    Json(payload): Json<RevokeUserRequest>,
+) -> impl IntoResponse {
+    let issued_before = payload.issued_before.unwrap_or_else(Utc::now);
+    revocation_response(
+        state
+            .revocations
+            .revoke_user(&sub, issued_before)
+            .await
+            .map(|_| state.revocations.revocations()),
+    )
+}
+
+#[instrument(name = "handle_clear_user_revocation", fields(user.id = %user.sub), skip(state, user))]
+pub async fn handle_clear_user_revocation(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(sub): Path<String>,
+) -> impl IntoResponse {
+    match state.revocations.clear_user(&sub).await {
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error(format!(
+                "no revocation for user {}",
+                sub
+            ))),
+        ),
+        result => revocation_response(result.map(|_| state.revocations.revocations())),
+    }
+}
+
+fn revocation_response(
+    result: Result<Revocations>,
+) -> (StatusCode, Json<ApiResponse<Revocations>>) {
+    match result {
+        Ok(revocations) => (StatusCode::OK, Json(ApiResponse::data(revocations))),
+        Err(e) => {
+            error!(
+                error = format!("{:#}", e),
+                "Failed to update the revocation list"
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to update the revocation list")),
+            )
+        }
+    }
+}
+
+fn job_response(
+    result: Result<JobView, JobError>,
+    success: StatusCode,
+) -> (StatusCode, Json<ApiResponse<JobView>>) {
+    match result {
+        Ok(view) => (success, Json(ApiResponse::data(view))),
+        Err(e) => {
+            let status = match e {
+                JobError::EmptyInput | JobError::TooLong => StatusCode::BAD_REQUEST,
+                JobError::TooManyJobs(_) => StatusCode::TOO_MANY_REQUESTS,
+                JobError::QueueFull => StatusCode::SERVICE_UNAVAILABLE,
+                JobError::NotFound(_) => StatusCode::NOT_FOUND,
+                JobError::AlreadyFinished(_) => StatusCode::CONFLICT,
+            };
+            (status, Json(ApiResponse::error(e.to_string())))
+        }
+    }
+}
+
+pub(super) async fn process_translation(
+    text: &str,
+    redaction: &RedactionPolicy,
+) -> Result<TranslationResponse> {
     // Initialize the AWS client.
     let aws_client = aws::AWSClient::new(Some(aws::InferenceParameters {
         temperature: 0.8,
@@ -73,9 +1113,10 @@ async fn process_translation(text: &str) -> Result<TranslationResponse> {
         top_p: 0.95,
     }))
     .await
-    .context("Error creating AWS client")?;
+    .context("Error creating AWS client")?
+    .with_content_capture(redaction.log_user_content());
 
-    let message = ConversationBuilder::new()
+    let conversation = ConversationBuilder::new()
     .with_system_prompt(
         r#"You are the brains for an app that aims to teach Japanese and Chinese.
 Because you are the brains for an app, you need to respond in JSON format.
@@ -143,11 +1184,11 @@ Your response should look like the following example:
     .context("Error creating messages for AWS Bedrock")?;
 
     let output = aws_client
-        .create_conversation(vec![message])
+        .create_conversation(conversation)
         .await
         .context("Error creating conversation with AWS Bedrock")?;
 
-    println!("Output:\n{}", output);
+    debug!(output = %redaction.user_content(&output), "received model output");
     let response: TranslationResponse =
         serde_json::from_str(&output).context("Error parsing Bedrock response")?;
 
@@ -0,0 +1,543 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use super::handlers::process_translation;
+use super::models::Translation;
+use crate::redaction::RedactionPolicy;
+
+// Upper bound on the amount of text sent to the model in a single call.
+// Documents are split into chunks of at most this many characters so that each
+// Bedrock call stays well within the inference limits.
+const MAX_CHUNK_CHARS: usize = 1500;
+
+// Every chunk is a Bedrock call, so a single job can't take more than this many of them.
+const MAX_JOB_CHUNKS: usize = 100;
+
+// How often the reaper looks for finished jobs to forget about.
+const REAPER_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct JobConfig {
+    pub workers: usize,
+    pub queue_size: usize,
+    pub retention: Duration,
+    /// How many queued or running jobs a single user may have, so that nobody fills the queue.
+    pub max_jobs_per_user: usize,
+}
+
+#[derive(Error, Debug)]
+pub enum JobError {
+    #[error("no text to translate")]
+    EmptyInput,
+
+    #[error(
+        "the text is too long, jobs take up to {} chunks of {} characters",
+        MAX_JOB_CHUNKS,
+        MAX_CHUNK_CHARS
+    )]
+    TooLong,
+
+    #[error("you already have {0} jobs queued or running")]
+    TooManyJobs(usize),
+
+    #[error("the job queue is full")]
+    QueueFull,
+
+    #[error("job {0} not found")]
+    NotFound(Uuid),
+
+    #[error("job {0} has already finished")]
+    AlreadyFinished(Uuid),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobProgress {
+    completed_chunks: usize,
+    total_chunks: usize,
+}
+
+// What we hand back to clients: a snapshot of the job at the time of the request.
+// Translations are the partial results accumulated so far.
+#[derive(Clone, Debug, Serialize)]
+pub struct JobView {
+    id: Uuid,
+    status: JobStatus,
+    progress: JobProgress,
+    translations: Vec<Translation>,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct Job {
+    id: Uuid,
+    owner: String,
+    status: JobStatus,
+    chunks: Vec<String>,
+    completed_chunks: usize,
+    translations: Vec<Translation>,
+    error: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    cancel: CancellationToken,
+}
+
+impl Job {
+    fn view(&self) -> JobView {
+        JobView {
+            id: self.id,
+            status: self.status,
+            progress: JobProgress {
+                completed_chunks: self.completed_chunks,
+                total_chunks: self.chunks.len(),
+            },
+            translations: self.translations.clone(),
+            error: self.error.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+
+    fn set_status(&mut self, status: JobStatus) {
+        self.status = status;
+        self.updated_at = Utc::now();
+    }
+}
+
+/// Keeps track of translation jobs and feeds them to a pool of background workers.
+///
+/// Jobs are scoped to the user that submitted them: lookups by any other user
+/// behave as if the job did not exist.
+#[derive(Debug)]
+pub struct JobManager {
+    jobs: RwLock<HashMap<Uuid, Job>>,
+    queue: mpsc::Sender<Uuid>,
+    max_jobs_per_user: usize,
+    redaction: Arc<RedactionPolicy>,
+}
+
+impl JobManager {
+    /// Creates the manager and spawns the worker pool and the reaper task.
+    /// Must be called from within a Tokio runtime.
+    pub fn start(config: JobConfig, redaction: Arc<RedactionPolicy>) -> Arc<Self> {
+        let (manager, rx) = Self::new(config.queue_size, config.max_jobs_per_user, redaction);
+
+        // Workers share a single receiver, whoever grabs the lock first gets the next job.
+        let rx = Arc::new(Mutex::new(rx));
+        for worker_id in 0..config.workers.max(1) {
+            let manager = Arc::clone(&manager);
+            let rx = Arc::clone(&rx);
+            tokio::spawn(async move {
+                loop {
+                    let next = rx.lock().await.recv().await;
+                    match next {
+                        Some(job_id) => manager.run_job(worker_id, job_id).await,
+                        None => break, // The manager was dropped.
+                    }
+                }
+            });
+        }
+
+        // Hold a weak reference so the reaper doesn't keep the manager alive forever.
+        let weak = Arc::downgrade(&manager);
+        let retention = config.retention;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REAPER_INTERVAL);
+            loop {
+                interval.tick().await;
+                match weak.upgrade() {
+                    Some(manager) => manager.reap(retention).await,
+                    None => break,
+                }
+            }
+        });
+
+        manager
+    }
+
+    // The manager, without workers to take the jobs off the queue.
+    fn new(
+        queue_size: usize,
+        max_jobs_per_user: usize,
+        redaction: Arc<RedactionPolicy>,
+    ) -> (Arc<Self>, mpsc::Receiver<Uuid>) {
+        let (tx, rx) = mpsc::channel(queue_size.max(1));
+        let manager = Arc::new(Self {
+            jobs: RwLock::new(HashMap::new()),
+            queue: tx,
+            max_jobs_per_user: max_jobs_per_user.max(1),
+            redaction,
+        });
+        (manager, rx)
+    }
+
+    #[instrument(name = "submit_job", skip(self, text), fields(user.id = %owner), err)]
+    pub async fn submit(&self, owner: &str, text: &str) -> Result<JobView, JobError> {
+        let chunks = split_into_chunks(text, MAX_CHUNK_CHARS);
+        if chunks.is_empty() {
+            return Err(JobError::EmptyInput);
+        }
+        if chunks.len() > MAX_JOB_CHUNKS {
+            return Err(JobError::TooLong);
+        }
+
+        let now = Utc::now();
+        let job = Job {
+            id: Uuid::new_v4(),
+            owner: owner.to_string(),
+            status: JobStatus::Queued,
+            chunks,
+            completed_chunks: 0,
+            translations: Vec::new(),
+            error: None,
+            created_at: now,
+            updated_at: now,
+            cancel: CancellationToken::new(),
+        };
+        let id = job.id;
+        let view = job.view();
+
+        let mut jobs = self.jobs.write().await;
+        let active = jobs
+            .values()
+            .filter(|job| job.owner == owner && !job.status.is_finished())
+            .count();
+        if active >= self.max_jobs_per_user {
+            return Err(JobError::TooManyJobs(active));
+        }
+
+        // Register the job before queueing it so that a worker never sees an unknown ID.
+        jobs.insert(id, job);
+        if self.queue.try_send(id).is_err() {
+            jobs.remove(&id);
+            return Err(JobError::QueueFull);
+        }
+        drop(jobs);
+
+        info!(job.id = %id, chunks = view.progress.total_chunks, "job queued");
+        Ok(view)
+    }
+
+    pub async fn get(&self, owner: &str, id: Uuid) -> Result<JobView, JobError> {
+        let jobs = self.jobs.read().await;
+        match jobs.get(&id) {
+            Some(job) if job.owner == owner => Ok(job.view()),
+            _ => Err(JobError::NotFound(id)),
+        }
+    }
+
+    #[instrument(name = "cancel_job", skip(self), fields(user.id = %owner), err)]
+    pub async fn cancel(&self, owner: &str, id: Uuid) -> Result<JobView, JobError> {
+        let mut jobs = self.jobs.write().await;
+        let job = match jobs.get_mut(&id) {
+            Some(job) if job.owner == owner => job,
+            _ => return Err(JobError::NotFound(id)),
+        };
+        if job.status.is_finished() {
+            return Err(JobError::AlreadyFinished(id));
+        }
+
+        // A running worker drops its in-flight model call as soon as the token is cancelled.
+        job.cancel.cancel();
+        job.set_status(JobStatus::Cancelled);
+        info!(job.id = %id, "job cancelled");
+        Ok(job.view())
+    }
+
+    #[instrument(name = "run_job", skip(self), fields(job.id = %job_id))]
+    async fn run_job(&self, worker_id: usize, job_id: Uuid) {
+        let (chunks, cancel) = {
+            let mut jobs = self.jobs.write().await;
+            let Some(job) = jobs.get_mut(&job_id) else {
+                // Reaped before a worker got to it.
+                return;
+            };
+            if job.status != JobStatus::Queued {
+                return;
+            }
+            job.set_status(JobStatus::Running);
+            (job.chunks.clone(), job.cancel.clone())
+        };
+        info!(worker_id, chunks = chunks.len(), "job started");
+
+        for chunk in chunks {
+            let result = tokio::select! {
+                _ = cancel.cancelled() => {
+                    info!("job cancelled while running");
+                    return;
+                }
+                result = process_translation(&chunk, &self.redaction) => result,
+            };
+
+            let mut jobs = self.jobs.write().await;
+            let Some(job) = jobs.get_mut(&job_id) else {
+                return;
+            };
+            if job.status != JobStatus::Running {
+                return;
+            }
+            match result {
+                Ok(response) => {
+                    job.translations.extend(response.into_translations());
+                    job.completed_chunks += 1;
+                    job.updated_at = Utc::now();
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to process job chunk. Error chain: \n{:?}",
+                        e.chain().collect::<Vec<_>>()
+                    );
+                    job.error = Some("Failed to create translation response".to_string());
+                    job.set_status(JobStatus::Failed);
+                    return;
+                }
+            }
+        }
+
+        if let Some(job) = self.jobs.write().await.get_mut(&job_id) {
+            if job.status == JobStatus::Running {
+                job.set_status(JobStatus::Completed);
+                info!("job completed");
+            }
+        }
+    }
+
+    async fn reap(&self, retention: Duration) {
+        let Ok(retention) = chrono::Duration::from_std(retention) else {
+            warn!("job retention is out of range, skipping reaping");
+            return;
+        };
+        let cutoff = Utc::now() - retention;
+
+        let mut jobs = self.jobs.write().await;
+        let before = jobs.len();
+        jobs.retain(|_, job| !job.status.is_finished() || job.updated_at > cutoff);
+        let reaped = before - jobs.len();
+        if reaped > 0 {
+            info!(reaped, "reaped finished jobs");
+        }
+    }
+}
+
+/// Splits text into chunks of at most `max_chars` characters, breaking on sentence
+/// boundaries. A single sentence longer than `max_chars` gets a chunk of its own.
+fn split_into_chunks(text: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    for sentence in split_sentences(text) {
+        let sentence_len = sentence.chars().count();
+        if current_len > 0 && current_len + sentence_len > max_chars {
+            chunks.push(current.trim().to_string());
+            current.clear();
+            current_len = 0;
+        }
+        current.push_str(sentence);
+        current_len += sentence_len;
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current.trim().to_string());
+    }
+
+    chunks.retain(|c| !c.is_empty());
+    chunks
+}
+
+// Splits after sentence terminators (English, Japanese and Chinese) and line breaks,
+// keeping the terminators attached to their sentence.
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for (idx, c) in text.char_indices() {
+        if matches!(c, '.' | '!' | '?' | '。' | '！' | '？' | '\n') {
+            let end = idx + c.len_utf8();
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(queue_size: usize) -> (Arc<JobManager>, mpsc::Receiver<Uuid>) {
+        JobManager::new(queue_size, 3, Arc::new(RedactionPolicy::default()))
+    }
+
+    #[test]
+    fn splits_after_cjk_terminators() {
+        assert_eq!(
+            split_sentences("今日は晴れ。明日は雨！本当？ Yes"),
+            ["今日は晴れ。", "明日は雨！", "本当？", " Yes"]
+        );
+        assert_eq!(
+            split_into_chunks("今日は晴れ。明日は雨！本当？", 6),
+            ["今日は晴れ。", "明日は雨！", "本当？"]
+        );
+        assert_eq!(
+            split_into_chunks("今日は晴れ。明日は雨！本当？", 11),
+            ["今日は晴れ。明日は雨！", "本当？"]
+        );
+    }
+
+    #[test]
+    fn long_sentences_get_a_chunk_of_their_own() {
+        let long = format!("{}.", "x".repeat(MAX_CHUNK_CHARS + 10));
+        let text = format!("Short. {} End.", long);
+        assert_eq!(
+            split_into_chunks(&text, MAX_CHUNK_CHARS),
+            ["Short.", long.as_str(), "End."]
+        );
+    }
+
+    #[test]
+    fn blank_text_has_no_chunks() {
+        assert!(split_into_chunks("", MAX_CHUNK_CHARS).is_empty());
+        assert!(split_into_chunks("  \n \n\t", MAX_CHUNK_CHARS).is_empty());
+        assert!(split_into_chunks(" \n \n", 1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_blank_text_and_full_queues() {
+        let (manager, _rx) = manager(1);
+        assert!(matches!(
+            manager.submit("alice", " \n ").await,
+            Err(JobError::EmptyInput)
+        ));
+
+        manager.submit("alice", "Hello.").await.unwrap();
+        assert!(matches!(
+            manager.submit("alice", "Goodbye.").await,
+            Err(JobError::QueueFull)
+        ));
+        assert_eq!(manager.jobs.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn limits_job_sizes_and_jobs_per_user() {
+        let (manager, _rx) = manager(10);
+        let sentence = format!("{}.", "x".repeat(MAX_CHUNK_CHARS - 1));
+        let too_long = vec![sentence.as_str(); MAX_JOB_CHUNKS + 1].join(" ");
+        assert!(matches!(
+            manager.submit("alice", &too_long).await,
+            Err(JobError::TooLong)
+        ));
+
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(manager.submit("alice", "Hello.").await.unwrap().id);
+        }
+        assert!(matches!(
+            manager.submit("alice", "Hello.").await,
+            Err(JobError::TooManyJobs(3))
+        ));
+        // Other users still get their jobs in.
+        manager.submit("bob", "Hello.").await.unwrap();
+
+        // Finished jobs don't count.
+        manager.cancel("alice", ids[0]).await.unwrap();
+        manager.submit("alice", "Hello.").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn jobs_are_only_visible_to_their_owner() {
+        let (manager, _rx) = manager(10);
+        let job = manager.submit("alice", "Hello. Goodbye.").await.unwrap();
+
+        let view = manager.get("alice", job.id).await.unwrap();
+        assert_eq!(view.status, JobStatus::Queued);
+        assert_eq!(view.progress.total_chunks, 1);
+        assert!(matches!(
+            manager.get("bob", job.id).await,
+            Err(JobError::NotFound(id)) if id == job.id
+        ));
+        assert!(matches!(
+            manager.cancel("bob", job.id).await,
+            Err(JobError::NotFound(_))
+        ));
+        assert_eq!(
+            manager.get("alice", job.id).await.unwrap().status,
+            JobStatus::Queued
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_are_never_run() {
+        let (manager, mut rx) = manager(10);
+        let job = manager.submit("alice", "Hello.").await.unwrap();
+
+        let cancelled = manager.cancel("alice", job.id).await.unwrap();
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        assert!(manager.jobs.read().await[&job.id].cancel.is_cancelled());
+        assert!(matches!(
+            manager.cancel("alice", job.id).await,
+            Err(JobError::AlreadyFinished(_))
+        ));
+
+        // A worker picking it up leaves it be, without calling the model.
+        let queued = rx.recv().await.unwrap();
+        manager.run_job(0, queued).await;
+        let view = manager.get("alice", job.id).await.unwrap();
+        assert_eq!(view.status, JobStatus::Cancelled);
+        assert_eq!(view.progress.completed_chunks, 0);
+    }
+
+    #[tokio::test]
+    async fn reaps_jobs_finished_past_the_retention() {
+        let (manager, _rx) = manager(10);
+        let old = manager.submit("alice", "Old.").await.unwrap();
+        let recent = manager.submit("alice", "Recent.").await.unwrap();
+        let queued = manager.submit("alice", "Queued.").await.unwrap();
+        manager.cancel("alice", old.id).await.unwrap();
+        manager.cancel("alice", recent.id).await.unwrap();
+        {
+            let mut jobs = manager.jobs.write().await;
+            let long_ago = Utc::now() - chrono::Duration::hours(2);
+            jobs.get_mut(&old.id).unwrap().updated_at = long_ago;
+            // Jobs that haven't finished are kept however old they are.
+            jobs.get_mut(&queued.id).unwrap().updated_at = long_ago;
+        }
+
+        manager.reap(Duration::from_secs(3600)).await;
+        assert!(matches!(
+            manager.get("alice", old.id).await,
+            Err(JobError::NotFound(_))
+        ));
+        assert!(manager.get("alice", recent.id).await.is_ok());
+        assert!(manager.get("alice", queued.id).await.is_ok());
+    }
+}
@@ -1,7 +1,20 @@
 mod auth;
 mod core; // Core server implementation.
+mod correction; // Corrections of what users write.
 mod handlers; // Request handlers.
+mod jobs; // Background translation jobs.
 mod models; // Data models. // AuthN/Z middleware.
+mod request_context; // Request IDs and trace context propagation.
+mod state; // State shared by the handlers.
+mod storage; // The users' translation history and flashcards.
+mod time_zone; // The time zone of the caller.
+mod trace; // HTTP request spans and metrics.
 
 // Re-export the main server function and any other public interfaces.
-pub use core::run_server;
+pub use auth::{
+    groups, scopes, ApiKey, ApiKeyStore, AuthenticatedUser, ClaimMapping, Claims, DevIssuer,
+    DevToken, DevTokenRequest, IssuerConfig, NewApiKey, OidcConfig, Policy, RevocationList,
+};
+pub use core::{run_server, ServerConfig};
+pub use jobs::JobConfig;
+pub use storage::{CardRepository, HistoryRepository, SqliteStorage};
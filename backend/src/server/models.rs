@@ -1,7 +1,15 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt;
 
+use uuid::Uuid;
+
+use crate::flashcards::{Card, Grade};
+use crate::lesson::Level;
+use crate::quiz::Answer;
+use crate::Language;
+
 #[derive(Debug, Serialize)]
 pub enum BuilderError {
     MissingField(&'static str),
@@ -20,7 +28,7 @@ impl fmt::Display for BuilderError {
 impl Error for BuilderError {}
 
 // Field within an "example".
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Example {
     phrase: String,
     pronunciation: String,
@@ -68,7 +76,7 @@ impl ExampleBuilder {
 }
 
 // Field within a "language_translation" (japanese or chinese).
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LanguageTranslation {
     translation: String,
     pronunciation: String,
@@ -137,7 +145,7 @@ impl LanguageTranslationBuilder {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Translation {
     original: String,
     japanese: LanguageTranslation,
@@ -184,7 +192,7 @@ impl TranslationBuilder {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TranslationResponse {
     translations: Vec<Translation>,
 }
@@ -211,6 +219,47 @@ impl TranslationResponseBuilder {
     }
 }
 
+// Flashcards are made of the phrases users want to drill.
+impl Example {
+    /// A card with the English on the front and the phrase on the back.
+    pub fn card(&self, language: Language, now: DateTime<Utc>) -> Card {
+        Card::new(
+            language,
+            &self.translation,
+            &self.phrase,
+            &self.pronunciation,
+            Vec::new(),
+            now,
+        )
+    }
+}
+
+impl Translation {
+    /// A card for the translation in `language`, with its grammar as notes, then one per example.
+    pub fn cards(&self, language: Language, now: DateTime<Utc>) -> Vec<Card> {
+        let translation = match language {
+            Language::Japanese => &self.japanese,
+            Language::Chinese => &self.chinese,
+        };
+        let card = Card::new(
+            language,
+            &self.original,
+            &translation.translation,
+            &translation.pronunciation,
+            translation.grammar.clone(),
+            now,
+        );
+        std::iter::once(card)
+            .chain(
+                translation
+                    .examples
+                    .iter()
+                    .map(|example| example.card(language, now)),
+            )
+            .collect()
+    }
+}
+
 // Add convenient builder creation methods to our main types.
 impl Example {
     pub fn builder() -> ExampleBuilder {
@@ -234,9 +283,214 @@ impl TranslationResponse {
     pub fn builder() -> TranslationResponseBuilder {
         TranslationResponseBuilder::new()
     }
+
+    pub fn into_translations(self) -> Vec<Translation> {
+        self.translations
+    }
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct TranslationRequest {
     pub text: String,
 }
+
+#[derive(Deserialize, Debug)]
+pub struct RevokeTokenRequest {
+    /// A `jti`, or an `origin_jti` to revoke every token refreshed from the same sign in.
+    pub jti: String,
+    /// When the token expires, after which there is no point in remembering it.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct RevokeUserRequest {
+    /// Defaults to now, i.e., every token issued so far.
+    pub issued_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListHistoryQuery {
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_history_limit")]
+    pub limit: usize,
+}
+
+fn default_history_limit() -> usize {
+    20
+}
+
+/// A page of lessons.
+#[derive(Deserialize, Debug)]
+pub struct ListLessonsQuery {
+    /// Only the ones in this language.
+    pub language: Option<Language>,
+    #[serde(default = "default_lessons_limit")]
+    pub limit: usize,
+}
+
+fn default_lessons_limit() -> usize {
+    20
+}
+
+/// A page of translation exercises.
+#[derive(Deserialize, Debug)]
+pub struct ListExercisesQuery {
+    /// Only the ones in this language.
+    pub language: Option<Language>,
+    #[serde(default = "default_exercises_limit")]
+    pub limit: usize,
+}
+
+fn default_exercises_limit() -> usize {
+    20
+}
+
+/// What to quiz the user on: one of their lessons, or a translation they got.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum QuizSource {
+    Lesson {
+        lesson_id: Uuid,
+    },
+    Translation {
+        language: Language,
+        translation: Box<Translation>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QuizRequest {
+    #[serde(flatten)]
+    pub source: QuizSource,
+    #[serde(default = "default_quiz_questions")]
+    pub questions: usize,
+}
+
+fn default_quiz_questions() -> usize {
+    8
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GradeQuizRequest {
+    /// In the order of the questions, `null` for the skipped ones.
+    pub answers: Vec<Option<Answer>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AttemptRequest {
+    /// The user's translation of the exercise.
+    pub translation: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateSessionRequest {
+    /// What to role-play, e.g., "ordering at a restaurant".
+    pub scenario: String,
+    pub language: Language,
+    pub level: Option<Level>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionTurnRequest {
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CorrectionRequest {
+    /// What the user wrote.
+    pub text: String,
+    /// The language they wrote it in.
+    pub language: Language,
+}
+
+/// The kinds of mistakes learners make.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Particle,
+    WordOrder,
+    /// The wrong kanji or hanzi, e.g., from a mixed-up reading or tone.
+    CharacterChoice,
+    Politeness,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WritingError {
+    pub category: ErrorCategory,
+    pub original: String,
+    pub corrected: String,
+    pub explanation: String,
+}
+
+/// A run of characters that was kept, added or removed by the correction.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum DiffSegment {
+    Equal { text: String },
+    Insert { text: String },
+    Delete { text: String },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CorrectionResponse {
+    pub original: String,
+    /// The same as `original` when there is nothing to correct.
+    pub corrected: String,
+    /// How to get from `original` to `corrected`, character by character.
+    pub diff: Vec<DiffSegment>,
+    pub errors: Vec<WritingError>,
+    /// How natural `original` sounds to a native speaker, from 0 to 100.
+    pub naturalness: u8,
+}
+
+/// What to make flashcards of: a translation from the history, one the user got, or one of its
+/// examples.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum CardSource {
+    History { history_id: Uuid },
+    Translation { translation: Box<Translation> },
+    Example { example: Example },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateCardsRequest {
+    /// The language to drill.
+    pub language: Language,
+    #[serde(flatten)]
+    pub source: CardSource,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReviewRequest {
+    pub grade: Grade,
+}
+
+/// The cards to review next.
+#[derive(Deserialize, Debug)]
+pub struct DueCardsQuery {
+    /// Only the cards in this language.
+    pub language: Option<Language>,
+    #[serde(default = "default_due_cards_limit")]
+    pub limit: usize,
+}
+
+fn default_due_cards_limit() -> usize {
+    20
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeckStatsQuery {
+    /// Only the cards in this language.
+    pub language: Option<Language>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CreatedCards {
+    pub cards: Vec<Card>,
+    /// How many the user already had.
+    pub skipped: usize,
+}
@@ -0,0 +1,243 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderMap, HeaderName, HeaderValue,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::{global, Context};
+use uuid::Uuid;
+
+use super::handlers::ApiResponse;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// Incoming request IDs longer than this are replaced with one of our own.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Returns the ID of the request currently being handled, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Assigns an ID to every request and makes it available to everything downstream.
+///
+/// A well-formed `x-request-id` sent by the client is reused so that the ID can be
+/// correlated with client-side logs, otherwise a new one is generated.
+/// The ID is echoed back in the response headers and error responses that come back
+/// without a body (e.g., from the auth middleware or the timeout layer) are given a
+/// JSON body carrying it.
+pub async fn request_context(mut req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    // Make sure the header seen by the rest of the stack is the one we settled on.
+    let header_value =
+        HeaderValue::from_str(&request_id).expect("request IDs are valid header values");
+    req.headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value.clone());
+
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), async move {
+            let response = next.run(req).await;
+            if needs_error_body(&response) {
+                let message = response
+                    .status()
+                    .canonical_reason()
+                    .unwrap_or("Request failed")
+                    .to_string();
+                // Keep the original headers, e.g., the WWW-Authenticate challenge of a 401.
+                let (mut parts, _) = response.into_parts();
+                let (error_parts, body) = Json(ApiResponse::<()>::error(message))
+                    .into_response()
+                    .into_parts();
+                parts.headers.remove(CONTENT_LENGTH);
+                parts.headers.extend(error_parts.headers);
+                Response::from_parts(parts, body)
+            } else {
+                response
+            }
+        })
+        .await;
+
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value);
+    response
+}
+
+/// Extracts the W3C trace context sent by the caller so that our spans join its trace.
+pub fn extract_trace_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+fn needs_error_body(response: &Response<Body>) -> bool {
+    let status = response.status();
+    (status.is_client_error() || status.is_server_error())
+        && !response.headers().contains_key(CONTENT_TYPE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::to_bytes,
+        http::{header::WWW_AUTHENTICATE, StatusCode},
+        middleware,
+        routing::get,
+        Router,
+    };
+    use opentelemetry::trace::{TraceContextExt, TraceId};
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/request-id",
+                get(|| async { current_request_id().unwrap_or_default() }),
+            )
+            .route(
+                "/unauthorized",
+                get(|| async {
+                    (
+                        StatusCode::UNAUTHORIZED,
+                        [(WWW_AUTHENTICATE, "Bearer")],
+                        Body::empty(),
+                    )
+                }),
+            )
+            .route(
+                "/conflict",
+                get(|| async {
+                    (
+                        StatusCode::CONFLICT,
+                        Json(ApiResponse::<()>::error("Taken")),
+                    )
+                }),
+            )
+            .layer(middleware::from_fn(request_context))
+    }
+
+    async fn send(uri: &str, request_id: Option<&str>) -> (Response, String) {
+        let mut request = Request::builder().uri(uri);
+        if let Some(request_id) = request_id {
+            request = request.header(REQUEST_ID_HEADER, request_id);
+        }
+        let response = app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (
+            Response::from_parts(parts, Body::empty()),
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    fn echoed_id(response: &Response) -> &str {
+        response.headers()[REQUEST_ID_HEADER].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn reuses_well_formed_request_ids() {
+        let (response, body) = send("/request-id", Some("client-123:a.b_c")).await;
+        assert_eq!(echoed_id(&response), "client-123:a.b_c");
+        assert_eq!(body, "client-123:a.b_c");
+    }
+
+    #[tokio::test]
+    async fn replaces_missing_or_malformed_request_ids() {
+        let oversized = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        for request_id in [None, Some(""), Some("has spaces"), Some(oversized.as_str())] {
+            let (response, body) = send("/request-id", request_id).await;
+            let echoed = echoed_id(&response);
+            assert!(
+                Uuid::parse_str(echoed).is_ok(),
+                "{:?} became {}",
+                request_id,
+                echoed
+            );
+            assert_eq!(body, echoed);
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_bodiless_errors_a_json_body() {
+        let (response, body) = send("/unauthorized", Some("abc-123")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"], "Unauthorized");
+        assert_eq!(body["request_id"], "abc-123");
+    }
+
+    #[tokio::test]
+    async fn leaves_errors_with_a_body_alone() {
+        let (response, body) = send("/conflict", Some("abc-123")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(echoed_id(&response), "abc-123");
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"], "Taken");
+        assert_eq!(body["request_id"], "abc-123");
+    }
+
+    #[test]
+    fn extracts_the_callers_trace_context() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+
+        let context = extract_trace_context(&headers);
+        assert_eq!(
+            context.span().span_context().trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+        assert!(context.span().span_context().is_remote());
+
+        let context = extract_trace_context(&HeaderMap::new());
+        assert!(!context.span().span_context().is_valid());
+    }
+}
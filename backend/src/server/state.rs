@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use super::auth::RevocationList;
+use super::jobs::JobManager;
+use super::storage::{CardRepository, HistoryRepository};
+use crate::clock::Clock;
+use crate::lesson::LessonHistory;
+use crate::practice::PracticeStore;
+use crate::quiz::QuizStore;
+use crate::redaction::RedactionPolicy;
+use crate::roleplay::SessionStore;
+
+// Shared state handed to the request handlers.
+#[derive(Clone, Debug)]
+pub struct AppState {
+    pub cards: Arc<dyn CardRepository>,
+    pub clock: Arc<dyn Clock>,
+    pub history: Arc<dyn HistoryRepository>,
+    pub jobs: Arc<JobManager>,
+    pub lessons: Arc<LessonHistory>,
+    pub practice: Arc<PracticeStore>,
+    pub quizzes: Arc<QuizStore>,
+    pub redaction: Arc<RedactionPolicy>,
+    pub revocations: Arc<RevocationList>,
+    pub sessions: Arc<SessionStore>,
+}
@@ -0,0 +1,107 @@
+//! Where what users translate, and the flashcards they make of it, are kept.
+//!
+//! Handlers go through [`HistoryRepository`] and [`CardRepository`], so that the embedded SQLite
+//! database can make way for a server one.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fmt;
+use uuid::Uuid;
+
+use super::models::TranslationResponse;
+use crate::flashcards::{Card, DeckStats, Review};
+use crate::language::SourceLanguage;
+use crate::Language;
+
+mod sqlite;
+
+pub use sqlite::SqliteStorage;
+
+/// A translation a user asked for.
+#[derive(Clone, Debug, Serialize)]
+pub struct TranslationRecord {
+    pub id: Uuid,
+    /// The text the user sent.
+    pub request: String,
+    pub detected_language: SourceLanguage,
+    pub model: String,
+    pub response: TranslationResponse,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A translation without its response, for listings.
+#[derive(Clone, Debug, Serialize)]
+pub struct TranslationSummary {
+    pub id: Uuid,
+    pub request: String,
+    pub detected_language: SourceLanguage,
+    pub model: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// How many there are in all.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// Every user's translation history, newest first.
+#[async_trait]
+pub trait HistoryRepository: fmt::Debug + Send + Sync {
+    async fn insert(&self, sub: &str, record: &TranslationRecord) -> Result<()>;
+
+    async fn list(
+        &self,
+        sub: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Page<TranslationSummary>>;
+
+    async fn get(&self, sub: &str, id: Uuid) -> Result<Option<TranslationRecord>>;
+
+    /// Whether there was such a translation.
+    async fn delete(&self, sub: &str, id: Uuid) -> Result<bool>;
+}
+
+/// Every user's flashcards, along with their reviews.
+#[async_trait]
+pub trait CardRepository: fmt::Debug + Send + Sync {
+    /// Adds the cards the user doesn't have yet, i.e., with a new front and back, and returns
+    /// them.
+    async fn insert_cards(&self, sub: &str, cards: &[Card]) -> Result<Vec<Card>>;
+
+    async fn get_card(&self, sub: &str, id: Uuid) -> Result<Option<Card>>;
+
+    /// The cards due at `now`, the longest overdue first.
+    async fn due_cards(
+        &self,
+        sub: &str,
+        language: Option<Language>,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Card>>;
+
+    /// Saves the card as reviewed, along with the review, if its last review is still the one at
+    /// `previous_review`. Returns whether it was, i.e., whether no other review got there first.
+    async fn record_review(
+        &self,
+        sub: &str,
+        card: &Card,
+        review: &Review,
+        previous_review: Option<DateTime<Utc>>,
+    ) -> Result<bool>;
+
+    /// The stats of the deck at `now`, counting the reviews since `day_start` as today's.
+    async fn deck_stats(
+        &self,
+        sub: &str,
+        language: Option<Language>,
+        now: DateTime<Utc>,
+        day_start: DateTime<Utc>,
+    ) -> Result<DeckStats>;
+}
@@ -0,0 +1,716 @@
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::ValueEnum;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use super::{CardRepository, HistoryRepository, Page, TranslationRecord, TranslationSummary};
+use crate::flashcards::{Card, DeckStats, Review, Schedule, MATURE_INTERVAL_DAYS};
+use crate::Language;
+
+// The schema, one migration at a time. Each runs once, in order: the database's `user_version`
+// is how many already did. Never edit one that shipped, add another.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE translations (
+        id TEXT PRIMARY KEY,
+        sub TEXT NOT NULL,
+        request TEXT NOT NULL,
+        detected_language TEXT NOT NULL,
+        model TEXT NOT NULL,
+        response TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );
+    CREATE INDEX translations_by_sub ON translations (sub, created_at);
+",
+    "
+    CREATE TABLE cards (
+        id TEXT PRIMARY KEY,
+        sub TEXT NOT NULL,
+        language TEXT NOT NULL,
+        front TEXT NOT NULL,
+        back TEXT NOT NULL,
+        pronunciation TEXT NOT NULL,
+        notes TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        ease REAL NOT NULL,
+        interval_days INTEGER NOT NULL,
+        repetitions INTEGER NOT NULL,
+        due_at TEXT NOT NULL,
+        lapses INTEGER NOT NULL,
+        last_reviewed_at TEXT,
+        UNIQUE (sub, language, front, back)
+    );
+    CREATE INDEX cards_by_due_at ON cards (sub, due_at);
+    CREATE TABLE reviews (
+        card_id TEXT NOT NULL REFERENCES cards (id),
+        sub TEXT NOT NULL,
+        grade TEXT NOT NULL,
+        interval_days INTEGER NOT NULL,
+        reviewed_at TEXT NOT NULL
+    );
+    CREATE INDEX reviews_by_sub ON reviews (sub, reviewed_at);
+",
+    "
+    CREATE TABLE items (
+        seq INTEGER PRIMARY KEY,
+        kind TEXT NOT NULL,
+        sub TEXT NOT NULL,
+        id TEXT NOT NULL,
+        item TEXT NOT NULL,
+        UNIQUE (kind, sub, id)
+    );
+    CREATE INDEX items_by_sub ON items (kind, sub, seq);
+",
+];
+
+/// The translation history, the flashcards and the items of the
+/// [`PerUserStore`](crate::per_user_store::PerUserStore)s, in an SQLite database.
+///
+/// Queries run on Tokio's blocking threads, so that a slow disk doesn't hold up the runtime.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it if needed, and brings its schema up to date.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .with_context(|| format!("Error opening the database {}", path.display()))?;
+        Self::new(connection)
+    }
+
+    /// A database that is forgotten on restart.
+    pub fn in_memory() -> Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(mut connection: Connection) -> Result<Self> {
+        migrate(&mut connection)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    // Runs `f` on a blocking thread, with the connection to itself.
+    pub(crate) async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let connection = Arc::clone(&self.connection);
+        tokio::task::spawn_blocking(move || {
+            f(&mut connection.lock().expect("database lock poisoned"))
+        })
+        .await?
+    }
+}
+
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        bail!(
+            "The database is at version {}, newer than this server's {}",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction
+            .execute_batch(migration)
+            .with_context(|| format!("Error running migration {}", i + 1))?;
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+// Fixed width, so that timestamps sort as text.
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn summary(row: &Row) -> rusqlite::Result<TranslationSummary> {
+    Ok(TranslationSummary {
+        id: parse(row, 0, |s| Uuid::parse_str(s).map_err(|e| anyhow!(e)))?,
+        request: row.get(1)?,
+        detected_language: parse(row, 2, |s| s.parse().map_err(|e: String| anyhow!(e)))?,
+        model: row.get(3)?,
+        created_at: parse(row, 4, |s| {
+            Ok(DateTime::parse_from_rfc3339(s)?.with_timezone(&Utc))
+        })?,
+    })
+}
+
+// Reads a text column that needs parsing.
+fn parse<T>(row: &Row, index: usize, parse: impl Fn(&str) -> Result<T>) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    parse(&text).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+const SUMMARY_COLUMNS: &str = "id, request, detected_language, model, created_at";
+
+#[async_trait]
+impl HistoryRepository for SqliteStorage {
+    async fn insert(&self, sub: &str, record: &TranslationRecord) -> Result<()> {
+        let (sub, record) = (sub.to_string(), record.clone());
+        self.run(move |connection| {
+            connection.execute(
+                "INSERT INTO translations
+                    (id, sub, request, detected_language, model, response, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    record.id.to_string(),
+                    sub,
+                    record.request,
+                    record.detected_language.as_str(),
+                    record.model,
+                    serde_json::to_string(&record.response)?,
+                    timestamp(&record.created_at),
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(
+        &self,
+        sub: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Page<TranslationSummary>> {
+        let sub = sub.to_string();
+        self.run(move |connection| {
+            let total: usize = connection.query_row(
+                "SELECT COUNT(*) FROM translations WHERE sub = ?1",
+                [&sub],
+                |row| row.get(0),
+            )?;
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM translations WHERE sub = ?1
+                ORDER BY created_at DESC, rowid DESC LIMIT ?2 OFFSET ?3",
+                SUMMARY_COLUMNS
+            ))?;
+            let items = statement
+                .query_map(params![sub, limit, offset], summary)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(Page {
+                items,
+                total,
+                offset,
+                limit,
+            })
+        })
+        .await
+    }
+
+    async fn get(&self, sub: &str, id: Uuid) -> Result<Option<TranslationRecord>> {
+        let sub = sub.to_string();
+        self.run(move |connection| {
+            let row = connection
+                .query_row(
+                    &format!(
+                        "SELECT {}, response FROM translations WHERE sub = ?1 AND id = ?2",
+                        SUMMARY_COLUMNS
+                    ),
+                    params![sub, id.to_string()],
+                    |row| Ok((summary(row)?, row.get::<_, String>(5)?)),
+                )
+                .optional()?;
+            row.map(|(summary, response)| {
+                Ok(TranslationRecord {
+                    id: summary.id,
+                    request: summary.request,
+                    detected_language: summary.detected_language,
+                    model: summary.model,
+                    response: serde_json::from_str(&response)
+                        .context("Error parsing a stored translation")?,
+                    created_at: summary.created_at,
+                })
+            })
+            .transpose()
+        })
+        .await
+    }
+
+    async fn delete(&self, sub: &str, id: Uuid) -> Result<bool> {
+        let sub = sub.to_string();
+        self.run(move |connection| {
+            let deleted = connection.execute(
+                "DELETE FROM translations WHERE sub = ?1 AND id = ?2",
+                params![sub, id.to_string()],
+            )?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+}
+
+const CARD_COLUMNS: &str = "id, language, front, back, pronunciation, notes, created_at, ease, \
+    interval_days, repetitions, due_at, lapses, last_reviewed_at";
+
+fn parse_time(text: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(text)?.with_timezone(&Utc))
+}
+
+fn card(row: &Row) -> rusqlite::Result<Card> {
+    Ok(Card {
+        id: parse(row, 0, |s| Uuid::parse_str(s).map_err(|e| anyhow!(e)))?,
+        language: parse(row, 1, |s| {
+            Language::from_str(s, false).map_err(|e| anyhow!(e))
+        })?,
+        front: row.get(2)?,
+        back: row.get(3)?,
+        pronunciation: row.get(4)?,
+        notes: parse(row, 5, |s| Ok(serde_json::from_str(s)?))?,
+        created_at: parse(row, 6, parse_time)?,
+        schedule: Schedule {
+            ease: row.get(7)?,
+            interval_days: row.get(8)?,
+            repetitions: row.get(9)?,
+            due_at: parse(row, 10, parse_time)?,
+        },
+        lapses: row.get(11)?,
+        last_reviewed_at: row
+            .get::<_, Option<String>>(12)?
+            .map(|text| {
+                parse_time(&text).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        12,
+                        rusqlite::types::Type::Text,
+                        e.into(),
+                    )
+                })
+            })
+            .transpose()?,
+    })
+}
+
+#[async_trait]
+impl CardRepository for SqliteStorage {
+    async fn insert_cards(&self, sub: &str, cards: &[Card]) -> Result<Vec<Card>> {
+        let (sub, cards) = (sub.to_string(), cards.to_vec());
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            let mut inserted = Vec::new();
+            for card in &cards {
+                let changes = transaction.execute(
+                    &format!(
+                        "INSERT OR IGNORE INTO cards (sub, {})
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                        CARD_COLUMNS
+                    ),
+                    params![
+                        sub,
+                        card.id.to_string(),
+                        card.language.to_string(),
+                        card.front,
+                        card.back,
+                        card.pronunciation,
+                        serde_json::to_string(&card.notes)?,
+                        timestamp(&card.created_at),
+                        card.schedule.ease,
+                        card.schedule.interval_days,
+                        card.schedule.repetitions,
+                        timestamp(&card.schedule.due_at),
+                        card.lapses,
+                        card.last_reviewed_at.as_ref().map(timestamp),
+                    ],
+                )?;
+                if changes > 0 {
+                    inserted.push(card.clone());
+                }
+            }
+            transaction.commit()?;
+            Ok(inserted)
+        })
+        .await
+    }
+
+    async fn get_card(&self, sub: &str, id: Uuid) -> Result<Option<Card>> {
+        let sub = sub.to_string();
+        self.run(move |connection| {
+            Ok(connection
+                .query_row(
+                    &format!(
+                        "SELECT {} FROM cards WHERE sub = ?1 AND id = ?2",
+                        CARD_COLUMNS
+                    ),
+                    params![sub, id.to_string()],
+                    card,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn due_cards(
+        &self,
+        sub: &str,
+        language: Option<Language>,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Card>> {
+        let sub = sub.to_string();
+        self.run(move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM cards
+                WHERE sub = ?1 AND (?2 IS NULL OR language = ?2) AND due_at <= ?3
+                ORDER BY due_at, rowid LIMIT ?4",
+                CARD_COLUMNS
+            ))?;
+            let cards = statement
+                .query_map(
+                    params![
+                        sub,
+                        language.map(|language| language.to_string()),
+                        timestamp(&now),
+                        limit
+                    ],
+                    card,
+                )?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(cards)
+        })
+        .await
+    }
+
+    async fn record_review(
+        &self,
+        sub: &str,
+        card: &Card,
+        review: &Review,
+        previous_review: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let (sub, card, review) = (sub.to_string(), card.clone(), review.clone());
+        self.run(move |connection| {
+            let transaction = connection.transaction()?;
+            let changes = transaction.execute(
+                "UPDATE cards SET ease = ?3, interval_days = ?4, repetitions = ?5, due_at = ?6,
+                    lapses = ?7, last_reviewed_at = ?8
+                WHERE sub = ?1 AND id = ?2 AND last_reviewed_at IS ?9",
+                params![
+                    sub,
+                    card.id.to_string(),
+                    card.schedule.ease,
+                    card.schedule.interval_days,
+                    card.schedule.repetitions,
+                    timestamp(&card.schedule.due_at),
+                    card.lapses,
+                    card.last_reviewed_at.as_ref().map(timestamp),
+                    previous_review.as_ref().map(timestamp),
+                ],
+            )?;
+            if changes == 0 {
+                return Ok(false);
+            }
+            transaction.execute(
+                "INSERT INTO reviews (card_id, sub, grade, interval_days, reviewed_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    review.card_id.to_string(),
+                    sub,
+                    review.grade.as_str(),
+                    review.interval_days,
+                    timestamp(&review.reviewed_at),
+                ],
+            )?;
+            transaction.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn deck_stats(
+        &self,
+        sub: &str,
+        language: Option<Language>,
+        now: DateTime<Utc>,
+        day_start: DateTime<Utc>,
+    ) -> Result<DeckStats> {
+        let sub = sub.to_string();
+        self.run(move |connection| {
+            let language = language.map(|language| language.to_string());
+            let (total, new, due, learning, mature) = connection.query_row(
+                "SELECT COUNT(*),
+                    COALESCE(SUM(last_reviewed_at IS NULL), 0),
+                    COALESCE(SUM(due_at <= ?3), 0),
+                    COALESCE(SUM(last_reviewed_at IS NOT NULL AND interval_days < ?4), 0),
+                    COALESCE(SUM(last_reviewed_at IS NOT NULL AND interval_days >= ?4), 0)
+                FROM cards WHERE sub = ?1 AND (?2 IS NULL OR language = ?2)",
+                params![sub, language, timestamp(&now), MATURE_INTERVAL_DAYS],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )?;
+            let (reviewed_today, recent, remembered): (usize, usize, usize) = connection
+                .query_row(
+                    "SELECT COALESCE(SUM(reviews.reviewed_at >= ?3), 0),
+                    COUNT(*),
+                    COALESCE(SUM(reviews.grade != 'again'), 0)
+                FROM reviews JOIN cards ON cards.id = reviews.card_id
+                WHERE reviews.sub = ?1 AND (?2 IS NULL OR cards.language = ?2)
+                    AND reviews.reviewed_at >= ?4",
+                    params![
+                        sub,
+                        language,
+                        timestamp(&day_start),
+                        timestamp(&(now - Duration::days(30))),
+                    ],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )?;
+            Ok(DeckStats {
+                total,
+                new,
+                due,
+                learning,
+                mature,
+                reviewed_today,
+                retention: (recent > 0).then(|| remembered as f64 / recent as f64),
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flashcards::Grade;
+    use crate::language::SourceLanguage;
+    use crate::server::models::TranslationResponse;
+    use chrono::SubsecRound;
+
+    fn record(request: &str, created_at: DateTime<Utc>) -> TranslationRecord {
+        TranslationRecord {
+            id: Uuid::new_v4(),
+            request: request.to_string(),
+            detected_language: SourceLanguage::detect(request),
+            model: "model".to_string(),
+            response: TranslationResponse::builder().build(),
+            created_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_through_the_newest_first() {
+        let history = SqliteStorage::in_memory().unwrap();
+        let now = Utc::now();
+        for i in 0..5 {
+            history
+                .insert("alice", &record(&i.to_string(), now + Duration::seconds(i)))
+                .await
+                .unwrap();
+        }
+        history.insert("bob", &record("bob's", now)).await.unwrap();
+
+        let page = history.list("alice", 0, 2).await.unwrap();
+        assert_eq!(page.total, 5);
+        let requests: Vec<_> = page.items.iter().map(|s| s.request.as_str()).collect();
+        assert_eq!(requests, ["4", "3"]);
+        let page = history.list("alice", 4, 2).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].request, "0");
+        assert!(history.list("carol", 0, 10).await.unwrap().items.is_empty());
+    }
+
+    #[tokio::test]
+    async fn gets_and_deletes_only_the_users_own() {
+        let history = SqliteStorage::in_memory().unwrap();
+        // Stored to the microsecond.
+        let record = record("だから言ったでしょう", Utc::now().trunc_subsecs(6));
+        history.insert("alice", &record).await.unwrap();
+
+        assert!(history.get("bob", record.id).await.unwrap().is_none());
+        assert!(!history.delete("bob", record.id).await.unwrap());
+        let stored = history.get("alice", record.id).await.unwrap().unwrap();
+        assert_eq!(stored.detected_language, SourceLanguage::Japanese);
+        assert_eq!(stored.created_at, record.created_at);
+
+        assert!(history.delete("alice", record.id).await.unwrap());
+        assert!(history.get("alice", record.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn migrates_once_and_keeps_the_data() {
+        let path = std::env::temp_dir().join(format!("history-{}.db", Uuid::new_v4()));
+        let record = record("hello", Utc::now());
+        SqliteStorage::open(&path)
+            .unwrap()
+            .insert("alice", &record)
+            .await
+            .unwrap();
+
+        let reopened = SqliteStorage::open(&path).unwrap();
+        assert!(reopened.get("alice", record.id).await.unwrap().is_some());
+        let version: usize = reopened
+            .connection
+            .lock()
+            .unwrap()
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn card(language: Language, front: &str, now: DateTime<Utc>) -> Card {
+        Card::new(
+            language,
+            front,
+            format!("{} back", front),
+            "",
+            Vec::new(),
+            now,
+        )
+    }
+
+    #[tokio::test]
+    async fn skips_cards_the_user_already_has() {
+        let storage = SqliteStorage::in_memory().unwrap();
+        let now = Utc::now().trunc_subsecs(6);
+        let hello = card(Language::Japanese, "Hello", now);
+        assert_eq!(
+            storage
+                .insert_cards("alice", std::slice::from_ref(&hello))
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let cards = [
+            card(Language::Japanese, "Hello", now),
+            card(Language::Chinese, "Hello", now),
+        ];
+        let inserted = storage.insert_cards("alice", &cards).await.unwrap();
+        assert_eq!(inserted, [cards[1].clone()]);
+        let cards = [
+            card(Language::Japanese, "Hello", now),
+            card(Language::Chinese, "Hello", now),
+        ];
+        assert_eq!(storage.insert_cards("bob", &cards).await.unwrap().len(), 2);
+        assert_eq!(
+            storage.get_card("alice", hello.id).await.unwrap(),
+            Some(hello)
+        );
+    }
+
+    #[tokio::test]
+    async fn reviews_reschedule_cards_and_count_towards_the_stats() {
+        let storage = SqliteStorage::in_memory().unwrap();
+        let now = Utc::now().trunc_subsecs(6);
+        let cards = [
+            card(Language::Japanese, "Hello", now - Duration::hours(2)),
+            card(Language::Japanese, "Thanks", now - Duration::hours(1)),
+            card(Language::Chinese, "Hello", now),
+        ];
+        storage.insert_cards("alice", &cards).await.unwrap();
+
+        let due = storage
+            .due_cards("alice", Some(Language::Japanese), now, 10)
+            .await
+            .unwrap();
+        let fronts: Vec<_> = due.iter().map(|card| card.front.as_str()).collect();
+        assert_eq!(fronts, ["Hello", "Thanks"]);
+
+        let mut reviewed = due[0].clone();
+        let review = reviewed.review(Grade::Good, now);
+        assert!(storage
+            .record_review("alice", &reviewed, &review, None)
+            .await
+            .unwrap());
+        let mut forgotten = due[1].clone();
+        let review = forgotten.review(Grade::Again, now);
+        assert!(storage
+            .record_review("alice", &forgotten, &review, None)
+            .await
+            .unwrap());
+        assert_eq!(
+            storage.get_card("alice", reviewed.id).await.unwrap(),
+            Some(reviewed.clone())
+        );
+        assert!(!storage
+            .record_review("bob", &reviewed, &review, None)
+            .await
+            .unwrap());
+
+        let stats = storage
+            .deck_stats("alice", None, now, now - Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            DeckStats {
+                total: 3,
+                new: 1,
+                due: 1,
+                learning: 2,
+                mature: 0,
+                reviewed_today: 2,
+                retention: Some(0.5),
+            }
+        );
+        let stats = storage
+            .deck_stats("alice", Some(Language::Chinese), now, now)
+            .await
+            .unwrap();
+        assert_eq!(
+            (stats.total, stats.reviewed_today, stats.retention),
+            (1, 0, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn reviews_of_a_card_already_reviewed_are_dropped() {
+        let storage = SqliteStorage::in_memory().unwrap();
+        let now = Utc::now().trunc_subsecs(6);
+        let new = card(Language::Japanese, "Hello", now);
+        storage
+            .insert_cards("alice", std::slice::from_ref(&new))
+            .await
+            .unwrap();
+
+        // The same review, submitted twice.
+        let mut first = new.clone();
+        let review = first.review(Grade::Good, now);
+        assert!(storage
+            .record_review("alice", &first, &review, None)
+            .await
+            .unwrap());
+        let mut second = new.clone();
+        let review = second.review(Grade::Good, now);
+        assert!(!storage
+            .record_review("alice", &second, &review, None)
+            .await
+            .unwrap());
+
+        let stored = storage.get_card("alice", new.id).await.unwrap().unwrap();
+        assert_eq!(stored.schedule.repetitions, 1);
+        let stats = storage.deck_stats("alice", None, now, now).await.unwrap();
+        assert_eq!(stats.reviewed_today, 1);
+
+        // Reviews of the card as it now is go through.
+        let mut next = stored.clone();
+        let review = next.review(Grade::Good, now + Duration::days(1));
+        assert!(storage
+            .record_review("alice", &next, &review, stored.last_reviewed_at)
+            .await
+            .unwrap());
+        let stored = storage.get_card("alice", new.id).await.unwrap().unwrap();
+        assert_eq!(stored.schedule.interval_days, 6);
+    }
+}
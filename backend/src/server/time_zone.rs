@@ -0,0 +1,100 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono_tz::Tz;
+
+use super::auth::Claims;
+use super::handlers::ApiResponse;
+use crate::clock::parse_time_zone;
+
+/// Lets clients tell the time zone of the user, e.g., "Asia/Tokyo".
+pub const TIME_ZONE_HEADER: HeaderName = HeaderName::from_static("x-time-zone");
+
+/// The time zone of the caller: the `x-time-zone` header, the standard `zoneinfo` claim of
+/// their token, or UTC.
+///
+/// An unknown time zone in the header is a 400, one in the token is ignored.
+#[derive(Clone, Copy, Debug)]
+pub struct UserTimeZone(pub Tz);
+
+impl<S> FromRequestParts<S> for UserTimeZone
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(value) = parts.headers.get(TIME_ZONE_HEADER) {
+            let time_zone = value
+                .to_str()
+                .map_err(|_| "invalid time zone".to_string())
+                .and_then(parse_time_zone);
+            return time_zone.map(Self).map_err(|e| {
+                (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(e))).into_response()
+            });
+        }
+
+        let from_token = parts
+            .extensions
+            .get::<Claims>()
+            .and_then(|claims| claims.zoneinfo.as_deref())
+            .and_then(|name| parse_time_zone(name).ok());
+        Ok(Self(from_token.unwrap_or(Tz::UTC)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    async fn extract(header: Option<&str>, zoneinfo: Option<&str>) -> Result<Tz, StatusCode> {
+        let mut request = Request::builder();
+        if let Some(header) = header {
+            request = request.header(TIME_ZONE_HEADER, header);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        parts.extensions.insert(Claims {
+            sub: "user-1".to_string(),
+            iss: "https://issuer.example.com".to_string(),
+            client_id: None,
+            username: None,
+            scopes: Vec::new(),
+            groups: Vec::new(),
+            jti: None,
+            origin_jti: None,
+            exp: 0,
+            iat: None,
+            zoneinfo: zoneinfo.map(str::to_string),
+        });
+        UserTimeZone::from_request_parts(&mut parts, &())
+            .await
+            .map(|UserTimeZone(tz)| tz)
+            .map_err(|response| response.status())
+    }
+
+    #[tokio::test]
+    async fn prefers_the_header_over_the_token() {
+        assert_eq!(
+            extract(Some("Asia/Tokyo"), Some("Europe/Paris")).await,
+            Ok(Tz::Asia__Tokyo)
+        );
+        assert_eq!(
+            extract(None, Some("Europe/Paris")).await,
+            Ok(Tz::Europe__Paris)
+        );
+        assert_eq!(extract(None, None).await, Ok(Tz::UTC));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_time_zones_in_the_header_only() {
+        assert_eq!(
+            extract(Some("Nowhere/Special"), None).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(extract(None, Some("Nowhere/Special")).await, Ok(Tz::UTC));
+    }
+}
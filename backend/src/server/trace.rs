@@ -0,0 +1,260 @@
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::Request;
+use axum::{
+    extract::{ConnectInfo, MatchedPath},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tracing::{info_span, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use super::request_context::{extract_trace_context, REQUEST_ID_HEADER};
+use crate::metrics;
+use crate::redaction::RedactionPolicy;
+
+/// Creates the `http_request` span every request is handled in.
+/// Header values are recorded according to the redaction policy.
+pub fn make_span<B>(request: &Request<B>, redaction: &RedactionPolicy) -> Span {
+    let matched_path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str);
+
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|connect_info| connect_info.0.to_string());
+
+    let headers = redaction.headers(request.headers());
+
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let span = info_span!(
+        "http_request",
+        request_id,
+        method = ?request.method(),
+        path = %request.uri().path(),
+        matched_path,
+        client_ip = client_ip.as_deref(),
+        headers = ?headers,
+        response.status = tracing::field::Empty,
+        response.size = tracing::field::Empty,
+        response.content_type = tracing::field::Empty,
+        response.latency_ms = tracing::field::Empty,
+    );
+
+    // Join the trace started by the caller, if any.
+    span.set_parent(extract_trace_context(request.headers()));
+    span
+}
+
+/// Records the duration and outcome of every request in the `http.server.request.duration` metric.
+pub async fn record_metrics(request: axum::extract::Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+    metrics::record_request(
+        method.as_str(),
+        route.as_deref(),
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+    response
+}
+
+pub fn on_response(response: &Response, latency: Duration, span: &Span) {
+    let size = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown");
+
+    span.record(
+        "response.status",
+        tracing::field::display(response.status()),
+    );
+    span.record("response.size", size);
+    span.record("response.content_type", content_type);
+    span.record("response.latency_ms", latency.as_millis());
+
+    tracing::info!(
+        parent: span,
+        "finished processing request"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use opentelemetry::global;
+    use opentelemetry::trace::{TraceContextExt, TraceId, TracerProvider as _};
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+    use tracing_subscriber::fmt::{format::FmtSpan, MakeWriter};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const TOKEN: &str = "eyJhbGciOiJSUzI1NiJ9.super-secret-payload.signature";
+
+    // Collects everything the fmt layer writes so that we can inspect it.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Capture {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Capture {
+        type Writer = Capture;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    async fn send_request(redaction: RedactionPolicy, headers: &[(&str, &str)]) -> String {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(capture.clone())
+            .with_span_events(FmtSpan::FULL)
+            .with_max_level(tracing::Level::TRACE)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let redaction = Arc::new(redaction);
+        let app = Router::new()
+            .route(
+                "/translate",
+                get(|| async {
+                    tracing::info!("handling request");
+                    "ok"
+                }),
+            )
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(move |request: &Request<_>| make_span(request, &redaction))
+                    .on_response(on_response),
+            );
+
+        let mut request = Request::builder().uri("/translate");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        capture.contents()
+    }
+
+    #[tokio::test]
+    async fn bearer_tokens_never_appear_in_spans() {
+        let output = send_request(
+            RedactionPolicy::default(),
+            &[("authorization", &format!("Bearer {}", TOKEN))],
+        )
+        .await;
+
+        assert!(output.contains("http_request"));
+        assert!(output.contains("finished processing request"));
+        assert!(!output.contains(TOKEN));
+        assert!(!output.contains("super-secret-payload"));
+    }
+
+    #[tokio::test]
+    async fn tokens_are_redacted_even_when_user_content_is_logged() {
+        let permissive = RedactionPolicy::new(Vec::<String>::new(), Vec::<String>::new(), true);
+        let output = send_request(
+            permissive,
+            &[
+                ("authorization", &format!("Bearer {}", TOKEN)),
+                ("proxy-authorization", TOKEN),
+            ],
+        )
+        .await;
+
+        assert!(!output.contains(TOKEN));
+    }
+
+    #[tokio::test]
+    async fn configured_headers_are_redacted_in_spans() {
+        let output = send_request(
+            RedactionPolicy::new(["x-api-key"], Vec::<String>::new(), false),
+            &[("x-api-key", TOKEN), ("x-request-id", "abc-123")],
+        )
+        .await;
+
+        assert!(!output.contains(TOKEN));
+        assert!(output.contains("abc-123"));
+    }
+
+    #[test]
+    fn spans_join_the_callers_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = TracerProvider::builder().build().tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        let trace_id = |traceparent: Option<&str>| {
+            let mut request = Request::builder().uri("/translate");
+            if let Some(traceparent) = traceparent {
+                request = request.header("traceparent", traceparent);
+            }
+            let request = request.body(Body::empty()).unwrap();
+            let span = make_span(&request, &RedactionPolicy::default());
+            span.context().span().span_context().trace_id()
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert_eq!(
+                trace_id(Some(
+                    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                )),
+                TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+            );
+            // Without a trace context, requests start a trace of their own.
+            assert_ne!(trace_id(None), TraceId::INVALID);
+            assert_ne!(
+                trace_id(None),
+                TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+            );
+        });
+    }
+}
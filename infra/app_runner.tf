@@ -15,10 +15,13 @@ resource "aws_apprunner_service" "kamekai" {
 
         runtime_environment_variables = {
           "APP_ENABLE_ANSI_LOGS"        = "false"
+          "APP_LOG_FORMAT"              = "json"
           "APP_USER_POOL"               = aws_cognito_user_pool.kamekai.endpoint
           "APP_CLIENT_ID"               = aws_cognito_user_pool_client.desktop_client.id
+          "APP_TELEMETRY_EXPORTER"      = "otlp"
           "HONEYCOMB_API_KEY"           = var.honeycomb_api_key
           "OTEL_EXPORTER_OTLP_ENDPOINT" = "https://api.honeycomb.io"
+          "OTEL_EXPORTER_OTLP_PROTOCOL" = "http/protobuf"
         }
       }
     }
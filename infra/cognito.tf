@@ -105,6 +105,23 @@ resource "aws_cognito_user_pool" "kamekai" {
   }
 }
 
+# Scopes the backend API requires, e.g., kamekai/translate.
+resource "aws_cognito_resource_server" "kamekai" {
+  identifier   = "kamekai"
+  name         = "kamekai-api"
+  user_pool_id = aws_cognito_user_pool.kamekai.id
+
+  scope {
+    scope_name        = "translate"
+    scope_description = "Translate text"
+  }
+
+  scope {
+    scope_name        = "learn"
+    scope_description = "Generate lessons and practice"
+  }
+}
+
 # Cognito App Client for Desktop App.
 resource "aws_cognito_user_pool_client" "desktop_client" {
   name         = "kamekai-desktop"
@@ -115,7 +132,7 @@ resource "aws_cognito_user_pool_client" "desktop_client" {
 
   allowed_oauth_flows_user_pool_client = true
   allowed_oauth_flows                  = ["code"]
-  allowed_oauth_scopes                 = ["openid", "email", "profile"]
+  allowed_oauth_scopes                 = concat(["openid", "email", "profile"], aws_cognito_resource_server.kamekai.scope_identifiers)
 
   callback_urls = [
     "tauri://localhost",
@@ -6,6 +6,9 @@ export const cognitoConfig = {
   redirect_uri: 'tauri://localhost', //'tauri://com.kamekai.app/auth/callback',
   logout_uri: 'tauri://localhost', //'tauri://com.kamekai.app/auth/logout',
   domain: 'auth.seafoodfry.ninja',
+  // The backend requires the kamekai/translate scope to translate anything,
+  // and kamekai/learn for lessons.
+  scope: 'openid email profile kamekai/translate kamekai/learn',
   // See
   // https://github.com/authts/react-oidc-context/blob/f175dcba6ab09871b027d6a2f2224a17712b67c5/src/AuthProvider.tsx#L20-L30
   // We must provide an implementation of onSigninCallback to oidcConfig to remove the payload from